@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+use anyhow::{Result, bail};
+use clap::{Parser, Subcommand};
+use runtime::hal::{parse_servo_ids, Servo, ServoBackup, ServoBus};
+
+/// Saves the EEPROM settings of every servo to a file, or writes them back.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Servos to include, e.g. `1-12,14`; defaults to every configured servo
+    #[arg(short, long, global = true)]
+    ids: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Read every servo and save the backup; `.json` files are written as JSON, others as TOML
+    Save { file: PathBuf },
+    /// Rewrite the registers that differ from the backup
+    Restore {
+        file: PathBuf,
+
+        /// Only show what would be written
+        #[arg(short = 'n', long, default_value_t = false)]
+        dry_run: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let servo = Servo::new()?;
+    let ids = match &args.ids {
+        Some(spec) => parse_servo_ids(spec)?,
+        None => servo.ids(),
+    };
+
+    match args.command {
+        Command::Save { file } => {
+            let backup = servo.backup(&ids)?;
+            if backup.servos.is_empty() {
+                bail!("No servos answered");
+            }
+            for snapshot in &backup.servos {
+                println!("Servo {:3}: {}, {} registers", snapshot.id, snapshot.model, snapshot.registers.len());
+            }
+            backup.save(&file)?;
+            println!("Saved {} servos to {}", backup.servos.len(), file.display());
+        }
+        Command::Restore { file, dry_run } => {
+            let mut backup = ServoBackup::load(&file)?;
+            println!("Backup taken {}", backup.created);
+            if args.ids.is_some() {
+                backup.servos.retain(|snapshot| ids.contains(&snapshot.id));
+            }
+
+            if dry_run {
+                for snapshot in &backup.servos {
+                    let changes = servo.diff_snapshot(snapshot)?;
+                    if changes.is_empty() {
+                        println!("Servo {:3}: matches the backup", snapshot.id);
+                    }
+                    for change in changes {
+                        println!("Would write {}", change);
+                    }
+                }
+                return Ok(());
+            }
+
+            let changes = servo.restore(&backup)?;
+            for change in &changes {
+                println!("Wrote {}", change);
+            }
+            println!("Restored {} servos, {} registers changed", backup.servos.len(), changes.len());
+        }
+    }
+
+    Ok(())
+}
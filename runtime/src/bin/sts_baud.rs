@@ -0,0 +1,28 @@
+use anyhow::{Result, bail};
+use runtime::hal::{Servo, ServoBus, BAUD_RATES};
+use std::env;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 2 {
+        bail!("Usage: {} [new_baud_rate]", args[0]);
+    }
+
+    let servo = Servo::new()?;
+    let Some(current) = servo.baud_rate() else {
+        bail!("This servo backend does not control the bus baud rate");
+    };
+    println!("Bus is running at {} baud", current);
+
+    let Some(new_rate) = args.get(1) else {
+        println!("Supported rates: {:?}", BAUD_RATES);
+        return Ok(());
+    };
+    let new_rate: u32 = new_rate.parse().map_err(|_| anyhow::anyhow!("Invalid baud rate"))?;
+
+    println!("Moving all servos to {} baud", new_rate);
+    servo.migrate_baud_rate(new_rate)?;
+    println!("Done. Set SERVO_BAUD_RATE={} or leave it unset to detect the rate.", new_rate);
+
+    Ok(())
+}
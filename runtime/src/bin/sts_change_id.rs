@@ -2,7 +2,7 @@ use anyhow::{Result, bail};
 use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
-use runtime::hal::{Servo, ServoRegister};
+use runtime::hal::{Servo, ServoBus, ServoRegister};
 use std::env;
 
 fn main() -> Result<()> {
@@ -21,12 +21,12 @@ fn main() -> Result<()> {
     let servo = Arc::new(Servo::new()?);
     println!("Changing servo ID from {} to {}", current_id, new_id);
 
-    change_servo_id(&servo, current_id, new_id)?;
+    change_servo_id(servo.as_ref(), current_id, new_id)?;
 
     println!("ID change complete. Verifying...");
     sleep(Duration::from_millis(500)); // Wait for the change to take effect
 
-    if verify_servo_id(&servo, new_id)? {
+    if verify_servo_id(servo.as_ref(), new_id)? {
         println!("Verification successful. Servo ID has been changed to {}.", new_id);
     } else {
         println!("Verification failed. Please check the servo and try again.");
@@ -35,7 +35,7 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn change_servo_id(servo: &Arc<Servo>, current_id: u8, new_id: u8) -> Result<()> {
+fn change_servo_id<S: ServoBus>(servo: &S, current_id: u8, new_id: u8) -> Result<()> {
     // Disable readout
     servo.disable_readout()?;
 
@@ -57,7 +57,7 @@ fn change_servo_id(servo: &Arc<Servo>, current_id: u8, new_id: u8) -> Result<()>
     Ok(())
 }
 
-fn verify_servo_id(servo: &Arc<Servo>, id: u8) -> Result<bool> {
+fn verify_servo_id<S: ServoBus>(servo: &S, id: u8) -> Result<bool> {
     match servo.read(id, ServoRegister::ID, 1) {
         Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
         _ => Ok(false),
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use runtime::hal::{Servo, ServoConfig};
+
+/// Brings the EEPROM settings of the servos in line with a config file.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the registers that differ from the file
+    Plan { file: PathBuf },
+    /// Write the registers that differ from the file
+    Apply { file: PathBuf },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let (file, apply) = match args.command {
+        Command::Plan { file } => (file, false),
+        Command::Apply { file } => (file, true),
+    };
+
+    let config = ServoConfig::load(&file)?;
+    let servo = Servo::new()?;
+
+    let changes = servo.plan(&config)?;
+    if changes.is_empty() {
+        println!("All servos match {}", file.display());
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    if !apply {
+        println!("{} registers differ, run apply to write them", changes.len());
+        return Ok(());
+    }
+
+    servo.apply(&changes)?;
+
+    // Read everything back to confirm the writes took
+    let remaining = servo.plan(&config)?;
+    for change in &remaining {
+        println!("Still differs: {}", change);
+    }
+    if !remaining.is_empty() {
+        anyhow::bail!("{} of {} registers did not take", remaining.len(), changes.len());
+    }
+    println!("Wrote {} registers", changes.len());
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+use anyhow::Result;
+use clap::Parser;
+use runtime::hal::{hex, parse_servo_ids, read_capture, Decoder};
+
+/// Prints a bus capture (taken with `SERVO_CAPTURE=<file>`) as Feetech instructions and replies.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    file: PathBuf,
+
+    /// Only show packets to or from these servos, e.g. `1-12,14`; broadcasts are always shown
+    #[arg(short, long)]
+    ids: Option<String>,
+
+    /// Only show packets on this port
+    #[arg(short, long)]
+    port: Option<String>,
+
+    /// Also print the bytes of every packet
+    #[arg(short, long, default_value_t = false)]
+    raw: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let ids = args.ids.as_deref().map(parse_servo_ids).transpose()?;
+    let packets = read_capture(&args.file)?;
+    let ports = packets.iter().map(|packet| &packet.port).collect::<std::collections::BTreeSet<_>>();
+
+    // Every packet goes through the decoder so replies still match their requests
+    let mut decoder = Decoder::default();
+    for packet in &packets {
+        let line = decoder.decode(packet);
+        let id = packet.bytes.get(2).copied().unwrap_or(0xFE);
+        if args.port.as_ref().is_some_and(|port| *port != packet.port)
+            || ids.as_ref().is_some_and(|ids| id != 0xFE && !ids.contains(&id))
+        {
+            continue;
+        }
+
+        let port = if ports.len() > 1 { format!("{} ", packet.port) } else { String::new() };
+        println!("{:>11.6} {}{} {}", packet.time.as_secs_f64(), port, packet.direction.symbol(), line);
+        if args.raw {
+            println!("{:>13}{}", "", hex(&packet.bytes));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use anyhow::{Result, Context};
+use clap::Parser;
+use nix::pty::openpty;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::ttyname;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use runtime::hal::{hex, parse_servo_ids, ServoRegister};
+use runtime::hal_sim::SimBus;
+
+const START_BYTE: u8 = 0xFF;
+const BROADCAST_ID: u8 = 0xFE;
+
+const CMD_PING: u8 = 0x01;
+const CMD_READ: u8 = 0x02;
+const CMD_WRITE: u8 = 0x03;
+const CMD_REG_WRITE: u8 = 0x04;
+const CMD_ACTION: u8 = 0x05;
+const CMD_RESET: u8 = 0x06;
+const CMD_SYNC_READ: u8 = 0x82;
+const CMD_SYNC_WRITE: u8 = 0x83;
+
+/// Emulates a bus of STS3215 servos on a pseudo-terminal.
+///
+/// Point SERVO_PORT at the printed device (or --link) to drive the emulated servos
+/// through the serial backend.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Servo IDs to emulate, e.g. "16" for 1 to 16 or "1-4,7"
+    #[arg(short, long, default_value = "16")]
+    ids: String,
+
+    /// Create a symlink to the pty slave at this path
+    #[arg(short, long)]
+    link: Option<PathBuf>,
+
+    /// Probability of not replying at all
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    timeout_rate: f64,
+
+    /// Probability of dropping one byte from a reply
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    drop_rate: f64,
+
+    /// Probability of sending a reply with a bad checksum
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    checksum_rate: f64,
+
+    /// Probability of setting --error-bits in a reply's status byte
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    error_rate: f64,
+
+    /// Status bits reported by injected errors (default: overload)
+    #[arg(long, default_value_t = 0x20)]
+    error_bits: u8,
+
+    /// Only inject faults into replies from these IDs
+    #[arg(long)]
+    fault_ids: Option<String>,
+
+    /// Seed for fault injection
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Print every packet
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+}
+
+struct Emulator {
+    bus: SimBus,
+    args: Args,
+    fault_ids: Option<Vec<u8>>,
+    rng: StdRng,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let pty = openpty(None, None).context("Failed to open pty")?;
+    let mut termios = tcgetattr(&pty.slave)?;
+    cfmakeraw(&mut termios);
+    tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+
+    let slave_path = ttyname(&pty.slave)?;
+    if let Some(link) = &args.link {
+        let _ = std::fs::remove_file(link);
+        std::os::unix::fs::symlink(&slave_path, link)
+            .with_context(|| format!("Failed to link {}", link.display()))?;
+    }
+
+    let bus = SimBus::new(parse_servo_ids(&args.ids)?);
+    println!("Emulating servos {:?}", bus.ids());
+    println!("SERVO_PORT={}", args.link.as_ref().unwrap_or(&slave_path).display());
+
+    let fault_ids = args.fault_ids.as_deref().map(parse_servo_ids).transpose()?;
+    let rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // Keep our slave fd open so the master doesn't see EOF between clients
+    let _slave = pty.slave;
+    let mut port = File::from(pty.master);
+    let mut emulator = Emulator { bus, args, fault_ids, rng };
+
+    let mut input = Vec::new();
+    let mut buffer = [0u8; 256];
+    loop {
+        let n = port.read(&mut buffer).context("Failed to read from pty")?;
+        input.extend_from_slice(&buffer[..n]);
+
+        while let Some(packet) = next_packet(&mut input) {
+            if emulator.args.verbose {
+                println!("<- {}", hex(&packet));
+            }
+            if let Some(reply) = emulator.handle(&packet) {
+                if emulator.args.verbose {
+                    println!("-> {}", hex(&reply));
+                }
+                port.write_all(&reply)?;
+            }
+        }
+    }
+}
+
+/// Pops the next complete packet off the input, discarding noise and packets with a bad
+/// checksum the way the servo firmware does.
+fn next_packet(input: &mut Vec<u8>) -> Option<Vec<u8>> {
+    loop {
+        let start = input.windows(2).position(|w| w == [START_BYTE, START_BYTE]);
+        match start {
+            Some(start) => { input.drain(..start); }
+            None => {
+                // Keep a trailing start byte, it may be the first half of a header
+                let keep = input.last() == Some(&START_BYTE);
+                input.drain(..input.len() - keep as usize);
+                return None;
+            }
+        }
+
+        if input.len() < 4 {
+            return None;
+        }
+        // Extra 0xFF padding before the ID is legal
+        if input[2] == START_BYTE {
+            input.remove(0);
+            continue;
+        }
+        let total = input[3] as usize + 4;
+        if input[3] < 2 {
+            input.drain(..2);
+            continue;
+        }
+        if input.len() < total {
+            return None;
+        }
+
+        let packet: Vec<u8> = input.drain(..total).collect();
+        if checksum(&packet) == packet[total - 1] {
+            return Some(packet);
+        }
+    }
+}
+
+impl Emulator {
+    fn handle(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let id = packet[2];
+        let instruction = packet[4];
+        let params = &packet[5..packet.len() - 1];
+
+        self.bus.advance();
+
+        match instruction {
+            CMD_PING => self.reply(id, true, Vec::new()),
+            CMD_READ if params.len() == 2 => match self.bus.read(id, params[0], params[1]) {
+                Some(data) => self.reply(id, true, data),
+                None => self.reply(id, true, Vec::new()),
+            },
+            CMD_WRITE if !params.is_empty() => {
+                // Reply under the old ID when the ID itself is rewritten
+                let reply = self.reply(id, false, Vec::new());
+                self.bus.write(id, params[0], &params[1..]);
+                reply
+            }
+            CMD_REG_WRITE if !params.is_empty() => {
+                self.bus.reg_write(id, params[0], &params[1..]);
+                self.reply(id, false, Vec::new())
+            }
+            CMD_ACTION => {
+                self.bus.action();
+                self.reply(id, false, Vec::new())
+            }
+            CMD_RESET => {
+                let reply = self.reply(id, false, Vec::new());
+                self.bus.reset(id);
+                reply
+            }
+            CMD_SYNC_READ if params.len() >= 2 => {
+                // Each listed servo answers in turn, as on a real bus
+                let (address, length) = (params[0], params[1]);
+                let mut replies = Vec::new();
+                for &servo_id in &params[2..] {
+                    let data = self.bus.read(servo_id, address, length).unwrap_or_default();
+                    if let Some(reply) = self.reply(servo_id, true, data) {
+                        replies.extend(reply);
+                    }
+                }
+                (!replies.is_empty()).then_some(replies)
+            }
+            CMD_SYNC_WRITE if params.len() >= 2 => {
+                let address = params[0];
+                let length = params[1] as usize;
+                for entry in params[2..].chunks_exact(length + 1) {
+                    self.bus.write(entry[0], address, &entry[1..]);
+                }
+                None
+            }
+            _ => {
+                if self.args.verbose {
+                    println!("   unsupported instruction 0x{:02X}", instruction);
+                }
+                None
+            }
+        }
+    }
+
+    // Builds the status packet for `id`, if the servo exists and is configured to answer
+    fn reply(&mut self, id: u8, always: bool, params: Vec<u8>) -> Option<Vec<u8>> {
+        if id == BROADCAST_ID {
+            return None;
+        }
+        let servo = self.bus.servo(id)?;
+        let level = servo.read(ServoRegister::ResponseStatusLevel as u8, 1).map_or(1, |d| d[0]);
+        if !always && level == 0 {
+            return None;
+        }
+        let mut status = servo.read(ServoRegister::ServoStatus as u8, 1).map_or(0, |d| d[0]);
+
+        let faulty = self.fault_ids.as_ref().is_none_or(|ids| ids.contains(&id));
+        if faulty && self.rng.gen_bool(self.args.timeout_rate) {
+            return None;
+        }
+        if faulty && self.rng.gen_bool(self.args.error_rate) {
+            status |= self.args.error_bits;
+        }
+
+        let mut packet = vec![START_BYTE, START_BYTE, id, params.len() as u8 + 2, status];
+        packet.extend_from_slice(&params);
+        packet.push(0);
+        let last = packet.len() - 1;
+        packet[last] = checksum(&packet);
+
+        if faulty && self.rng.gen_bool(self.args.checksum_rate) {
+            packet[last] = !packet[last];
+        }
+        if faulty && self.rng.gen_bool(self.args.drop_rate) {
+            let index = self.rng.gen_range(0..packet.len());
+            packet.remove(index);
+        }
+
+        Some(packet)
+    }
+}
+
+// Fault rates are probabilities, which `gen_bool` insists on
+fn parse_rate(arg: &str) -> Result<f64, String> {
+    let rate: f64 = arg.parse().map_err(|_| format!("'{}' is not a number", arg))?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(format!("{} is not between 0 and 1", rate));
+    }
+    Ok(rate)
+}
+
+fn checksum(packet: &[u8]) -> u8 {
+    let sum: u16 = packet[2..packet.len() - 1].iter().map(|&x| x as u16).sum();
+    !((sum & 0xFF) as u8)
+}
@@ -1,10 +1,10 @@
 use std::error::Error;
 use std::thread;
 use std::time::{Duration, Instant};
-use runtime::hal::IMU;
+use runtime::hal::{IMU, ImuSource};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut imu = IMU::new()?;
+    let imu = IMU::new()?;
     let target_duration = Duration::from_millis(20);  // 50Hz = 20ms period
 
     println!("Starting IMU readings at 50Hz. Press Ctrl+C to stop.");
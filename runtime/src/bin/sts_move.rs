@@ -1,5 +1,5 @@
 use anyhow::{bail, Result};
-use runtime::hal::Servo;
+use runtime::hal::{Servo, ServoBus};
 use std::env;
 use std::thread;
 use std::time::Duration;
@@ -9,12 +9,14 @@ const MOVE_SPEED: u16 = 0; // 0 means maximum speed
 
 fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        bail!("Usage: {} <servo_id> <target_position>", args[0]);
+    if args.len() != 3 && args.len() != 4 {
+        bail!("Usage: {} <servo_id> <target_position> [acceleration]", args[0]);
     }
 
     let servo_id: u8 = args[1].parse()?;
     let target_position: i16 = args[2].parse()?;
+    // In 100 steps/s^2, 0 for as fast as the servo can
+    let acceleration: Option<u8> = args.get(3).map(|arg| arg.parse()).transpose()?;
 
     println!("Initializing servo...");
     let servo = Servo::new()?;
@@ -23,14 +25,18 @@ fn main() -> Result<()> {
         "Moving servo {} to position {}...",
         servo_id, target_position
     );
-    servo.move_servo(servo_id, target_position, MOVE_TIME, MOVE_SPEED)?;
+    match acceleration {
+        Some(acceleration) => servo.move_servo_with_acceleration(servo_id, target_position, MOVE_TIME, MOVE_SPEED, acceleration)?,
+        None => servo.move_servo(servo_id, target_position, MOVE_TIME, MOVE_SPEED)?,
+    }
 
     // Wait for the move to complete
     thread::sleep(Duration::from_millis(MOVE_TIME as u64));
 
     // Read and print the final position
     let info = servo.read_info(servo_id)?;
-    println!("Move complete. Final position: {}", info.current_location);
+    let position = servo.units(servo_id).position(info.current_location);
+    println!("Move complete. Final position: {} ({:.1}°)", info.current_location, position.degrees());
 
     Ok(())
 }
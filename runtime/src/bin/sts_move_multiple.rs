@@ -1,58 +1,40 @@
 use anyhow::Result;
-use runtime::hal::{Servo, ServoMultipleWriteCommand, MAX_SERVOS};
+use runtime::hal::{Servo, ServoBus};
 use std::env;
+use std::time::Duration;
 
 fn main() -> Result<()> {
     // Parse command-line arguments
     let args: Vec<String> = env::args().collect();
-    if args.len() != 5 {
-        println!("Usage: {} <position> <time> <speed> <send_only_positions>", args[0]);
+    if args.len() != 3 {
+        println!("Usage: {} <position> <time>", args[0]);
         println!("  position: target position (0-4095)");
-        println!("  time: movement time in milliseconds");
-        println!("  speed: movement speed (0-4095)");
-        println!("  send_only_positions: send only positions (0 or 1)");
+        println!("  time: movement time in milliseconds, longer if a servo can't make it");
         std::process::exit(1);
     }
 
     let position: i16 = args[1].parse()?;
-    let time: u16 = args[2].parse()?;
-    let speed: u16 = args[3].parse()?;
-    let send_only_positions: u8 = args[4].parse()?;
+    let time: u64 = args[2].parse()?;
     // Initialize the servo
     let servo = Servo::new()?;
 
     // Enable servo readout
     servo.enable_readout()?;
 
-    // Prepare the command for all servos
-    let mut cmd = ServoMultipleWriteCommand {
-        ids: [0; MAX_SERVOS],
-        positions: [0; MAX_SERVOS],
-        times: [0; MAX_SERVOS],
-        speeds: [0; MAX_SERVOS],
-        only_write_positions: send_only_positions,
-    };
-
-    // Fill the command structure for all servos
-    for i in 0..MAX_SERVOS {
-        cmd.ids[i] = (i + 1) as u8;
-        cmd.positions[i] = position;
-        cmd.times[i] = time;
-        cmd.speeds[i] = speed;
-    }
-
-    // Send the command to move all servos
-    servo.write_multiple(&cmd)?;
+    // Move all servos so they arrive together
+    let goals: Vec<(u8, i16)> = servo.ids().into_iter().map(|id| (id, position)).collect();
+    let duration = servo.move_synchronized(&goals, Duration::from_millis(time))?;
 
-    println!("Command sent to move all servos to position {} with time {} ms and speed {}, send_only_positions: {}", position, time, speed, send_only_positions);
+    println!("Command sent to move all servos to position {} in {} ms", position, duration.as_millis());
 
     // Wait for the movement to complete
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    std::thread::sleep(duration + Duration::from_millis(200));
 
     // Read and print the current positions of all servos
     let servo_data = servo.read_continuous()?;
-    for (i, servo_info) in servo_data.servo.iter().enumerate() {
-        println!("Servo {}: Current position = {}", i + 1, servo_info.current_location);
+    for (id, servo_info) in &servo_data.servos {
+        let position = servo.units(*id).position(servo_info.current_location);
+        println!("Servo {}: Current position = {} ({:.1}°)", id, servo_info.current_location, position.degrees());
     }
 
     Ok(())
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use runtime::hal::{Servo, MAX_SERVOS, TorqueMode, ServoRegister};
+use runtime::hal::{BusStats, Servo, ServoBus, TorqueMode, ServoRegister};
 use cursive::views::{TextView, LinearLayout, DummyView, Panel, Dialog, EditView, SelectView};
 use cursive::traits::*;
 use std::sync::{Arc, Mutex};
@@ -44,6 +44,7 @@ fn show_hints(s: &mut cursive::Cursive) {
         "] - End calibration",
         "c - Capture current position",
         "C - End and save capture",
+        "B - Bus statistics of every servo",
         "Q - Quit",
         "H - Show this help",
     ];
@@ -59,6 +60,11 @@ fn show_hints(s: &mut cursive::Cursive) {
 
 fn main() -> Result<()> {
     let servo = Arc::new(Servo::new()?);
+    // One row per servo, in ID order
+    let ids = Arc::new(servo.ids());
+    if ids.is_empty() {
+        anyhow::bail!("No servos configured");
+    }
 
     // Enable continuous readout
     servo.enable_readout()?;
@@ -84,24 +90,24 @@ fn main() -> Result<()> {
     layout.add_child(header);
 
     // Add rows for each servo
-    for i in 0..MAX_SERVOS {
-        let joint_name = JOINT_NAMES.get(i).unwrap_or(&"Unknown");
+    for &id in ids.iter() {
+        let joint_name = JOINT_NAMES.get(id as usize - 1).unwrap_or(&"Unknown");
         let row = LinearLayout::horizontal()
-            .child(TextView::new(format!("{:2}", i + 1)).center().with_name(format!("ID {}", i)).fixed_width(4))
-            .child(TextView::new(*joint_name).center().with_name(format!("Joint {}", i)).fixed_width(7))
-            .child(TextView::new("----").center().with_name(format!("CurrPos {}", i)).fixed_width(8))
-            .child(TextView::new("----").center().with_name(format!("CurrSpd {}", i)).fixed_width(8))
-            .child(TextView::new("----").center().with_name(format!("Load {}", i)).fixed_width(8))
-            .child(TextView::new("----").center().with_name(format!("Torque {}", i)).fixed_width(8))
-            .child(TextView::new("----").center().with_name(format!("Volt {}", i)).fixed_width(6))
-            .child(TextView::new("----").center().with_name(format!("Temp {}", i)).fixed_width(6))
-            .child(TextView::new("----").center().with_name(format!("Curr {}", i)).fixed_width(6))
-            .child(TextView::new("----").center().with_name(format!("Status {}", i)).fixed_width(8))
-        .child(TextView::new("----").center().with_name(format!("TorqLim {}", i)).fixed_width(8));
-
-            // .child(TextView::new("----").center().with_name(format!("Async {}", i)).fixed_width(6))
-            // .child(TextView::new("----").center().with_name(format!("Lock {}", i)).fixed_width(6));
-        layout.add_child(row.with_name(format!("servo_row_{}", i)));
+            .child(TextView::new(format!("{:2}", id)).center().with_name(format!("ID {}", id)).fixed_width(4))
+            .child(TextView::new(*joint_name).center().with_name(format!("Joint {}", id)).fixed_width(7))
+            .child(TextView::new("----").center().with_name(format!("CurrPos {}", id)).fixed_width(8))
+            .child(TextView::new("----").center().with_name(format!("CurrSpd {}", id)).fixed_width(8))
+            .child(TextView::new("----").center().with_name(format!("Load {}", id)).fixed_width(8))
+            .child(TextView::new("----").center().with_name(format!("Torque {}", id)).fixed_width(8))
+            .child(TextView::new("----").center().with_name(format!("Volt {}", id)).fixed_width(6))
+            .child(TextView::new("----").center().with_name(format!("Temp {}", id)).fixed_width(6))
+            .child(TextView::new("----").center().with_name(format!("Curr {}", id)).fixed_width(6))
+            .child(TextView::new("----").center().with_name(format!("Status {}", id)).fixed_width(8))
+        .child(TextView::new("----").center().with_name(format!("TorqLim {}", id)).fixed_width(8));
+
+            // .child(TextView::new("----").center().with_name(format!("Async {}", id)).fixed_width(6))
+            // .child(TextView::new("----").center().with_name(format!("Lock {}", id)).fixed_width(6));
+        layout.add_child(row.with_name(format!("servo_row_{}", id)));
     }
 
     // Add a dummy view to push the task count to the bottom
@@ -135,6 +141,27 @@ fn main() -> Result<()> {
         .full_width()
     );
 
+    // Bus traffic of the selected servo
+    layout.add_child(
+        Panel::new(
+            LinearLayout::horizontal()
+                .child(DummyView)
+                .child(
+                    LinearLayout::vertical()
+                        .child(TextView::new("Transactions: ----").with_name("BusTransactions"))
+                        .child(TextView::new("Errors: ----").with_name("BusErrors"))
+                )
+                .child(DummyView.fixed_width(2))
+                .child(
+                    LinearLayout::vertical()
+                        .child(TextView::new("Latency: ----").with_name("BusLatency"))
+                        .child(TextView::new("Bytes: ----").with_name("BusBytes"))
+                )
+        )
+        .title("Bus")
+        .full_width()
+    );
+
     // Add instructions
     layout.add_child(
         TextView::new("Press 'H' for help")
@@ -163,26 +190,29 @@ fn main() -> Result<()> {
     // Modify Up and Down callbacks to wrap around
     let servo_clone_up = Arc::clone(&servo);
     let selected_servo_up = Arc::clone(&selected_servo);
+    let ids_up = Arc::clone(&ids);
     siv.add_global_callback(cursive::event::Event::Key(cursive::event::Key::Up), move |s| {
         let mut selected = selected_servo_up.lock().unwrap();
-        *selected = (*selected + MAX_SERVOS - 1) % MAX_SERVOS;
-        update_selected_row(s, *selected);
-        update_angle_limits(s, *selected as u8 + 1, &servo_clone_up);
+        *selected = (*selected + ids_up.len() - 1) % ids_up.len();
+        update_selected_row(s, &ids_up, *selected);
+        update_angle_limits(s, ids_up[*selected], servo_clone_up.as_ref());
     });
 
     let servo_clone_down = Arc::clone(&servo);
     let selected_servo_down = Arc::clone(&selected_servo);
+    let ids_down = Arc::clone(&ids);
     siv.add_global_callback(cursive::event::Event::Key(cursive::event::Key::Down), move |s| {
         let mut selected = selected_servo_down.lock().unwrap();
-        *selected = (*selected + 1) % MAX_SERVOS;
-        update_selected_row(s, *selected);
-        update_angle_limits(s, *selected as u8 + 1, &servo_clone_down);
+        *selected = (*selected + 1) % ids_down.len();
+        update_selected_row(s, &ids_down, *selected);
+        update_angle_limits(s, ids_down[*selected], servo_clone_down.as_ref());
     });
 
     siv.add_global_callback('h', show_hints);
 
     let servo_clone_enter = Arc::clone(&servo);
     let selected_servo_enter = Arc::clone(&selected_servo);
+    let ids_enter = Arc::clone(&ids);
     siv.add_global_callback(cursive::event::Event::Key(cursive::event::Key::Enter), move |s| {
         // Check if a settings dialog is already open
         if s.find_name::<Dialog>("servo_settings").is_some() || s.find_name::<Dialog>("capture_dialog").is_some(){
@@ -190,89 +220,91 @@ fn main() -> Result<()> {
         }
 
         let selected = *selected_servo_enter.lock().unwrap();
-        let servo_id = selected as u8 + 1;
+        let servo_id = ids_enter[selected];
         open_servo_settings(s, servo_id, Arc::clone(&servo_clone_enter));
     });
 
     let servo_clone_toggle = Arc::clone(&servo);
     let selected_servo_toggle = Arc::clone(&selected_servo);
+    let ids_toggle = Arc::clone(&ids);
     siv.add_global_callback('t', move |s| {
         let selected = *selected_servo_toggle.lock().unwrap();
-        let servo_id = selected as u8 + 1;
+        let servo_id = ids_toggle[selected];
         toggle_servo_torque(s, servo_id, Arc::clone(&servo_clone_toggle));
     });
 
     let servo_clone_calibrate_start = Arc::clone(&servo);
     let selected_servo_calibrate_start = Arc::clone(&selected_servo);
+    let ids_calibrate_start = Arc::clone(&ids);
     siv.add_global_callback('[', move |s| {
         let selected = *selected_servo_calibrate_start.lock().unwrap();
-        let servo_id = selected as u8 + 1;
+        let servo_id = ids_calibrate_start[selected];
         start_calibration(s, servo_id, Arc::clone(&servo_clone_calibrate_start));
     });
 
     let servo_clone_calibrate_end = Arc::clone(&servo);
     let selected_servo_calibrate_end = Arc::clone(&selected_servo);
+    let ids_calibrate_end = Arc::clone(&ids);
     siv.add_global_callback(']', move |s| {
         let selected = *selected_servo_calibrate_end.lock().unwrap();
-        let servo_id = selected as u8 + 1;
+        let servo_id = ids_calibrate_end[selected];
         end_calibration(s, servo_id, Arc::clone(&servo_clone_calibrate_end));
     });
 
     let mut update_count = 0;
     let servo_clone_for_scan = Arc::clone(&servo);
+    let ids_refresh = Arc::clone(&ids);
 
     siv.set_global_callback(cursive::event::Event::Refresh, move |s| {
         update_count += 1;
 
         match servo_clone.read_continuous() {
             Ok(data) => {
-                for (i, servo_info) in data.servo.iter().enumerate() {
-                    s.call_on_name(&format!("CurrPos {}", i), |view: &mut TextView| {
+                for (i, &id) in ids_refresh.iter().enumerate() {
+                    // Servos missing from the readout show zeros until they answer
+                    let servo_info = data.get(id).copied().unwrap_or_default();
+                    s.call_on_name(&format!("CurrPos {}", id), |view: &mut TextView| {
                         view.set_content(format!("{:4}", servo_info.current_location));
                     });
-                    s.call_on_name(&format!("CurrSpd {}", i), |view: &mut TextView| {
-                        let speed = servo_info.current_speed as u16 & 0x7FFF; // Remove 15th bit
-                        let sign = if servo_info.current_speed as u16 & 0x8000 != 0 { '-' } else { '+' };
-                        view.set_content(format!("{}{:4}", sign, speed));
+                    let readout = servo_clone.units(id).readout(&servo_info);
+                    s.call_on_name(&format!("CurrSpd {}", id), |view: &mut TextView| {
+                        view.set_content(format!("{:+5.0}°/s", readout.velocity.degrees_per_second()));
                     });
-                    s.call_on_name(&format!("Load {}", i), |view: &mut TextView| {
-                        let speed = servo_info.current_load as u16 & 0x3FF; // Remove 10th bit
-                        let sign = if servo_info.current_load as u16 & 0x400 != 0 { '-' } else { '+' };
-                        view.set_content(format!("{}{:4}", sign, speed));
+                    s.call_on_name(&format!("Load {}", id), |view: &mut TextView| {
+                        view.set_content(format!("{:+5.1}%", readout.load.percent()));
                     });
-                    update_torque_display(s, (i + 1) as u8, servo_info.torque_switch);
-                    s.call_on_name(&format!("TorqLim {}", i), |view: &mut TextView| {
+                    update_torque_display(s, id, servo_info.torque_switch);
+                    s.call_on_name(&format!("TorqLim {}", id), |view: &mut TextView| {
                         view.set_content(format!("{:4}", servo_info.torque_limit));
                     });
-                    s.call_on_name(&format!("Volt {}", i), |view: &mut TextView| {
-                        view.set_content(format!("{:2.1}V", servo_info.current_voltage as f32 / 10.0));
+                    s.call_on_name(&format!("Volt {}", id), |view: &mut TextView| {
+                        view.set_content(format!("{:2.1}V", readout.voltage.volts()));
                     });
-                    s.call_on_name(&format!("Temp {}", i), |view: &mut TextView| {
-                        view.set_content(format!("{}°C", servo_info.current_temperature));
+                    s.call_on_name(&format!("Temp {}", id), |view: &mut TextView| {
+                        view.set_content(format!("{:.0}°C", readout.temperature.celsius()));
                     });
-                    s.call_on_name(&format!("Curr {}", i), |view: &mut TextView| {
-                        view.set_content(format!("{:4}", servo_info.current_current));
+                    s.call_on_name(&format!("Curr {}", id), |view: &mut TextView| {
+                        view.set_content(readout.current.map_or("n/a".to_string(), |current| format!("{:.2}A", current.amps())));
                     });
-                    s.call_on_name(&format!("Status {}", i), |view: &mut TextView| {
-                        view.set_content(format!("{:05b}", servo_info.servo_status));
+                    s.call_on_name(&format!("Status {}", id), |view: &mut TextView| {
+                        view.set_content(servo_info.faults().letters());
                     });
-                    if servo_info.servo_status != 0 {
-                        s.call_on_name(&format!("Status {}", i), |view: &mut TextView| {
+                    if !servo_info.faults().is_empty() {
+                        s.call_on_name(&format!("Status {}", id), |view: &mut TextView| {
                             view.set_style(ColorStyle::highlight());
                         });
                     } else {
-                        s.call_on_name(&format!("Status {}", i), |view: &mut TextView| {
+                        s.call_on_name(&format!("Status {}", id), |view: &mut TextView| {
                             view.set_style(ColorStyle::default());
                         });
                     }
-                    s.call_on_name(&format!("Lock {}", i), |view: &mut TextView| {
+                    s.call_on_name(&format!("Lock {}", id), |view: &mut TextView| {
                         view.set_content(format!("{:4}", servo_info.lock_mark));
                     });
 
                     // Check servo responsiveness every 10th update
                     if update_count % 50 == 0 {
-                        let servo_id = i as u8 + 1;
-                        let mut is_responsive = match servo_clone_for_scan.scan(servo_id) {
+                        let mut is_responsive = match servo_clone_for_scan.scan(id) {
                             Ok(true) => true,
                             Ok(false) => false,
                             Err(_) => false,
@@ -288,24 +320,24 @@ fn main() -> Result<()> {
                         };
                         
                         // Apply style to ID and Joint name
-                        s.call_on_name(&format!("ID {}", i), |view: &mut TextView| {
+                        s.call_on_name(&format!("ID {}", id), |view: &mut TextView| {
                             view.set_style(style);
                         });
-                        s.call_on_name(&format!("Joint {}", i), |view: &mut TextView| {
+                        s.call_on_name(&format!("Joint {}", id), |view: &mut TextView| {
                             view.set_style(style);
                         });
 
                         let selected = *selected_servo.lock().unwrap();
 
                         if selected == i && unresponsive_servos[i] {
-                            s.call_on_name(&format!("ID {}", i), |view: &mut TextView| {
+                            s.call_on_name(&format!("ID {}", id), |view: &mut TextView| {
                                 view.set_style(ColorStyle::secondary());
                             });
                         } else if selected == i {
-                            s.call_on_name(&format!("ID {}", i), |view: &mut TextView| {
+                            s.call_on_name(&format!("ID {}", id), |view: &mut TextView| {
                                 view.set_style(ColorStyle::secondary());
                             });
-                            s.call_on_name(&format!("Joint {}", i), |view: &mut TextView| {
+                            s.call_on_name(&format!("Joint {}", id), |view: &mut TextView| {
                                 view.set_style(ColorStyle::secondary());
                             });
                         }
@@ -334,7 +366,10 @@ fn main() -> Result<()> {
                 *last_update = now;
 
                 let selected = *selected_servo.lock().unwrap();
-                let current_pos = data.servo[selected].current_location;
+                if update_count % 10 == 0 {
+                    update_bus_stats(s, ids_refresh[selected], &servo_clone.bus_stats());
+                }
+                let current_pos = data.get(ids_refresh[selected]).map_or(0, |info| info.current_location);
                 CURRENT_POSITION.store(current_pos, Ordering::Relaxed);
 
                 s.call_on_name("CalibrationPos", |view: &mut TextView| {
@@ -354,7 +389,7 @@ fn main() -> Result<()> {
     });
 
     // Initialize the OnceLock at the start of main
-    UNRESPONSIVE_SERVOS.get_or_init(|| Arc::new(Mutex::new(vec![false; MAX_SERVOS])));
+    UNRESPONSIVE_SERVOS.get_or_init(|| Arc::new(Mutex::new(vec![false; ids.len()])));
 
     // Initialize capture state
     CAPTURE_STATE.get_or_init(|| Arc::new(Mutex::new(CaptureState {
@@ -366,6 +401,7 @@ fn main() -> Result<()> {
     siv.set_user_data(servo.clone());
 
     // Add capture callbacks
+    siv.add_global_callback('b', show_bus_stats);
     siv.add_global_callback('c', |s| handle_capture(s, false));
     siv.add_global_callback('C', |s| handle_capture(s, true));
 
@@ -375,10 +411,10 @@ fn main() -> Result<()> {
 }
 
 // Update the update_selected_row function
-fn update_selected_row(s: &mut cursive::Cursive, selected: usize) {
+fn update_selected_row(s: &mut cursive::Cursive, ids: &[u8], selected: usize) {
     let unresponsive_servos = UNRESPONSIVE_SERVOS.get().unwrap().lock().unwrap();
     
-    for i in 0..MAX_SERVOS {
+    for (i, &id) in ids.iter().enumerate() {
         let style = if unresponsive_servos[i] {
             ColorStyle::highlight()
         } else if i == selected {
@@ -387,16 +423,91 @@ fn update_selected_row(s: &mut cursive::Cursive, selected: usize) {
             ColorStyle::default()
         };
 
-        s.call_on_name(&format!("ID {}", i), |view: &mut TextView| {
+        s.call_on_name(&format!("ID {}", id), |view: &mut TextView| {
             view.set_style(style);
         });
-        s.call_on_name(&format!("Joint {}", i), |view: &mut TextView| {
+        s.call_on_name(&format!("Joint {}", id), |view: &mut TextView| {
             view.set_style(style);
         });
     }
 }
 
-fn update_angle_limits(s: &mut cursive::Cursive, servo_id: u8, servo: &Arc<Servo>) {
+fn update_bus_stats(s: &mut cursive::Cursive, servo_id: u8, stats: &BusStats) {
+    let stats = stats.get(servo_id).cloned().unwrap_or_default();
+    s.call_on_name("BusTransactions", |view: &mut TextView| {
+        view.set_content(format!(
+            "Transactions: {}  Errors: {} ({:.1}%)  Retries: {}",
+            stats.transactions, stats.errors(), stats.error_rate() * 100.0, stats.retries
+        ));
+    });
+    s.call_on_name("BusErrors", |view: &mut TextView| {
+        view.set_content(format!(
+            "Timeout: {}  Checksum: {}  ID: {}  Short: {}  Verify: {}",
+            stats.timeouts, stats.checksum_errors, stats.id_mismatches, stats.truncated, stats.verify_failures
+        ));
+    });
+    s.call_on_name("BusLatency", |view: &mut TextView| {
+        view.set_content(format!(
+            "Latency p50: {}  p99: {}  max: {}",
+            format_latency(stats.latency.quantile(0.5)),
+            format_latency(stats.latency.quantile(0.99)),
+            format_latency(stats.latency.max())
+        ));
+    });
+    s.call_on_name("BusBytes", |view: &mut TextView| {
+        view.set_content(format!("Bytes sent: {}  received: {}", stats.bytes_sent, stats.bytes_received));
+    });
+}
+
+fn show_bus_stats(s: &mut cursive::Cursive) {
+    let servo = s.user_data::<Arc<Servo>>().unwrap().clone();
+    let stats = servo.bus_stats();
+
+    let mut lines = vec![format!(
+        "{:>3} {:>8} {:>7} {:>7} {:>5} {:>5} {:>6} {:>6} {:>8} {:>8} {:>8}",
+        "ID", "Trans", "Timeout", "Chksum", "ID", "Short", "Retry", "Verify", "p50", "p99", "Max"
+    )];
+    for (id, servo_stats) in &stats.servos {
+        lines.push(format!(
+            "{:>3} {:>8} {:>7} {:>7} {:>5} {:>5} {:>6} {:>6} {:>8} {:>8} {:>8}",
+            id,
+            servo_stats.transactions,
+            servo_stats.timeouts,
+            servo_stats.checksum_errors,
+            servo_stats.id_mismatches,
+            servo_stats.truncated,
+            servo_stats.retries,
+            servo_stats.verify_failures,
+            format_latency(servo_stats.latency.quantile(0.5)),
+            format_latency(servo_stats.latency.quantile(0.99)),
+            format_latency(servo_stats.latency.max())
+        ));
+    }
+    if stats.servos.is_empty() {
+        lines.push("This backend doesn't report bus statistics".to_string());
+    }
+    lines.push(String::new());
+    lines.push(format!("Bus total: {} bytes sent, {} bytes received", stats.bytes_sent, stats.bytes_received));
+
+    s.add_layer(
+        Dialog::around(TextView::new(lines.join("\n")).scrollable())
+            .title("Bus Statistics")
+            .button("Clear", move |s| {
+                servo.clear_bus_stats();
+                s.pop_layer();
+            })
+            .button("Close", |s| { s.pop_layer(); })
+    );
+}
+
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{:.1}ms", latency.as_secs_f64() * 1000.0),
+        None => "----".to_string(),
+    }
+}
+
+fn update_angle_limits<S: ServoBus>(s: &mut cursive::Cursive, servo_id: u8, servo: &S) {
     match servo.read_angle_limits(servo_id) {
         Ok((min_angle, max_angle)) => {
             s.call_on_name("MinAngle", |view: &mut TextView| {
@@ -407,7 +518,8 @@ fn update_angle_limits(s: &mut cursive::Cursive, servo_id: u8, servo: &Arc<Servo
             });
             
             // Calculate and display the angle range
-            let angle_range = (max_angle as f64 - min_angle as f64) / 4096.0 * 360.0;
+            let profile = servo.profile(servo_id);
+            let angle_range = (max_angle - min_angle) as f32 / profile.resolution as f32 * profile.range_degrees;
             s.call_on_name("AngleRange", |view: &mut TextView| {
                 view.set_content(format!("Angle Range: {:.2}°", angle_range));
             });
@@ -427,9 +539,8 @@ fn update_angle_limits(s: &mut cursive::Cursive, servo_id: u8, servo: &Arc<Servo
     }
 
     // Update offset
-    match servo.read(servo_id, ServoRegister::PositionCorrection, 2) {
-        Ok(data) => {
-            let offset = i16::from_le_bytes([data[0], data[1]]);
+    match servo.read_reg::<i16>(servo_id, ServoRegister::PositionCorrection) {
+        Ok(offset) => {
             s.call_on_name("Offset", |view: &mut TextView| {
                 view.set_content(format!("Offset: {}", offset));
             });
@@ -514,7 +625,7 @@ fn open_servo_settings(s: &mut cursive::Cursive, servo_id: u8, servo: Arc<Servo>
 
             // Set offset if provided
             if let Some(off) = offset {
-                if let Err(e) = set_servo_offset(servo_id, off, &servo) {
+                if let Err(e) = set_servo_offset(servo_id, off, servo.as_ref()) {
                     s.add_layer(Dialog::info(format!("Error setting offset: {}", e)));
                 } else {
                     s.call_on_name("Offset", |view: &mut TextView| {
@@ -525,7 +636,7 @@ fn open_servo_settings(s: &mut cursive::Cursive, servo_id: u8, servo: Arc<Servo>
 
             // Set torque limit if provided
             if torque_limit > 0 {
-                if let Err(e) = set_servo_torque(servo_id, torque_limit, &servo) {
+                if let Err(e) = set_servo_torque(servo_id, torque_limit, servo.as_ref()) {
                     s.add_layer(Dialog::info(format!("Error setting torque limit: {}", e)));
                 } else {
                     s.call_on_name("CurrentTorque", |view: &mut TextView| {
@@ -544,20 +655,12 @@ fn open_servo_settings(s: &mut cursive::Cursive, servo_id: u8, servo: Arc<Servo>
     s.add_layer(dialog);
 }
 
-fn set_servo_offset(servo_id: u8, offset: i16, servo: &Arc<Servo>) -> Result<()> {
-    let offset_value = if offset < 0 {
-        (offset.abs() as u16) | 0x800 // Set bit 11 for negative values
-    } else {
-        offset as u16
-    };
-
+fn set_servo_offset<S: ServoBus>(servo_id: u8, offset: i16, servo: &S) -> Result<()> {
     // Unlock EEPROM
     servo.write(servo_id, ServoRegister::LockMark, &[0])?;
-    std::thread::sleep(Duration::from_millis(10));
 
     // Write new offset
-    servo.write_servo_memory(servo_id, ServoRegister::PositionCorrection, offset_value)?;
-    std::thread::sleep(Duration::from_millis(10));
+    servo.write_reg(servo_id, ServoRegister::PositionCorrection, offset)?;
 
     // Lock EEPROM
     servo.write(servo_id, ServoRegister::LockMark, &[1])?;
@@ -595,7 +698,7 @@ fn toggle_servo_torque(s: &mut cursive::Cursive, servo_id: u8, servo: Arc<Servo>
 }
 
 fn update_torque_display(s: &mut cursive::Cursive, servo_id: u8, torque_value: u8) {
-    s.call_on_name(&format!("Torque {}", servo_id as usize - 1), |view: &mut TextView| {
+    s.call_on_name(&format!("Torque {}", servo_id), |view: &mut TextView| {
         view.set_content(format!("{:4}", torque_value));
         if torque_value == 1 {
             view.set_style(ColorStyle::secondary());
@@ -632,30 +735,24 @@ fn end_calibration(s: &mut cursive::Cursive, servo_id: u8, servo: Arc<Servo>) {
     }
 
     let mut max_pos = CURRENT_POSITION.load(Ordering::Relaxed);
+    let resolution = servo.profile(servo_id).resolution as i16;
+    let center = resolution / 2;
 
     if max_pos < min_pos {
-        max_pos += 4096;
+        max_pos += resolution;
     }
 
-    let offset_value = min_pos + (max_pos - min_pos) / 2 - 2048;
+    let offset_value = min_pos + (max_pos - min_pos) / 2 - center;
 
-    // Convert offset to 12-bit signed value
-    let offset_value = if offset_value < 0 {
-        offset_value.abs() as u16 | 0x800 // (set negative)
-    } else {
-        if offset_value > 2048 {
-            (offset_value - 4096).abs() as u16 | 0x800
-        } else {
-            offset_value as u16
-        }
-    };
+    // PositionCorrection is signed, wrap to the shorter way round
+    let offset_value = if offset_value > center { offset_value - resolution } else { offset_value };
 
     // Calculate new limits
-    let min_angle = 2048 - (max_pos - min_pos) / 2;
-    let max_angle = 2048 + (max_pos - min_pos) / 2;
+    let min_angle = center - (max_pos - min_pos) / 2;
+    let max_angle = center + (max_pos - min_pos) / 2;
 
     // Write new values to EEPROM
-    if let Err(e) = write_calibration_to_eeprom(servo_id, &servo, offset_value, min_angle, max_angle) {
+    if let Err(e) = write_calibration_to_eeprom(servo_id, servo.as_ref(), offset_value, min_angle, max_angle) {
         s.add_layer(Dialog::info(format!("Error writing calibration to EEPROM: {}", e)));
         return;
     }
@@ -675,46 +772,21 @@ fn end_calibration(s: &mut cursive::Cursive, servo_id: u8, servo: Arc<Servo>) {
     s.add_layer(Dialog::info(format!("Calibration completed for servo {}. New offset: {}", servo_id, offset_value)));
 }
 
-fn write_calibration_to_eeprom(servo_id: u8, servo: &Servo, offset: u16, min_angle: i16, max_angle: i16) -> Result<()> {
+// The HAL reads each EEPROM write back and retries it, see RetryPolicy
+fn write_calibration_to_eeprom<S: ServoBus>(servo_id: u8, servo: &S, offset: i16, min_angle: i16, max_angle: i16) -> Result<()> {
     // Unlock EEPROM
     servo.write(servo_id, ServoRegister::LockMark, &[0])?;
-    std::thread::sleep(Duration::from_millis(20));
 
-    // Write new offset
-    servo.write_servo_memory(servo_id, ServoRegister::PositionCorrection, offset)?;
-    std::thread::sleep(Duration::from_millis(20));
-
-    // Write new limits
-    for try_num in 0..3 {
-        servo.write_servo_memory(servo_id, ServoRegister::MinAngleLimit, min_angle as u16)?;
-        std::thread::sleep(Duration::from_millis(20));
-        let read_min = servo.read(servo_id, ServoRegister::MinAngleLimit, 2)?;
-        let read_min = u16::from_le_bytes([read_min[0], read_min[1]]);
-        if read_min == min_angle as u16 {
-            break;
-        }
-        if try_num == 2 {
-            return Err(anyhow::anyhow!("Failed to write MinAngleLimit after 3 attempts"));
-        }
-    }
-
-    for try_num in 0..3 {
-        servo.write_servo_memory(servo_id, ServoRegister::MaxAngleLimit, max_angle as u16)?;
-        std::thread::sleep(Duration::from_millis(20));
-        let read_max = servo.read(servo_id, ServoRegister::MaxAngleLimit, 2)?;
-        let read_max = u16::from_le_bytes([read_max[0], read_max[1]]);
-        if read_max == max_angle as u16 {
-            break;
-        }
-        if try_num == 2 {
-            return Err(anyhow::anyhow!("Failed to write MaxAngleLimit after 3 attempts"));
-        }
-    }
+    let written = [
+        (ServoRegister::PositionCorrection, offset),
+        (ServoRegister::MinAngleLimit, min_angle),
+        (ServoRegister::MaxAngleLimit, max_angle),
+    ].into_iter().try_for_each(|(register, value)| servo.write_reg(servo_id, register, value));
 
-    // Lock EEPROM
-    servo.write(servo_id, ServoRegister::LockMark, &[1])?;
-
-    Ok(())
+    // Lock EEPROM, even if a write failed
+    let locked = servo.write(servo_id, ServoRegister::LockMark, &[1]);
+    written?;
+    locked
 }
 
 fn handle_capture(s: &mut cursive::Cursive, end: bool) {
@@ -756,10 +828,9 @@ fn start_capture(s: &mut cursive::Cursive, name: String) {
 
     match servo.read_continuous() {
         Ok(data) => {
-            let positions: serde_json::Map<String, Value> = data.servo
+            let positions: serde_json::Map<String, Value> = data.servos
                 .iter()
-                .enumerate()
-                .map(|(i, info)| ((i + 1).to_string(), json!(info.current_location)))
+                .map(|(id, info)| (id.to_string(), json!(info.current_location)))
                 .collect();
             
             capture_state.captures.push(json!({
@@ -784,10 +855,9 @@ fn continue_capture(s: &mut cursive::Cursive) {
 
     match servo.read_continuous() {
         Ok(data) => {
-            let positions: serde_json::Map<String, Value> = data.servo
+            let positions: serde_json::Map<String, Value> = data.servos
                 .iter()
-                .enumerate()
-                .map(|(i, info)| ((i + 1).to_string(), json!(info.current_location)))
+                .map(|(id, info)| (id.to_string(), json!(info.current_location)))
                 .collect();
             
             capture_state.captures.push(json!({
@@ -821,7 +891,7 @@ fn end_capture(s: &mut cursive::Cursive) {
 }
 
 // Add this function to set the torque for a servo
-fn set_servo_torque(servo_id: u8, torque: u16, servo: &Arc<Servo>) -> Result<()> {
+fn set_servo_torque<S: ServoBus>(servo_id: u8, torque: u16, servo: &S) -> Result<()> {
     // Unlock EEPROM
     servo.write(servo_id, ServoRegister::LockMark, &[0])?;
     std::thread::sleep(Duration::from_millis(10));
@@ -1,6 +1,6 @@
 use anyhow::{Result, bail};
 use ctrlc;
-use runtime::hal::{Servo, ServoRegister};
+use runtime::hal::{Servo, ServoBus};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
@@ -31,19 +31,22 @@ fn main() -> Result<()> {
 
     let loop_duration = Duration::from_secs_f64(1.0 / LOOP_RATE);
 
+    // Identifies the model the readings are scaled for
+    servo.scan(servo_id)?;
     servo.enable_readout()?;
 
     while running.load(Ordering::SeqCst) {
         let start = Instant::now();
 
-        match read_servo_info(&servo, servo_id) {
+        match servo.read_info(servo_id) {
             Ok(info) => {
+                let readout = servo.units(servo_id).readout(&info);
                 println!(
-                    "Position: {}, Speed: {}, Load: {}, Current: {} mA",
-                    info.position,
-                    info.speed,
-                    info.load,
-                    info.current
+                    "Position: {:.2}°, Speed: {:.1}°/s, Load: {}, Current: {}",
+                    readout.position.degrees(),
+                    readout.velocity.degrees_per_second(),
+                    readout.load,
+                    readout.current.map_or("n/a".to_string(), |current| current.to_string())
                 );
             }
             Err(e) => {
@@ -62,24 +65,3 @@ fn main() -> Result<()> {
     println!("Exiting...");
     Ok(())
 }
-
-struct ServoInfo {
-    position: u16,
-    speed: i16,
-    load: i16,
-    current: f32,
-}
-
-fn read_servo_info(servo: &Servo, id: u8) -> Result<ServoInfo> {
-    let position = servo.read(id, ServoRegister::CurrentLocation, 2)?;
-    let speed = servo.read(id, ServoRegister::CurrentSpeed, 2)?;
-    let load = servo.read(id, ServoRegister::CurrentLoad, 2)?;
-    let current = servo.read(id, ServoRegister::CurrentCurrent, 2)?;
-
-    Ok(ServoInfo {
-        position: u16::from_le_bytes([position[0], position[1]]),
-        speed: i16::from_le_bytes([speed[0], speed[1]]),
-        load: i16::from_le_bytes([load[0], load[1]]),
-        current: u16::from_le_bytes([current[0], current[1]]) as f32 * 6.5 / 100.0,
-    })
-}
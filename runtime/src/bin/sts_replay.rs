@@ -5,7 +5,8 @@ use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, Context};
 use clap::Parser;
-use runtime::hal::{Servo, MAX_SERVOS, ServoMultipleWriteCommand};
+use std::collections::BTreeMap;
+use runtime::hal::{Servo, ServoBus, MAX_SERVO_ID};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -36,48 +37,27 @@ fn main() -> Result<()> {
 
     println!("Replaying capture: {}", capture_data.name);
 
-    let mut current_positions = vec![0; MAX_SERVOS];
-    let servo_data = servo.read_continuous()?;
-    servo_data.servo.iter().enumerate().for_each(|(i, s)| {
-        current_positions[i] = s.current_location as i32;
-    });
+    servo.enable_readout()?;
 
     loop {
         for frame in &capture_data.cap {
             let start_time = Instant::now();
-            let steps = (frame.delay as f64 / 20.0).ceil() as u64;
-            let target_positions = frame_to_positions(frame);
-            
-            for step in 0..steps {
-                let progress = step as f64 / steps as f64;
-                let interpolated_positions: Vec<i32> = current_positions.iter().zip(target_positions.iter())
-                    .map(|(&current, &target)| {
-                        (current as f64 + (target as f64 - current as f64) * progress) as i32
-                    })
-                    .collect();
+            // Servos missing from a frame hold their previous position
+            let goals: Vec<(u8, i16)> = frame_to_positions(frame)
+                .into_iter()
+                .map(|(id, position)| (id, position as i16))
+                .collect();
+
+            // Every servo in the frame arrives when the frame ends, or together later
+            let duration = if goals.is_empty() {
+                Duration::from_millis(frame.delay)
+            } else {
+                servo.move_synchronized(&goals, Duration::from_millis(frame.delay))?
+            };
 
-                let mut cmd = ServoMultipleWriteCommand {
-                    only_write_positions: 1,
-                    ids: [0; MAX_SERVOS],
-                    positions: [0; MAX_SERVOS],
-                    times: [0; MAX_SERVOS],
-                    speeds: [0; MAX_SERVOS],
-                };
-
-                for (servo_id, &pos) in interpolated_positions.iter().enumerate() {
-                    cmd.ids[servo_id] = servo_id as u8 + 1;
-                    cmd.positions[servo_id] = pos as i16;
-                }
-
-                servo.write_multiple(&cmd)?;
-
-                std::thread::sleep(Duration::from_millis(20));
-            }
-
-            current_positions = target_positions;
             let elapsed = start_time.elapsed();
-            if elapsed < Duration::from_millis(frame.delay) {
-                std::thread::sleep(Duration::from_millis(frame.delay) - elapsed);
+            if elapsed < duration {
+                std::thread::sleep(duration - elapsed);
             }
         }
 
@@ -98,12 +78,12 @@ fn load_capture_file(path: &PathBuf) -> Result<CaptureData> {
     Ok(capture_data)
 }
 
-fn frame_to_positions(frame: &CapFrame) -> Vec<i32> {
-    let mut positions = vec![0; MAX_SERVOS];
+fn frame_to_positions(frame: &CapFrame) -> BTreeMap<u8, i32> {
+    let mut positions = BTreeMap::new();
     for (key, &value) in &frame.pos {
-        if let Ok(index) = key.parse::<usize>() {
-            if index > 0 && index <= MAX_SERVOS {
-                positions[index - 1] = value;
+        if let Ok(id) = key.parse::<u8>() {
+            if id > 0 && id <= MAX_SERVO_ID {
+                positions.insert(id, value);
             }
         }
     }
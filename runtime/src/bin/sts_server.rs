@@ -11,9 +11,9 @@ use std::net::IpAddr;
 use regex::Regex;
 use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::task;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 use std::env;
-use runtime::hal::{Servo, IMU, MAX_SERVOS, ServoMultipleWriteCommand, ServoData, ServoMode, ServoDirection, ServoRegister, TorqueMode};
+use runtime::hal::{AsyncServo, Servo, ServoBus, ServoError, ServoFaults, ServoStats, ServoUnits, Angle, AngularAcceleration, AngularVelocity, Current, MotionProfiles, position_writes, FaultEvent, LATENCY_BUCKETS_US, IMU, ImuSource, MAX_SERVO_ID, ServoTarget, ServoData, ServoMode, ServoDirection, ServoRegister, TorqueMode};
 use std::collections::HashMap;
 use tokio::sync::RwLock;
 use uuid::Uuid;
@@ -23,42 +23,68 @@ use std::pin::Pin;
 use chrono::prelude::*;
 use tokio_stream::{self, StreamExt, Stream};
 
+const FAULT_MONITOR_INTERVAL: Duration = Duration::from_millis(100);
+// Calibration thresholds per amp, in the units clients have always sent
+const CALIBRATION_CURRENT_SCALE: f32 = 65.0;
+
 pub mod servo_control {
     tonic::include_proto!("hal_pb");
 }
 
 use servo_control::servo_control_server::{ServoControl, ServoControlServer};
-use servo_control::{Empty, JointPositions, WifiCredentials, ServoId, ServoInfo, ServoIds, IdChange, ChangeIdResponse, ServoInfoResponse, servo_info_response, change_id_response, VideoStreamUrls, CalibrationResponse, CalibrationStatus, TorqueSettings, TorqueEnableSettings, ImuData, Vector3, AudioChunk, UploadResponse, PlayRequest, RecordingConfig, CalibrationRequest};
+use servo_control::{Empty, JointPositions, WifiCredentials, ServoId, ServoInfo, ServoIds, IdChange, ChangeIdResponse, ServoInfoResponse, servo_info_response, change_id_response, VideoStreamUrls, CalibrationResponse, CalibrationStatus, TorqueSettings, TorqueEnableSettings, ImuData, Vector3, AudioChunk, UploadResponse, PlayRequest, RecordingConfig, CalibrationRequest, ServoFaultStatus, BusStats, ServoBusStats};
 
 #[derive(Debug)]
-pub struct StsServoControl {
-    servo: Arc<Mutex<Servo>>,
-    imu: Arc<Mutex<Option<IMU>>>,
+pub struct StsServoControl<S: ServoBus = Servo, I: ImuSource = IMU> {
+    servo: AsyncServo<S>,
+    imu: Arc<Mutex<Option<I>>>,
     last_positions: Arc<Mutex<ServoData>>,
     calibrating_servo: Arc<Mutex<Option<u8>>>,
     calibration_running: Arc<AtomicBool>,
     audio_files: Arc<RwLock<HashMap<String, PathBuf>>>,
     recording_running: Arc<AtomicBool>,
+    motion_profiles: Arc<MotionProfiles>,
 }
 
 impl StsServoControl {
     pub fn new() -> Result<Self> {
-        let servo = Servo::new()?;
-        let imu = IMU::new().ok();
+        Self::with_hardware(Servo::new()?, IMU::new().ok())
+    }
+}
+
+impl<S: ServoBus + 'static, I: ImuSource + 'static> StsServoControl<S, I> {
+    pub fn with_hardware(servo: S, imu: Option<I>) -> Result<Self> {
+        // Identifies each servo's model before the bus is busy with readout
+        for id in servo.ids() {
+            let _ = servo.scan(id);
+        }
         servo.enable_readout()?;
         let initial_data = servo.read_continuous()?;
         
         Ok(Self {
-            servo: Arc::new(Mutex::new(servo)),
+            servo: AsyncServo::new(servo)?,
             imu: Arc::new(Mutex::new(imu)),
             last_positions: Arc::new(Mutex::new(initial_data)),
             calibrating_servo: Arc::new(Mutex::new(None)),
             calibration_running: Arc::new(AtomicBool::new(false)),
             audio_files: Arc::new(RwLock::new(HashMap::new())),
             recording_running: Arc::new(AtomicBool::new(false)),
+            motion_profiles: Arc::new(MotionProfiles::from_env()?),
         })
     }
 
+    // Reads the bus periodically so fault transitions are latched even when no client polls
+    fn spawn_fault_monitor(&self) {
+        let servo = self.servo.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FAULT_MONITOR_INTERVAL);
+            loop {
+                interval.tick().await;
+                let _ = servo.diagnostic(|servo| servo.read_continuous()).await;
+            }
+        });
+    }
+
     fn get_interface_ip(interface: &str) -> Option<IpAddr> {
         let output = Command::new("ip")
             .args(&["addr", "show", interface])
@@ -79,166 +105,166 @@ impl StsServoControl {
         let calibration_running = self.calibration_running.clone();
 
         task::spawn(async move {
-            let servo = servo.lock().await;
+            match Self::find_limits(servo_id, &servo, calibration_speed, current_threshold, &calibration_running).await {
+                Ok(Some((max_backward, max_forward))) => {
+                    *calibrating_servo.lock().await = None;
+                    calibration_running.store(false, Ordering::SeqCst);
+                    if let Err(e) = Self::calculate_and_write_calibration(servo_id, &servo, max_backward, max_forward).await {
+                        eprintln!("Failed to write calibration for servo {}: {}", servo_id, e.message());
+                    }
+                }
+                // Cancelled
+                Ok(None) => {
+                    if let Err(e) = Self::end_calibration(servo_id, &servo).await {
+                        eprintln!("Failed to restore servo {} after cancelling calibration: {:#}", servo_id, e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Calibration of servo {} failed: {:#}", servo_id, e);
 
-            servo.disable_movement().unwrap();
+                    let _ = Self::end_calibration(servo_id, &servo).await;
+                    *calibrating_servo.lock().await = None;
+                    calibration_running.store(false, Ordering::SeqCst);
+                }
+            }
+        });
 
+        Ok(())
+    }
 
-            servo.disable_readout().unwrap();
-            servo.set_mode(servo_id, ServoMode::ConstantSpeed).unwrap();
+    // Drives the servo into both end stops, returning them or None if cancelled
+    async fn find_limits(servo_id: u8, servo: &AsyncServo<S>, calibration_speed: u16, current_threshold: f32, calibration_running: &AtomicBool) -> Result<Option<(i16, i16)>> {
+        servo.diagnostic(|s| s.disable_movement()).await?;
 
-            servo.write_servo_memory(servo_id, runtime::hal::ServoRegister::TorqueLimit, 150).unwrap();
 
-            let mut max_forward = 0;
-            let mut max_backward = 0;
+        servo.diagnostic(|s| s.disable_readout()).await?;
+        servo.diagnostic(move |s| s.switch_mode(servo_id, ServoMode::ConstantSpeed)).await?;
 
-            let version = servo.read(servo_id, ServoRegister::ServoMainVersion, 2).unwrap();
-            let mut current_multiplier = 1.0;
+        servo.diagnostic(move |s| s.write_servo_memory(servo_id, runtime::hal::ServoRegister::TorqueLimit, 150)).await?;
 
-            // sts3215
-            if version[0] == 0x09 && version[1] == 0x03 {
-                current_multiplier = 6.5;
-            }
+        let mut max_forward = 0;
+        let mut max_backward = 0;
 
-            for pass in 0..2 {
-                let direction = if pass == 0 { ServoDirection::Clockwise } else { ServoDirection::Counterclockwise };
-                servo.set_speed(servo_id, calibration_speed, direction).unwrap();
+        let profile = servo.diagnostic(move |s| s.identify(servo_id)).await?;
+        if profile.current_lsb_ma.is_none() {
+            return Err(ServoError::InvalidInput(format!("{} servos have no current sensing", profile.name)).into());
+        }
+        let units = ServoUnits::new(profile);
 
-                let mut threshold_exceeded_count = 0;
+        for pass in 0..2 {
+            let direction = if pass == 0 { ServoDirection::Clockwise } else { ServoDirection::Counterclockwise };
+            servo.diagnostic(move |s| s.set_speed(servo_id, calibration_speed, direction)).await?;
 
-                loop {
-                    if !calibration_running.load(Ordering::SeqCst) {
-                        servo.set_speed(servo_id, 0, ServoDirection::Clockwise).unwrap();
-                        return;
-                    }
+            let mut threshold_exceeded_count = 0;
 
-                    let mut info = servo.read_info(servo_id).unwrap();
-                    let mut retry_count = 0;
-                    while info.current_current == 0 && info.current_location == 0 && retry_count < 3 {
-                        tokio::time::sleep(Duration::from_millis(1)).await;
-                        info = servo.read_info(servo_id).unwrap();
-                        retry_count += 1;
-                    }
-                    let position = info.current_location;
-                    let current = info.current_current as f32 * 6.5 / 100.0 * current_multiplier;
+            loop {
+                if !calibration_running.load(Ordering::SeqCst) {
+                    servo.control(move |s| s.set_speed(servo_id, 0, ServoDirection::Clockwise)).await?;
+                    return Ok(None);
+                }
+
+                let mut info = servo.diagnostic(move |s| s.read_info(servo_id)).await?;
+                let mut retry_count = 0;
+                while info.current_current == 0 && info.current_location == 0 && retry_count < 3 {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    info = servo.diagnostic(move |s| s.read_info(servo_id)).await?;
+                    retry_count += 1;
+                }
+                let position = info.current_location;
+                let current = units.current(info.current_current).map_or(0.0, Current::amps) * CALIBRATION_CURRENT_SCALE;
+                
+                if current > current_threshold {
+                    threshold_exceeded_count += 1;
                     
-                    if current > current_threshold {
-                        threshold_exceeded_count += 1;
-                        
-                        if threshold_exceeded_count >= 3 {
-                            for _ in 0..3 { 
-                                tokio::time::sleep(Duration::from_millis(10)).await;
-                                servo.set_speed(servo_id, 0, direction).unwrap();
-                            }
-                            tokio::time::sleep(Duration::from_millis(100)).await;
-
-                            servo.set_speed(servo_id, calibration_speed, opposite_direction(direction)).unwrap();
-                            tokio::time::sleep(Duration::from_millis(350)).await;
-
-                            servo.set_speed(servo_id, 0, opposite_direction(direction)).unwrap();
-                            tokio::time::sleep(Duration::from_millis(100)).await;
-
-                            let info = servo.read_info(servo_id).unwrap();
-
-                            if direction == ServoDirection::Clockwise {
-                                max_forward = info.current_location;
-                            } else {
-                                max_backward = info.current_location;
-                            }
-
-                            break;
+                    if threshold_exceeded_count >= 3 {
+                        for _ in 0..3 { 
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            servo.diagnostic(move |s| s.set_speed(servo_id, 0, direction)).await?;
                         }
-                    } else {
-                        threshold_exceeded_count = 0;
-                    }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
 
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                }
+                        servo.diagnostic(move |s| s.set_speed(servo_id, calibration_speed, opposite_direction(direction))).await?;
+                        tokio::time::sleep(Duration::from_millis(350)).await;
+
+                        servo.diagnostic(move |s| s.set_speed(servo_id, 0, opposite_direction(direction))).await?;
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+
+                        let info = servo.diagnostic(move |s| s.read_info(servo_id)).await?;
 
-                if pass < 1 {
-                    tokio::time::sleep(Duration::from_millis(500)).await;
+                        if direction == ServoDirection::Clockwise {
+                            max_forward = info.current_location;
+                        } else {
+                            max_backward = info.current_location;
+                        }
+
+                        break;
+                    }
+                } else {
+                    threshold_exceeded_count = 0;
                 }
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
             }
 
-            servo.write(servo_id, ServoRegister::LockMark, &[0]).unwrap();
-            servo.set_speed(servo_id, 0, ServoDirection::Clockwise).unwrap();
-            servo.write_servo_memory(servo_id, ServoRegister::TorqueLimit, 600).unwrap();
-            servo.set_mode(servo_id, ServoMode::Position).unwrap();
-            servo.write(servo_id, ServoRegister::LockMark, &[1]).unwrap();
+            if pass < 1 {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        }
 
-            servo.enable_readout().unwrap();
-            *calibrating_servo.lock().await = None;
-            calibration_running.store(false, Ordering::SeqCst);
-            Self::calculate_and_write_calibration(servo_id, &servo, max_backward, max_forward).await.unwrap();
-        });
+        Self::end_calibration(servo_id, servo).await?;
 
-        Ok(())
+        Ok(Some((max_backward, max_forward)))
+    }
+
+    // Undoes what find_limits set up, however it ended: the servo gets its torque back in
+    // position mode, and readout and movement are on again. Every step is tried.
+    async fn end_calibration(servo_id: u8, servo: &AsyncServo<S>) -> Result<()> {
+        let results = [
+            servo.control(move |s| s.write_servo_memory(servo_id, ServoRegister::TorqueLimit, 600)).await,
+            servo.control(move |s| s.switch_mode(servo_id, ServoMode::Position)).await,
+            servo.control(|s| s.enable_readout()).await,
+            servo.control(|s| s.enable_movement()).await,
+        ];
+        results.into_iter().collect()
     }
 
-    async fn calculate_and_write_calibration(servo_id: u8, servo: &Servo, min_pos: i16, max_pos: i16) -> Result<(), Status> {
+    async fn calculate_and_write_calibration(servo_id: u8, servo: &AsyncServo<S>, min_pos: i16, max_pos: i16) -> Result<(), Status> {
         let mut max_pos = max_pos;
+        let resolution = servo.bus().profile(servo_id).resolution as i16;
+        let center = resolution / 2;
 
         if max_pos < min_pos {
-            max_pos += 4096;
+            max_pos += resolution;
         }
     
-        let offset_value = min_pos + (max_pos - min_pos) / 2 - 2048;
+        let offset_value = min_pos + (max_pos - min_pos) / 2 - center;
     
-        // Convert offset to 12-bit signed value
-        let offset_value = if offset_value < 0 {
-            offset_value.abs() as u16 | 0x800 // (set negative bit)
-        } else {
-            if offset_value > 2048 {
-                (offset_value - 4096).abs() as u16 | 0x800
-            } else {
-                offset_value as u16
-            }
-        };
+        // PositionCorrection is signed, wrap to the shorter way round
+        let offset_value = if offset_value > center { offset_value - resolution } else { offset_value };
     
         // Calculate new limits
-        let min_angle = 2048 - (max_pos - min_pos) / 2;
-        let max_angle = 2048 + (max_pos - min_pos) / 2;
+        let min_angle = center - (max_pos - min_pos) / 2;
+        let max_angle = center + (max_pos - min_pos) / 2;
 
         println!("Writing calibration, offset: {}, min_angle: {}, max_angle: {}", offset_value, min_angle, max_angle);
 
         // Unlock EEPROM
-        servo.write(servo_id, ServoRegister::LockMark, &[0])
-            .map_err(|e| Status::internal(format!("Failed to unlock EEPROM: {}", e)))?;
-        tokio::time::sleep(Duration::from_millis(20)).await;
-
-        // Write new offset
-        servo.write_servo_memory(servo_id, ServoRegister::PositionCorrection, offset_value)
-            .map_err(|e| Status::internal(format!("Failed to write offset: {}", e)))?;
-        tokio::time::sleep(Duration::from_millis(20)).await;
-
-        // Write new limits
-        for _ in 0..3 {
-            servo.write_servo_memory(servo_id, ServoRegister::MinAngleLimit, min_angle as u16)
-                .map_err(|e| Status::internal(format!("Failed to write MinAngleLimit: {}", e)))?;
-            tokio::time::sleep(Duration::from_millis(20)).await;
-            let read_min = servo.read(servo_id, ServoRegister::MinAngleLimit, 2)
-                .map_err(|e| Status::internal(format!("Failed to read MinAngleLimit: {}", e)))?;
-            let read_min = u16::from_le_bytes([read_min[0], read_min[1]]);
-            if read_min == min_angle as u16 {
-                break;
-            }
-        }
-
-        for _ in 0..3 {
-            servo.write_servo_memory(servo_id, ServoRegister::MaxAngleLimit, max_angle as u16)
-                .map_err(|e| Status::internal(format!("Failed to write MaxAngleLimit: {}", e)))?;
-            tokio::time::sleep(Duration::from_millis(20)).await;
-            let read_max = servo.read(servo_id, ServoRegister::MaxAngleLimit, 2)
-                .map_err(|e| Status::internal(format!("Failed to read MaxAngleLimit: {}", e)))?;
-            let read_max = u16::from_le_bytes([read_max[0], read_max[1]]);
-            if read_max == max_angle as u16 {
-                break;
-            }
+        servo.diagnostic(move |s| s.write(servo_id, ServoRegister::LockMark, &[0])).await
+            .map_err(|e| servo_status(e.context("Failed to unlock EEPROM")))?;
+
+        // Write new offset and limits; the HAL reads each one back and retries it
+        for (register, value, name) in [
+            (ServoRegister::PositionCorrection, offset_value, "offset"),
+            (ServoRegister::MinAngleLimit, min_angle, "MinAngleLimit"),
+            (ServoRegister::MaxAngleLimit, max_angle, "MaxAngleLimit"),
+        ] {
+            servo.diagnostic(move |s| s.write_reg(servo_id, register, value)).await
+                .map_err(|e| servo_status(e.context(format!("Failed to write {}", name))))?;
         }
 
         // Lock EEPROM
-        servo.write(servo_id, ServoRegister::LockMark, &[1])
-            .map_err(|e| Status::internal(format!("Failed to lock EEPROM: {}", e)))?;
+        servo.diagnostic(move |s| s.write(servo_id, ServoRegister::LockMark, &[1])).await
+            .map_err(|e| servo_status(e.context("Failed to lock EEPROM")))?;
 
         Ok(())
     }
@@ -306,36 +332,32 @@ impl StsServoControl {
 }
 
 #[tonic::async_trait]
-impl ServoControl for StsServoControl {
+impl<S: ServoBus + 'static, I: ImuSource + 'static> ServoControl for StsServoControl<S, I> {
     async fn get_positions(&self, _request: Request<Empty>) -> Result<Response<JointPositions>, Status> {
-        let servo: tokio::sync::MutexGuard<'_, Servo> = self.servo.lock().await;
-        let servo_data = servo.read_continuous().map_err(|e| Status::internal(e.to_string()))?;
+        let servo_data = self.servo.read_continuous().await.map_err(servo_status)?;
+        let servo = self.servo.bus();
         
         // Update last_positions
         *self.last_positions.lock().await = servo_data.clone();
         
         let positions = JointPositions {
-            positions: servo_data.servo.iter()
-                .take(MAX_SERVOS)
-                .enumerate()
-                .map(|(id, info)| servo_control::JointPosition {
-                    id: (id + 1) as i32, // Add 1 to make IDs start from 1
-                    position: Servo::raw_to_degrees(info.current_location as u16),
-                    speed: {
-                        let speed_raw = info.current_speed as u16;
-                        let speed_magnitude = speed_raw & 0x7FFF; // Remove 15th bit
-                        let speed_sign = if speed_raw & 0x8000 != 0 { -1.0 } else { 1.0 };
-                        speed_sign * (speed_magnitude as f32 * 360.0 / 4096.0)
-                    },
+            positions: servo_data.servos.iter()
+                .map(|(&id, info)| servo_control::JointPosition {
+                    id: id as i32,
+                    position: servo.units(id).position(info.current_location).degrees(),
+                    speed: servo.units(id).velocity(info.current_speed).degrees_per_second(),
+                    acceleration: 0.0,
                 })
                 .collect(),
+            duration_ms: 0,
+            profile: String::new(),
         };
         Ok(Response::new(positions))
     }
 
     async fn set_positions(&self, request: Request<JointPositions>) -> Result<Response<Empty>, Status> {
         let positions = request.into_inner();
-        let servo = self.servo.lock().await;
+        let servo = self.servo.bus();
         let mut last_positions = self.last_positions.lock().await;
         let calibration_running = self.calibration_running.clone();
 
@@ -343,32 +365,73 @@ impl ServoControl for StsServoControl {
             return Err(Status::internal("Calibration is in progress"));
         }
         
-        let mut cmd = ServoMultipleWriteCommand {
-            only_write_positions: 1,
-            ids: [0; MAX_SERVOS],
-            positions: [0; MAX_SERVOS],
-            times: [0; MAX_SERVOS],
-            speeds: [0; MAX_SERVOS],
-        };
+        if let Some(p) = positions.positions.iter().find(|p| p.id < 1 || p.id > MAX_SERVO_ID as i32) {
+            return Err(Status::invalid_argument(format!("Invalid servo ID {}", p.id)));
+        }
 
-        for i in 0..MAX_SERVOS {
-            let servo_id = (i + 1) as u32; // Add 1 because IDs start from 1
-            let position = positions.positions.iter()
-                .find(|p| p.id == servo_id as i32)
-                .map(|p| p.position)
-                .unwrap_or_else(|| Servo::raw_to_degrees(last_positions.servo[i].current_location as u16));
+        // A synchronized move works out its own speeds and accelerations
+        if positions.duration_ms > 0 && (!positions.profile.is_empty() || positions.positions.iter().any(|p| p.acceleration > 0.0)) {
+            return Err(Status::invalid_argument("duration_ms can't be combined with a profile or per-joint accelerations"));
+        }
 
-            cmd.ids[i] = servo_id as u8;
-            cmd.positions[i] = Servo::degrees_to_raw(position) as i16;
-            // You can set times and speeds here if needed
-            cmd.times[i] = 0;
-            cmd.speeds[i] = 0;
+        if positions.duration_ms > 0 {
+            let mut goals = Vec::with_capacity(positions.positions.len());
+            for p in &positions.positions {
+                let id = p.id as u8;
+                let position = servo.units(id).position_raw(Angle::from_degrees(p.position));
+                goals.push((id, position));
+                last_positions.servos.entry(id).or_default().current_location = position;
+            }
+            self.servo.move_synchronized(goals, Duration::from_millis(positions.duration_ms as u64)).await
+                .map_err(servo_status)?;
+            return Ok(Response::new(Empty {}));
+        }
 
-            last_positions.servo[i].current_location = Servo::degrees_to_raw(position) as i16;
+        let profile = match positions.profile.as_str() {
+            "" => None,
+            name => Some(self.motion_profiles.get(name).map_err(|e| servo_status(e.into()))?),
+        };
+        // Servos without a requested position are held where they were last seen
+        let mut ids = servo.ids();
+        ids.extend(positions.positions.iter().map(|p| p.id as u8));
+        ids.sort_unstable();
+        ids.dedup();
+
+        // Speeds and accelerations are only sent for joints that asked for them, the rest
+        // keep theirs and plain positions stream faster
+        let mut plain = Vec::with_capacity(ids.len());
+        let mut paced = Vec::new();
+        for id in ids {
+            let units = servo.units(id);
+            let requested = positions.positions.iter().find(|p| p.id == id as i32);
+            let last = last_positions.get(id).map(|info| units.position(info.current_location).degrees());
+            let Some(position) = requested.map(|p| p.position).or(last) else { continue };
+
+            let position = units.position_raw(Angle::from_degrees(position));
+            last_positions.servos.entry(id).or_default().current_location = position;
+            let Some(p) = requested else {
+                plain.push(ServoTarget { id, position, ..Default::default() });
+                continue;
+            };
+
+            let mut target = match profile {
+                Some(profile) => profile.target(&units, id, position),
+                None => ServoTarget { id, position, ..Default::default() },
+            };
+            if p.acceleration > 0.0 {
+                target.acceleration = units.acceleration_raw(AngularAcceleration::from_degrees_per_second_squared(p.acceleration));
+            }
+            if profile.is_some() || p.acceleration > 0.0 {
+                paced.push(target);
+            } else {
+                plain.push(target);
+            }
         }
 
-        servo.write_multiple(&cmd)
-            .map_err(|e| Status::internal(e.to_string()))?;
+        for cmd in position_writes(plain, paced) {
+            self.servo.write_multiple(cmd).await
+                .map_err(servo_status)?;
+        }
         
         Ok(Response::new(Empty {}))
     }
@@ -407,11 +470,11 @@ network={{
     }
 
     async fn scan(&self, _request: Request<Empty>) -> Result<Response<ServoIds>, Status> {
-        let servo = self.servo.lock().await;
         let mut ids = Vec::new();
         
+        // One ID at a time, so control commands can run in between
         for id in 0..100 as u8 {
-            if servo.scan(id).map_err(|e| Status::internal(e.to_string()))? {
+            if self.servo.scan(id).await.map_err(servo_status)? {
                 ids.push(id as u32);
             }
         }
@@ -421,40 +484,71 @@ network={{
 
     async fn get_servo_info(&self, request: Request<ServoId>) -> Result<Response<ServoInfoResponse>, Status> {
         let id = request.into_inner().id as u8;
-        let servo = self.servo.lock().await;
-        
-        let servo_info = servo.read_info(id).map_err(|e| Status::internal(e.to_string()))?;
-        let (min_position, max_position) = servo.read_angle_limits(id).map_err(|e| Status::internal(e.to_string()))?;
-        let min_position = Servo::raw_to_degrees(min_position as u16);
-        let max_position = Servo::raw_to_degrees(max_position as u16);
-        
-        // Handle speed conversion
-        let speed_raw = servo_info.current_speed as u16;
-        let speed_magnitude = speed_raw & 0x7FFF; // Remove 15th bit
-        let speed_sign = if speed_raw & 0x8000 != 0 { -1.0 } else { 1.0 };
-        let speed = speed_sign * (speed_magnitude as f32 * 360.0 / 4096.0);
+        let (servo_info, (min_position, max_position)) = self.servo
+            .diagnostic(move |servo| Ok((servo.read_info(id)?, servo.read_angle_limits(id)?)))
+            .await
+            .map_err(servo_status)?;
+        let units = self.servo.bus().units(id);
+        let readout = units.readout(&servo_info);
 
         let info = ServoInfo {
             id: id as i32,
-            temperature: servo_info.current_temperature as f32,
-            current: servo_info.current_current as f32,
-            voltage: ((servo_info.current_voltage as f32 / 10.0) * 10.0).round() / 10.0,
-            speed,
-            current_position: Servo::raw_to_degrees(servo_info.current_location as u16),
-            min_position: min_position as f32,
-            max_position: max_position as f32,
+            temperature: readout.temperature.celsius(),
+            current: readout.current.map_or(0.0, Current::amps),
+            voltage: readout.voltage.volts(),
+            speed: readout.velocity.degrees_per_second(),
+            current_position: readout.position.degrees(),
+            min_position: units.position(min_position).degrees(),
+            max_position: units.position(max_position).degrees(),
+            faults: fault_names(servo_info.faults()),
         };
         Ok(Response::new(ServoInfoResponse {
             result: Some(servo_info_response::Result::Info(info)),
         }))
     }
 
+    async fn get_servo_faults(&self, request: Request<ServoId>) -> Result<Response<ServoFaultStatus>, Status> {
+        let id = request.into_inner().id;
+        let record = self.servo.bus().faults(id as u8).unwrap_or_default();
+
+        Ok(Response::new(ServoFaultStatus {
+            id,
+            active: fault_names(record.active),
+            latched: fault_names(record.latched),
+            history: record.history.iter().map(fault_event).collect(),
+        }))
+    }
+
+    async fn clear_servo_faults(&self, request: Request<ServoId>) -> Result<Response<Empty>, Status> {
+        let id = request.into_inner().id as u8;
+        self.servo.bus().clear_faults(id);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_bus_stats(&self, _request: Request<Empty>) -> Result<Response<BusStats>, Status> {
+        let stats = self.servo.diagnostic(|s| Ok(s.bus_stats())).await
+            .map_err(|e| servo_status(e.context("Failed to read bus statistics")))?;
+
+        Ok(Response::new(BusStats {
+            servos: stats.servos.iter().map(|(&id, stats)| servo_bus_stats(id, stats)).collect(),
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            latency_bounds_us: LATENCY_BUCKETS_US.to_vec(),
+        }))
+    }
+
+    async fn clear_bus_stats(&self, _request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        self.servo.diagnostic(|s| { s.clear_bus_stats(); Ok(()) }).await
+            .map_err(|e| servo_status(e.context("Failed to clear bus statistics")))?;
+        Ok(Response::new(Empty {}))
+    }
+
     async fn change_id(&self, request: Request<IdChange>) -> Result<Response<ChangeIdResponse>, Status> {
         let id_change = request.into_inner();
-        let servo = self.servo.lock().await;
+        let (old_id, new_id) = (id_change.old_id as u8, id_change.new_id as u8);
         
         // First, check if the new ID is already in use
-        if servo.scan(id_change.new_id as u8).map_err(|e| Status::internal(e.to_string()))? {
+        if self.servo.scan(new_id).await.map_err(servo_status)? {
             return Ok(Response::new(ChangeIdResponse {
                 result: Some(change_id_response::Result::Error(servo_control::ErrorInfo {
                     message: "New ID is already in use".to_string(),
@@ -464,15 +558,15 @@ network={{
         }
 
         // Change the ID    
-        servo.write(id_change.old_id as u8, ServoRegister::LockMark, &[0])
-            .map_err(|e| Status::internal(e.to_string()))?;
-        servo.write(id_change.old_id as u8, runtime::hal::ServoRegister::ID, &[id_change.new_id as u8])
-            .map_err(|e| Status::internal(e.to_string()))?;
-        servo.write(id_change.old_id as u8, ServoRegister::LockMark, &[1])
-            .map_err(|e| Status::internal(e.to_string()))?;
+        self.servo.diagnostic(move |servo| {
+            servo.write(old_id, ServoRegister::LockMark, &[0])?;
+            servo.write(old_id, runtime::hal::ServoRegister::ID, &[new_id])?;
+            // The servo only answers to its new ID from here on
+            servo.write(new_id, ServoRegister::LockMark, &[1])
+        }).await.map_err(servo_status)?;
         
         // Verify the change
-        if servo.scan(id_change.new_id as u8).map_err(|e| Status::internal(e.to_string()))? {
+        if self.servo.scan(new_id).await.map_err(servo_status)? {
             Ok(Response::new(ChangeIdResponse {
                 result: Some(change_id_response::Result::Success(true)),
             }))
@@ -609,38 +703,42 @@ network={{
 
     async fn set_torque(&self, request: Request<TorqueSettings>) -> Result<Response<Empty>, Status> {
         let torque_settings = request.into_inner();
-        let servo = self.servo.lock().await;
 
-        for setting in torque_settings.settings {
-            let torque_value = (setting.torque * 10.0) as u16; // Convert 0-100% to 0-1000
-            servo.write_servo_memory(setting.id as u8, ServoRegister::TorqueLimit, torque_value)
-                .map_err(|e| Status::internal(format!("Failed to set torque for servo {}: {}", setting.id, e)))?;
-        }
+        self.servo.control(move |servo| {
+            for setting in torque_settings.settings {
+                let torque_value = (setting.torque * 10.0) as u16; // Convert 0-100% to 0-1000
+                servo.write_servo_memory(setting.id as u8, ServoRegister::TorqueLimit, torque_value)
+                    .map_err(|e| e.context(format!("Failed to set torque for servo {}", setting.id)))?;
+            }
+            Ok(())
+        }).await.map_err(servo_status)?;
 
         Ok(Response::new(Empty {}))
     }
 
     async fn set_torque_enable(&self, request: Request<TorqueEnableSettings>) -> Result<Response<Empty>, Status> {
         let torque_enable_settings = request.into_inner();
-        let servo = self.servo.lock().await;
 
-        for setting in torque_enable_settings.settings {
-            let torque_mode = if setting.enable {
-                TorqueMode::Enabled
-            } else {
-                TorqueMode::Disabled
-            };
-            servo.set_torque_mode(setting.id as u8, torque_mode)
-                .map_err(|e| Status::internal(format!("Failed to set torque enable for servo {}: {}", setting.id, e)))?;
-        }
+        self.servo.control(move |servo| {
+            for setting in torque_enable_settings.settings {
+                let torque_mode = if setting.enable {
+                    TorqueMode::Enabled
+                } else {
+                    TorqueMode::Disabled
+                };
+                servo.set_torque_mode(setting.id as u8, torque_mode)
+                    .map_err(|e| e.context(format!("Failed to set torque enable for servo {}", setting.id)))?;
+            }
+            Ok(())
+        }).await.map_err(servo_status)?;
 
         Ok(Response::new(Empty {}))
     }
 
     async fn get_imu_data(&self, _request: Request<Empty>) -> Result<Response<ImuData>, Status> {
-        let mut imu = self.imu.lock().await;
+        let imu = self.imu.lock().await;
         
-        let imu_data = match imu.as_mut() {
+        let imu_data = match imu.as_ref() {
             Some(imu) => imu.read_data()
                 .map_err(|e| Status::internal(format!("Failed to read IMU data: {}", e)))?,
             None => return Err(Status::unavailable("IMU is not available")),
@@ -795,41 +893,82 @@ network={{
     }
 
     async fn enable_movement(&self, _request: Request<Empty>) -> Result<Response<Empty>, Status> {
-        let servo = self.servo.lock().await;
         if self.calibration_running.load(Ordering::SeqCst) {
             return Err(Status::internal("Calibration is running, cannot enable movement"));
         }   
-        servo.enable_movement()
-            .map_err(|e| Status::internal(format!("Failed to enable movement: {}", e)))?;
+        self.servo.control(|servo| servo.enable_movement()).await
+            .map_err(|e| servo_status(e.context("Failed to enable movement")))?;
 
         Ok(Response::new(Empty {}))
     }
 
     async fn disable_movement(&self, _request: Request<Empty>) -> Result<Response<Empty>, Status> {
-        let servo = self.servo.lock().await;
-        servo.disable_movement()
-            .map_err(|e| Status::internal(format!("Failed to disable movement: {}", e)))?;
+        self.servo.control(|servo| servo.disable_movement()).await
+            .map_err(|e| servo_status(e.context("Failed to disable movement")))?;
 
         Ok(Response::new(Empty {}))
     }
 
     async fn set_position(&self, request: Request<servo_control::JointPosition>) -> Result<Response<Empty>, Status> {
         let position = request.into_inner();
-        let servo = self.servo.lock().await;
-        
-        // Convert degrees to raw value
-        let raw_position = Servo::degrees_to_raw(position.position);
         
-        // Convert speed to raw value (assuming speed is in degrees/second)
-        let speed = (position.speed.abs() * 4096.0 / 360.0) as u16;
+        let units = self.servo.bus().units(position.id as u8);
+        let raw_position = units.position_raw(Angle::from_degrees(position.position));
+        let speed = units.velocity_raw(AngularVelocity::from_degrees_per_second(position.speed)).unsigned_abs();
 
-        servo.move_servo(position.id as u8, raw_position as i16, 0, speed)
-            .map_err(|e| Status::internal(format!("Failed to set position: {}", e)))?;
+        let result = if position.acceleration > 0.0 {
+            let acceleration = units.acceleration_raw(AngularAcceleration::from_degrees_per_second_squared(position.acceleration));
+            self.servo.move_servo_with_acceleration(position.id as u8, raw_position, 0, speed, acceleration).await
+        } else {
+            self.servo.move_servo(position.id as u8, raw_position, 0, speed).await
+        };
+        result.map_err(|e| servo_status(e.context("Failed to set position")))?;
 
         Ok(Response::new(Empty {}))
     }
 }
 
+// Lets clients tell a missing servo from a bus fault or a servo-reported error
+fn servo_status(e: anyhow::Error) -> Status {
+    let message = format!("{:#}", e);
+    match e.downcast_ref::<ServoError>() {
+        Some(ServoError::Timeout { .. }) => Status::unavailable(message),
+        Some(ServoError::Status { .. }) => Status::failed_precondition(message),
+        Some(ServoError::InvalidInput(_)) => Status::invalid_argument(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn fault_names(faults: ServoFaults) -> Vec<String> {
+    faults.names().into_iter().map(str::to_string).collect()
+}
+
+fn fault_event(event: &FaultEvent) -> servo_control::FaultEvent {
+    servo_control::FaultEvent {
+        timestamp: event.time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64),
+        raised: fault_names(event.raised),
+        cleared: fault_names(event.cleared),
+    }
+}
+
+fn servo_bus_stats(id: u8, stats: &ServoStats) -> ServoBusStats {
+    ServoBusStats {
+        id: id as i32,
+        transactions: stats.transactions,
+        timeouts: stats.timeouts,
+        checksum_errors: stats.checksum_errors,
+        id_mismatches: stats.id_mismatches,
+        truncated: stats.truncated,
+        bytes_sent: stats.bytes_sent,
+        bytes_received: stats.bytes_received,
+        latency_buckets: stats.latency.buckets.to_vec(),
+        latency_total_us: stats.latency.total_us,
+        latency_max_us: stats.latency.max_us,
+        retries: stats.retries,
+        verify_failures: stats.verify_failures,
+    }
+}
+
 fn opposite_direction(direction: ServoDirection) -> ServoDirection {
     match direction {
         ServoDirection::Clockwise => ServoDirection::Counterclockwise,
@@ -841,6 +980,7 @@ fn opposite_direction(direction: ServoDirection) -> ServoDirection {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "0.0.0.0:50051".parse()?;
     let servo_control = StsServoControl::new()?;
+    servo_control.spawn_fault_monitor();
 
     let service = tower::ServiceBuilder::new()
         .layer(tonic_web::GrpcWebLayer::new())
@@ -1,21 +1,27 @@
 use anyhow::Result;
-use runtime::hal::{Servo, ServoMultipleWriteCommand, MAX_SERVOS};
+use runtime::hal::{AsyncServo, Servo, ServoBus, ServoMultipleWriteCommand, ServoTarget};
 use tokio::time::{sleep, interval, Duration};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use crate::model::Model;
 use ndarray::Array1;
 
-pub struct Robot{
+pub struct Robot<S: ServoBus = Servo> {
 
-    servo: Servo,
+    servo: AsyncServo<S>,
 }
 
-impl Robot{
+impl Robot {
     pub fn new() -> Result<Self> {
         let servo = Servo::new()?;
 
-        Ok(Self { servo })
+        Self::with_servo(servo)
+    }
+}
+
+impl<S: ServoBus + 'static> Robot<S> {
+    pub fn with_servo(servo: S) -> Result<Self> {
+        Ok(Self { servo: AsyncServo::new(servo)? })
     }
 
     pub async fn run(&self, model: Arc<Model>) -> Result<()> {
@@ -36,13 +42,11 @@ impl Robot{
     }
 
     async fn get_joint_states(&self) -> Result<[f32; 16]> {
-        let servo_data = self.servo.read_continuous()?;
-        let joint_states: [f32; 16] = servo_data.servo.iter()
-            .take(16)
-            .map(|s| s.target_location as f32)
-            .collect::<Vec<f32>>()
-            .try_into()
-            .unwrap_or([0.0; 16]);
+        let servo_data = self.servo.read_continuous().await?;
+        // Joint i is driven by servo i + 1
+        let joint_states: [f32; 16] = std::array::from_fn(|i| {
+            servo_data.get(i as u8 + 1).map_or(0.0, |s| s.target_location as f32)
+        });
         Ok(joint_states)
     }
 
@@ -74,21 +78,16 @@ impl Robot{
     }
 
     async fn send_joint_commands(&self, positions: &[f32; 16]) -> Result<()> {
-        let mut cmd = ServoMultipleWriteCommand {
-            ids: [0; MAX_SERVOS],
-            positions: [0; MAX_SERVOS],
-            times: [0; MAX_SERVOS],
-            speeds: [0; MAX_SERVOS],
-            only_write_positions: 0,
+        let cmd = ServoMultipleWriteCommand {
+            only_write_positions: false,
+            write_accelerations: false,
+            targets: positions.iter()
+                .enumerate()
+                .map(|(i, &position)| ServoTarget { id: i as u8 + 1, position: position as i16, time: 20, ..Default::default() })
+                .collect(),
         };
 
-        for i in 0..16 {
-            cmd.ids[i] = (i + 1) as u8;
-            cmd.positions[i] = positions[i] as i16;
-            cmd.times[i] = 20;
-        }
-
-        self.servo.write_multiple(&cmd)?;
+        self.servo.write_multiple(cmd).await?;
 
         println!("Command sent to move all servos to position {} with time {} ms and speed {}, send_only_positions: {}", positions[0] as i16, 20, 0, 0);
         Ok(())
@@ -96,9 +95,9 @@ impl Robot{
 }
 
 #[tokio::main]
-pub async fn run(model: Arc<Model>, robot: Arc<Robot>) -> Result<()> {
+pub async fn run<S: ServoBus + 'static>(model: Arc<Model>, robot: Arc<Robot<S>>) -> Result<()> {
 
-    robot.servo.enable_readout()?;  
+    robot.servo.control(|servo| servo.enable_readout()).await?;  
 
     robot.run(model).await?;
 
@@ -0,0 +1,286 @@
+use anyhow::{Result, anyhow, Context};
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use crate::hal::{Servo, ServoBus, ServoData, ServoInfo, ServoMultipleWriteCommand};
+
+/// Which queue a bus operation waits in. Control operations always run before any
+/// queued diagnostic one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Motion and torque commands and the readout that feeds them.
+    Control,
+    /// Scans, servo info, calibration, configuration.
+    Diagnostic,
+}
+
+type Job<S> = Box<dyn FnOnce(&S) + Send>;
+
+struct Queue<S> {
+    jobs: Mutex<Jobs<S>>,
+    ready: Condvar,
+}
+
+struct Jobs<S> {
+    control: VecDeque<Job<S>>,
+    diagnostic: VecDeque<Job<S>>,
+    closed: bool,
+}
+
+impl<S> Jobs<S> {
+    fn get(&mut self, priority: Priority) -> &mut VecDeque<Job<S>> {
+        match priority {
+            Priority::Control => &mut self.control,
+            Priority::Diagnostic => &mut self.diagnostic,
+        }
+    }
+}
+
+// Stops the bus thread once the last handle is gone
+struct Owner<S> {
+    queue: Arc<Queue<S>>,
+    bus: Arc<S>,
+}
+
+impl<S> Drop for Owner<S> {
+    fn drop(&mut self) {
+        self.queue.jobs.lock().unwrap().closed = true;
+        self.queue.ready.notify_all();
+    }
+}
+
+/// Non-blocking front end for async code: one thread owns the bus and runs queued
+/// operations in priority order, and callers await the results.
+///
+/// An operation whose future is dropped before it starts is skipped, and
+/// [`cancel`](Self::cancel) drops everything still waiting in a queue. Operations that
+/// have started run to completion, so long procedures like calibration should be
+/// queued as many short steps.
+pub struct AsyncServo<S: ServoBus = Servo> {
+    owner: Arc<Owner<S>>,
+}
+
+impl<S: ServoBus> Clone for AsyncServo<S> {
+    fn clone(&self) -> Self {
+        AsyncServo { owner: self.owner.clone() }
+    }
+}
+
+impl<S: ServoBus> fmt::Debug for AsyncServo<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncServo").field("bus", &self.owner.bus).finish()
+    }
+}
+
+impl<S: ServoBus + 'static> AsyncServo<S> {
+    pub fn new(bus: S) -> Result<Self> {
+        let queue = Arc::new(Queue {
+            jobs: Mutex::new(Jobs { control: VecDeque::new(), diagnostic: VecDeque::new(), closed: false }),
+            ready: Condvar::new(),
+        });
+        let bus = Arc::new(bus);
+
+        thread::Builder::new()
+            .name("servo-bus".to_string())
+            .spawn({
+                let queue = queue.clone();
+                let bus = bus.clone();
+                move || run(&queue, bus.as_ref())
+            })
+            .context("Failed to start servo bus thread")?;
+
+        Ok(AsyncServo { owner: Arc::new(Owner { queue, bus }) })
+    }
+
+    /// The underlying bus, for calls that don't touch the wire such as `profile`,
+    /// `faults` or `ids`. Anything that does should be queued with [`call`](Self::call).
+    pub fn bus(&self) -> &S {
+        &self.owner.bus
+    }
+
+    /// Queues `f` to run on the bus thread and waits for its result.
+    pub async fn call<T, F>(&self, priority: Priority, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&S) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job<S> = Box::new(move |bus| {
+            // The caller stopped waiting
+            if tx.is_closed() {
+                return;
+            }
+            let _ = tx.send(f(bus));
+        });
+
+        let queue = &self.owner.queue;
+        queue.jobs.lock().unwrap().get(priority).push_back(job);
+        queue.ready.notify_one();
+
+        rx.await.map_err(|_| anyhow!("Servo command was cancelled"))?
+    }
+
+    pub async fn control<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&S) -> Result<T> + Send + 'static,
+    {
+        self.call(Priority::Control, f).await
+    }
+
+    pub async fn diagnostic<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&S) -> Result<T> + Send + 'static,
+    {
+        self.call(Priority::Diagnostic, f).await
+    }
+
+    /// Drops every operation still waiting at `priority`; their callers get an error.
+    /// Returns how many were dropped.
+    pub fn cancel(&self, priority: Priority) -> usize {
+        let cancelled = std::mem::take(self.owner.queue.jobs.lock().unwrap().get(priority));
+        cancelled.len()
+    }
+
+    pub async fn read_continuous(&self) -> Result<ServoData> {
+        self.control(|bus| bus.read_continuous()).await
+    }
+
+    pub async fn write_multiple(&self, cmd: ServoMultipleWriteCommand) -> Result<()> {
+        self.control(move |bus| bus.write_multiple(&cmd)).await
+    }
+
+    pub async fn move_synchronized(&self, goals: Vec<(u8, i16)>, duration: Duration) -> Result<Duration> {
+        self.control(move |bus| bus.move_synchronized(&goals, duration)).await
+    }
+
+    pub async fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
+        self.control(move |bus| bus.move_servo(id, position, time, speed)).await
+    }
+
+    pub async fn move_servo_with_acceleration(&self, id: u8, position: i16, time: u16, speed: u16, acceleration: u8) -> Result<()> {
+        self.control(move |bus| bus.move_servo_with_acceleration(id, position, time, speed, acceleration)).await
+    }
+
+    pub async fn read_info(&self, id: u8) -> Result<ServoInfo> {
+        self.diagnostic(move |bus| bus.read_info(id)).await
+    }
+
+    pub async fn scan(&self, id: u8) -> Result<bool> {
+        self.diagnostic(move |bus| bus.scan(id)).await
+    }
+}
+
+fn run<S>(queue: &Queue<S>, bus: &S) {
+    loop {
+        let job = {
+            let mut jobs = queue.jobs.lock().unwrap();
+            loop {
+                if let Some(job) = jobs.control.pop_front().or_else(|| jobs.diagnostic.pop_front()) {
+                    break job;
+                }
+                if jobs.closed {
+                    return;
+                }
+                jobs = queue.ready.wait(jobs).unwrap();
+            }
+        };
+
+        // A panicking operation fails its own caller, not the bus
+        if panic::catch_unwind(AssertUnwindSafe(|| job(bus))).is_err() {
+            eprintln!("Servo bus operation panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use crate::hal_sim;
+
+    type SimServo = AsyncServo<hal_sim::Servo>;
+
+    // Occupies the bus thread until the returned sender is dropped or sent to
+    async fn block(servo: &SimServo) -> mpsc::Sender<()> {
+        let (started_tx, started_rx) = oneshot::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let blocker = servo.clone();
+        tokio::spawn(async move {
+            blocker.control(move |_| {
+                let _ = started_tx.send(());
+                let _ = release_rx.recv();
+                Ok(())
+            }).await
+        });
+        started_rx.await.unwrap();
+        release_tx
+    }
+
+    async fn wait_queued(servo: &SimServo, priority: Priority, count: usize) {
+        while servo.owner.queue.jobs.lock().unwrap().get(priority).len() < count {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_control_before_earlier_diagnostics() {
+        let servo = SimServo::new(hal_sim::Servo::new([1])).unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let release = block(&servo).await;
+
+        let diagnostic = tokio::spawn({
+            let (servo, order) = (servo.clone(), order.clone());
+            async move { servo.diagnostic(move |_| {
+                order.lock().unwrap().push(Priority::Diagnostic);
+                Ok(())
+            }).await }
+        });
+        wait_queued(&servo, Priority::Diagnostic, 1).await;
+        let control = tokio::spawn({
+            let (servo, order) = (servo.clone(), order.clone());
+            async move { servo.control(move |_| {
+                order.lock().unwrap().push(Priority::Control);
+                Ok(())
+            }).await }
+        });
+        wait_queued(&servo, Priority::Control, 1).await;
+
+        release.send(()).unwrap();
+        diagnostic.await.unwrap().unwrap();
+        control.await.unwrap().unwrap();
+        assert_eq!(*order.lock().unwrap(), [Priority::Control, Priority::Diagnostic]);
+    }
+
+    #[tokio::test]
+    async fn fails_cancelled_callers() {
+        let servo = SimServo::new(hal_sim::Servo::new([1])).unwrap();
+        let release = block(&servo).await;
+
+        let diagnostic = tokio::spawn({
+            let servo = servo.clone();
+            async move { servo.read_info(1).await }
+        });
+        wait_queued(&servo, Priority::Diagnostic, 1).await;
+        assert_eq!(servo.cancel(Priority::Control), 0);
+        assert_eq!(servo.cancel(Priority::Diagnostic), 1);
+
+        let e = diagnostic.await.unwrap().unwrap_err();
+        assert!(e.to_string().contains("cancelled"), "{}", e);
+        drop(release);
+        assert!(servo.read_info(1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn survives_panicking_operations() {
+        let servo = SimServo::new(hal_sim::Servo::new([1])).unwrap();
+        let e = servo.control(|_| -> Result<()> { panic!("operation failed") }).await.unwrap_err();
+        assert!(e.to_string().contains("cancelled"), "{}", e);
+        assert_eq!(servo.control(|bus| Ok(bus.ids())).await.unwrap(), [1]);
+    }
+}
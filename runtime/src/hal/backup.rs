@@ -0,0 +1,230 @@
+use anyhow::{Result, bail, Context};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::hal::{MemoryLockState, RegisterAccess, Servo, ServoBus, ServoError, ServoRegister, REGISTERS};
+
+/// Format version written to new backups; files from newer versions are refused.
+pub const BACKUP_VERSION: u32 = 1;
+
+// Moving servos between IDs or rates needs the dedicated tools, see `sts_change_id` and `sts_baud`
+const NOT_RESTORED: [ServoRegister; 2] = [ServoRegister::ID, ServoRegister::BaudRate];
+
+/// EEPROM contents of a set of servos, saved as TOML or, for `.json` paths, JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServoBackup {
+    pub version: u32,
+    /// RFC 3339 time the backup was taken.
+    pub created: String,
+    #[serde(rename = "servo", default)]
+    pub servos: Vec<ServoSnapshot>,
+}
+
+/// One servo's EEPROM registers by name, decoded as by [`RegisterInfo::decode`](crate::hal::RegisterInfo::decode).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServoSnapshot {
+    pub id: u8,
+    /// Name of the servo's [`ServoProfile`](crate::hal::ServoProfile).
+    pub model: String,
+    pub registers: BTreeMap<String, i32>,
+}
+
+/// A register whose value on the servo differs from the wanted one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub id: u8,
+    pub register: ServoRegister,
+    pub live: i32,
+    pub wanted: i32,
+}
+
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "servo {} {}: {} -> {}", self.id, self.register.info().name, self.live, self.wanted)
+    }
+}
+
+impl ServoBackup {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let backup: ServoBackup = if is_json(path) {
+            serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?
+        } else {
+            toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?
+        };
+        if backup.version > BACKUP_VERSION {
+            bail!("{} is a version {} backup, this build reads up to version {}", path.display(), backup.version, BACKUP_VERSION);
+        }
+        Ok(backup)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = if is_json(path) { serde_json::to_string_pretty(self)? } else { toml::to_string(self)? };
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn get(&self, id: u8) -> Option<&ServoSnapshot> {
+        self.servos.iter().find(|snapshot| snapshot.id == id)
+    }
+}
+
+/// Whether `restore` and `apply` may write `register`.
+pub(crate) fn is_restorable(register: ServoRegister) -> bool {
+    let info = register.info();
+    info.eeprom && info.access == RegisterAccess::ReadWrite && !NOT_RESTORED.contains(&register)
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+impl Servo {
+    /// Snapshots the EEPROM of each servo in `ids` that answers a scan.
+    pub fn backup(&self, ids: &[u8]) -> Result<ServoBackup> {
+        let mut servos = Vec::new();
+        for &id in ids {
+            if !self.scan(id)? {
+                continue;
+            }
+            let registers = self.read_eeprom(id)?
+                .into_iter()
+                .map(|(register, value)| (register.info().name.to_string(), value))
+                .collect();
+            servos.push(ServoSnapshot { id, model: self.profile(id).name.to_string(), registers });
+        }
+        Ok(ServoBackup { version: BACKUP_VERSION, created: chrono::Local::now().to_rfc3339(), servos })
+    }
+
+    /// Registers `restore` would rewrite on `snapshot.id`. Read-only registers, `ID` and
+    /// `BaudRate` are never part of the diff.
+    pub fn diff_snapshot(&self, snapshot: &ServoSnapshot) -> Result<Vec<RegisterChange>> {
+        let id = snapshot.id;
+        if !self.scan(id)? {
+            return Err(ServoError::Timeout { id }.into());
+        }
+        let model = self.profile(id).name;
+        if !model.eq_ignore_ascii_case(&snapshot.model) {
+            bail!("Servo {} is an {} servo, but its backup is for {}", id, model, snapshot.model);
+        }
+
+        let live = self.read_eeprom(id)?;
+        let mut changes = Vec::new();
+        for (name, &wanted) in &snapshot.registers {
+            let register = ServoRegister::from_name(name)
+                .with_context(|| format!("Unknown register '{}' in backup of servo {}", name, id))?;
+            let Some(&(_, live)) = live.iter().find(|(eeprom, _)| *eeprom == register) else {
+                bail!("{} in backup of servo {} is not an EEPROM register", name, id);
+            };
+            if !is_restorable(register) {
+                continue;
+            }
+            if live != wanted {
+                changes.push(RegisterChange { id, register, live, wanted });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Rewrites the EEPROM registers that differ from the backup on every servo in it,
+    /// and returns what was changed. Stops at the first servo that fails.
+    pub fn restore(&self, backup: &ServoBackup) -> Result<Vec<RegisterChange>> {
+        let mut applied = Vec::new();
+        for snapshot in &backup.servos {
+            let changes = self.diff_snapshot(snapshot)?;
+            self.write_eeprom(&changes)
+                .with_context(|| format!("Failed to restore servo {}", snapshot.id))?;
+            applied.extend(changes);
+        }
+        Ok(applied)
+    }
+
+    /// Writes each change with `LockMark` cleared. Every servo is locked again even if one
+    /// of its writes fails. The writes are read back unless the [`RetryPolicy`](crate::hal::RetryPolicy)
+    /// turns verification off.
+    pub fn write_eeprom(&self, changes: &[RegisterChange]) -> Result<()> {
+        let mut ids: Vec<u8> = changes.iter().map(|change| change.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        for id in ids {
+            self.set_memory_lock(id, MemoryLockState::Unlocked)?;
+            let written = changes.iter()
+                .filter(|change| change.id == id)
+                .try_for_each(|change| self.write_reg(change.id, change.register, change.wanted));
+            let locked = self.set_memory_lock(id, MemoryLockState::Locked);
+            written?;
+            locked?;
+        }
+        Ok(())
+    }
+
+    // Decodes every EEPROM register from one read of the whole area
+    pub(crate) fn read_eeprom(&self, id: u8) -> Result<Vec<(ServoRegister, i32)>> {
+        let eeprom: Vec<_> = REGISTERS.iter().filter(|info| info.eeprom).collect();
+        let length = eeprom.iter().map(|info| info.address + info.size).max().unwrap_or(0);
+        let data = self.read(id, ServoRegister::FirmwareMajorVersion, length)?;
+        if data.len() != length as usize {
+            return Err(ServoError::TruncatedPacket { id, expected: length as usize, received: data.len() }.into());
+        }
+
+        Ok(eeprom.iter().map(|info| {
+            let address = info.address as usize;
+            let raw = match info.size {
+                1 => data[address] as u16,
+                _ => u16::from_le_bytes([data[address], data[address + 1]]),
+            };
+            (info.register, info.decode(raw))
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal_sim;
+
+    fn sim(ids: impl IntoIterator<Item = u8>) -> Servo {
+        Servo::with_bus(hal_sim::Servo::new(ids))
+    }
+
+    #[test]
+    fn diffs_only_restorable_registers() {
+        let servo = sim([1]);
+        let mut snapshot = servo.backup(&[1, 2]).unwrap().servos.remove(0);
+        assert!(servo.diff_snapshot(&snapshot).unwrap().is_empty());
+
+        snapshot.registers.insert("MaxTorque".to_string(), 500);
+        snapshot.registers.insert("ID".to_string(), 7);
+        snapshot.registers.insert("FirmwareMajorVersion".to_string(), 9);
+        assert_eq!(servo.diff_snapshot(&snapshot).unwrap(), [
+            RegisterChange { id: 1, register: ServoRegister::MaxTorque, live: 1000, wanted: 500 },
+        ]);
+
+        let backup = ServoBackup { version: BACKUP_VERSION, created: String::new(), servos: vec![snapshot.clone()] };
+        assert_eq!(servo.restore(&backup).unwrap().len(), 1);
+        assert!(servo.diff_snapshot(&snapshot).unwrap().is_empty());
+        assert_eq!(servo.read_reg::<u8>(1, ServoRegister::ID).unwrap(), 1);
+    }
+
+    #[test]
+    fn refuses_snapshots_that_dont_match() {
+        let servo = sim([1]);
+        let snapshot = servo.backup(&[1]).unwrap().servos.remove(0);
+
+        let other_model = ServoSnapshot { model: "SCS".to_string(), ..snapshot.clone() };
+        assert!(servo.diff_snapshot(&other_model).unwrap_err().to_string().contains("backup is for SCS"));
+
+        let mut unknown = snapshot.clone();
+        unknown.registers.insert("Speed".to_string(), 1);
+        assert!(servo.diff_snapshot(&unknown).unwrap_err().to_string().contains("Unknown register 'Speed'"));
+
+        let mut ram = snapshot.clone();
+        ram.registers.insert("TargetLocation".to_string(), 1);
+        assert!(servo.diff_snapshot(&ram).unwrap_err().to_string().contains("not an EEPROM register"));
+
+        let absent = ServoSnapshot { id: 2, ..snapshot };
+        let e = servo.diff_snapshot(&absent).unwrap_err();
+        assert!(matches!(e.downcast_ref::<ServoError>(), Some(ServoError::Timeout { id: 2 })));
+    }
+}
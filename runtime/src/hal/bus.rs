@@ -0,0 +1,952 @@
+use anyhow::{Result, bail, Context};
+use std::env;
+use std::fmt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::hal::{is_idempotent_write, is_transient, PWM_DUTY_ENCODING, parse_servo_models, parse_servo_offsets, plan_sync_move, BusStats, JointMove, FaultLog, RetryPolicy, FaultRecord, RevolutionCounter, Revolutions, ServoUnits, RegisterAccess, ServoError, ServoProfile, STS3215, ServoInfo, ServoRegister, ServoData, ServoMultipleWriteCommand, StagedMove, TorqueMode, ServoMode, ServoDirection, MemoryLockState, IMUData};
+
+const BROADCAST_ID: u8 = 0xFE;
+/// Time a servo takes to commit an EEPROM write.
+pub const EEPROM_WRITE_DELAY: Duration = Duration::from_millis(10);
+
+/// Operations every servo backend provides, independent of how the bus is reached.
+///
+/// Only the raw register access, motion and readout calls have to be implemented;
+/// everything else is built on top of `read` and `write` and may be overridden when
+/// a backend has a faster path.
+pub trait ServoBus: Send + Sync + fmt::Debug {
+    fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()>;
+
+    fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>>;
+
+    fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()>;
+
+    /// Like `move_servo`, and sets the acceleration the move ramps up and down with in the
+    /// same write; 0 accelerates as fast as the servo can.
+    fn move_servo_with_acceleration(&self, id: u8, position: i16, time: u16, speed: u16, acceleration: u8) -> Result<()> {
+        let registers = StagedMove { id, acceleration, position, time, speed }.to_registers();
+        self.write(id, ServoRegister::Acceleration, &registers)
+    }
+
+    /// Reads `register` and decodes it as described by its [`RegisterInfo`](crate::hal::RegisterInfo),
+    /// e.g. `read_reg::<i16>(id, ServoRegister::PositionCorrection)`.
+    fn read_reg<T: TryFrom<i32>>(&self, id: u8, register: ServoRegister) -> Result<T>
+    where
+        Self: Sized,
+    {
+        let info = register.info();
+        let raw = match info.size {
+            1 => read_u8(self, id, register)? as u16,
+            _ => read_u16(self, id, register)?,
+        };
+        let value = info.decode(raw);
+        T::try_from(value).map_err(|_| {
+            ServoError::InvalidInput(format!("{} value {} does not fit the requested type", info.name, value)).into()
+        })
+    }
+
+    /// Encodes `value` for `register` and writes it. EEPROM registers still need
+    /// `LockMark` cleared first.
+    fn write_reg(&self, id: u8, register: ServoRegister, value: impl Into<i32>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        let info = register.info();
+        if info.access == RegisterAccess::ReadOnly {
+            return Err(ServoError::InvalidInput(format!("{} is read-only", info.name)).into());
+        }
+        let value = value.into();
+        self.profile(id).validate(register, value)?;
+        let raw = info.encode(value)?;
+        self.write(id, register, &raw.to_le_bytes()[..info.size as usize])
+    }
+
+    fn read_info(&self, id: u8) -> Result<ServoInfo>;
+
+    fn read_continuous(&self) -> Result<ServoData>;
+
+    /// IDs of the servos `read_continuous` reports on, in ascending order.
+    fn ids(&self) -> Vec<u8>;
+
+    /// When the readout last heard from `id`, for backends that track it. Entries in
+    /// `read_continuous` older than this are stale.
+    fn last_update(&self, _id: u8) -> Option<Instant> {
+        None
+    }
+
+    fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()>;
+
+    /// Queues `staged` on its servo with REG_WRITE; the servo holds it until `action`.
+    /// Staging the same servo again replaces its queued move.
+    fn stage_move(&self, _staged: &StagedMove) -> Result<()> {
+        bail!("Staged moves are not supported by this servo backend")
+    }
+
+    /// Broadcasts ACTION, starting every staged move at once.
+    fn action(&self) -> Result<()> {
+        bail!("Staged moves are not supported by this servo backend")
+    }
+
+    /// Moves every servo in `goals` from where it stands to its position so that they all
+    /// arrive together, `duration` from now or as much later as the slowest one needs; see
+    /// [`plan_sync_move`]. Returns how long the move takes.
+    fn move_synchronized(&self, goals: &[(u8, i16)], duration: Duration) -> Result<Duration> {
+        let data = self.read_continuous()?;
+        let mut joints = Vec::with_capacity(goals.len());
+        for &(id, to) in goals {
+            let from = match data.get(id) {
+                Some(info) => info.current_location,
+                None => read_u16(self, id, ServoRegister::CurrentLocation)? as i16,
+            };
+            joints.push(JointMove { id, from, to, profile: self.profile(id) });
+        }
+
+        let plan = plan_sync_move(&joints, duration);
+        self.write_multiple(&ServoMultipleWriteCommand {
+            only_write_positions: false,
+            write_accelerations: true,
+            targets: plan.targets,
+        })?;
+        Ok(plan.duration)
+    }
+
+    /// Faults `id` has reported since they were last cleared, for buses that latch them.
+    fn faults(&self, _id: u8) -> Option<FaultRecord> {
+        None
+    }
+
+    fn clear_faults(&self, _id: u8) {}
+
+    /// Transaction counters and reply latencies per servo. Backends that don't see
+    /// individual transactions return none.
+    fn bus_stats(&self) -> BusStats {
+        BusStats::default()
+    }
+
+    fn clear_bus_stats(&self) {}
+
+    fn enable_readout(&self) -> Result<()>;
+
+    fn disable_readout(&self) -> Result<()>;
+
+    fn enable_movement(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn disable_movement(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Speed the bus currently runs at, for backends that drive the UART themselves.
+    fn baud_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Moves every servo on the bus to `baud_rate`, one of
+    /// [`BAUD_RATES`](crate::hal::BAUD_RATES), and follows with the port. Servos that
+    /// were already switched are put back if one fails.
+    fn migrate_baud_rate(&self, _baud_rate: u32) -> Result<()> {
+        bail!("Changing the baud rate is not supported by this servo backend")
+    }
+
+    /// Model profile used for unit conversions and range checks on `id`. Backends
+    /// that don't track models assume an STS3215.
+    fn profile(&self, _id: u8) -> &'static ServoProfile {
+        &STS3215
+    }
+
+    /// Conversions between the registers of `id` and physical units.
+    fn units(&self, id: u8) -> ServoUnits {
+        ServoUnits::new(self.profile(id))
+    }
+
+    /// Reads the version registers of `id` and looks up its model, falling back to
+    /// the STS3215 profile for unknown versions.
+    fn identify(&self, id: u8) -> Result<&'static ServoProfile> {
+        let main_version = read_u8(self, id, ServoRegister::ServoMainVersion)?;
+        let sub_version = read_u8(self, id, ServoRegister::ServoSubVersion)?;
+        Ok(ServoProfile::identify(main_version, sub_version).unwrap_or(&STS3215))
+    }
+
+    fn set_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
+        self.profile(id).validate(ServoRegister::OperationMode, mode as i32)?;
+        self.write(id, ServoRegister::OperationMode, &[mode as u8])
+    }
+
+    fn set_speed(&self, id: u8, speed: u16, direction: ServoDirection) -> Result<()> {
+        let speed = if direction == ServoDirection::Clockwise { speed as i32 } else { -(speed as i32) };
+        let raw = ServoRegister::RunningSpeed.info().encode(speed)?;
+        self.write(id, ServoRegister::RunningSpeed, &raw.to_le_bytes())
+    }
+
+    fn mode(&self, id: u8) -> Result<ServoMode> {
+        match read_u8(self, id, ServoRegister::OperationMode)? {
+            0 => Ok(ServoMode::Position),
+            1 => Ok(ServoMode::ConstantSpeed),
+            2 => Ok(ServoMode::PWMOpenLoop),
+            3 => Ok(ServoMode::StepServo),
+            other => Err(ServoError::InvalidInput(format!("Servo {} is in unknown mode {}", id, other)).into()),
+        }
+    }
+
+    /// Switches `id` to `mode` without it lurching. Position moves use `RunningTime` and
+    /// `RunningSpeed` as limits, while the other modes take them as duty and velocity, so
+    /// both are cleared first: wheel and PWM mode start at rest and a spinning servo stops.
+    /// Step mode starts with no steps pending. Position mode is entered with torque off and
+    /// the target set to where the servo stands, then torque is turned back on if it was.
+    fn switch_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
+        self.profile(id).validate(ServoRegister::OperationMode, mode as i32)?;
+        self.write(id, ServoRegister::RunningTime, &[0; 4])?;
+        match mode {
+            ServoMode::Position => {
+                let torque = read_u8(self, id, ServoRegister::TorqueSwitch)?;
+                self.set_torque_mode(id, TorqueMode::Disabled)?;
+                self.set_mode(id, mode)?;
+                let location = ServoRegister::CurrentLocation.info().decode(read_u16(self, id, ServoRegister::CurrentLocation)?);
+                let position = location.rem_euclid(self.profile(id).resolution as i32);
+                self.move_servo(id, position as i16, 0, 0)?;
+                if torque != TorqueMode::Disabled as u8 {
+                    self.set_torque_mode(id, TorqueMode::Enabled)?;
+                }
+            }
+            ServoMode::StepServo => {
+                self.set_mode(id, mode)?;
+                // The target is relative in step mode, so this holds the servo where it is
+                self.write(id, ServoRegister::TargetLocation, &[0, 0])?;
+            }
+            ServoMode::ConstantSpeed | ServoMode::PWMOpenLoop => self.set_mode(id, mode)?,
+        }
+        Ok(())
+    }
+
+    /// Turns `id` continuously at `velocity` steps/s, negative for the other way. Switches
+    /// it to constant-speed (wheel) mode if it isn't in it.
+    fn set_wheel_velocity(&self, id: u8, velocity: i16) -> Result<()> {
+        ensure_mode(self, id, ServoMode::ConstantSpeed)?;
+        let raw = ServoRegister::RunningSpeed.info().encode(velocity as i32)?;
+        self.write(id, ServoRegister::RunningSpeed, &raw.to_le_bytes())
+    }
+
+    /// Drives `id` without position feedback at `duty`, in 0.1 % from -1000 to 1000.
+    /// Switches it to PWM mode if it isn't in it.
+    fn set_pwm_duty(&self, id: u8, duty: i16) -> Result<()> {
+        if !(-1000..=1000).contains(&duty) {
+            return Err(ServoError::InvalidInput(format!("PWM duty {} is outside -1000 to 1000", duty)).into());
+        }
+        ensure_mode(self, id, ServoMode::PWMOpenLoop)?;
+        let raw = PWM_DUTY_ENCODING.encode(duty as i32, 2).expect("duty is checked above");
+        self.write(id, ServoRegister::RunningTime, &raw.to_le_bytes())
+    }
+
+    /// Turns `id` by `steps` from where it stands, up to 32767 either way (eight turns of
+    /// an STS3215), at `speed` steps/s or full speed for 0. Switches it to step mode if it
+    /// isn't in it.
+    fn move_steps(&self, id: u8, steps: i16, speed: u16) -> Result<()> {
+        ensure_mode(self, id, ServoMode::StepServo)?;
+        self.write(id, ServoRegister::TargetLocation, &step_move(steps, speed)?)
+    }
+
+    fn read_pid(&self, id: u8) -> Result<(u8, u8, u8)> {
+        let p = read_u8(self, id, ServoRegister::PProportionalCoeff)?;
+        let i = read_u8(self, id, ServoRegister::IIntegralCoeff)?;
+        let d = read_u8(self, id, ServoRegister::DDifferentialCoeff)?;
+        Ok((p, i, d))
+    }
+
+    fn set_pid(&self, id: u8, p: u8, i: u8, d: u8) -> Result<()> {
+        // Unlock flash
+        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Unlocked as u8])?;
+
+        // Set PID parameters
+        self.write(id, ServoRegister::PProportionalCoeff, &[p])?;
+        self.write(id, ServoRegister::IIntegralCoeff, &[i])?;
+        self.write(id, ServoRegister::DDifferentialCoeff, &[d])?;
+
+        // Lock flash
+        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Locked as u8])?;
+
+        Ok(())
+    }
+
+    fn set_memory_lock(&self, id: u8, state: MemoryLockState) -> Result<()> {
+        self.write(id, ServoRegister::LockMark, &[state as u8])
+    }
+
+    fn read_angle_limits(&self, id: u8) -> Result<(i16, i16)> {
+        let min_limit = read_u16(self, id, ServoRegister::MinAngleLimit)? as i16;
+        let max_limit = read_u16(self, id, ServoRegister::MaxAngleLimit)? as i16;
+        Ok((min_limit, max_limit))
+    }
+
+    fn set_torque_mode(&self, id: u8, mode: TorqueMode) -> Result<()> {
+        self.write(id, ServoRegister::TorqueSwitch, &[mode as u8])
+    }
+
+    fn write_servo_memory(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
+        self.write(id, register, &value.to_le_bytes())
+    }
+
+    fn scan(&self, id: u8) -> Result<bool> {
+        // Try to read the servo ID from memory address 0x5 (ServoRegister::ID)
+        match self.read(id, ServoRegister::ID, 1) {
+            Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
+            Ok(_) => Ok(false), // Received data, but it doesn't match the ID
+            Err(e) => match e.downcast_ref::<ServoError>() {
+                // No response, assume no servo at this ID
+                Some(ServoError::Timeout { .. }) => Ok(false),
+                // Something answered but the exchange failed
+                Some(_) => Err(e),
+                // Backends without typed errors can't tell the two apart
+                None => Ok(false),
+            },
+        }
+    }
+}
+
+// Switches `id` to `mode` unless it is already in it
+fn ensure_mode<B: ServoBus + ?Sized>(bus: &B, id: u8, mode: ServoMode) -> Result<()> {
+    if bus.mode(id)? != mode {
+        bus.switch_mode(id, mode)?;
+    }
+    Ok(())
+}
+
+// `TargetLocation` through `RunningSpeed` for a relative move in step mode
+fn step_move(steps: i16, speed: u16) -> Result<[u8; 6]> {
+    let steps = ServoRegister::TargetLocation.info().encode(steps as i32)?;
+    let speed = ServoRegister::RunningSpeed.info().encode(speed as i32)?;
+    let mut data = [0; 6];
+    data[0..2].copy_from_slice(&steps.to_le_bytes());
+    data[4..6].copy_from_slice(&speed.to_le_bytes());
+    Ok(data)
+}
+
+fn read_u8<B: ServoBus + ?Sized>(bus: &B, id: u8, register: ServoRegister) -> Result<u8> {
+    match bus.read(id, register, 1)?.as_slice() {
+        &[value] => Ok(value),
+        data => Err(ServoError::TruncatedPacket { id, expected: 1, received: data.len() }.into()),
+    }
+}
+
+fn read_u16<B: ServoBus + ?Sized>(bus: &B, id: u8, register: ServoRegister) -> Result<u16> {
+    match bus.read(id, register, 2)?.as_slice() {
+        &[low, high] => Ok(u16::from_le_bytes([low, high])),
+        data => Err(ServoError::TruncatedPacket { id, expected: 2, received: data.len() }.into()),
+    }
+}
+
+/// Parses a servo ID list such as `16` (IDs 1 to 16) or `1-10,20,21`.
+pub fn parse_servo_ids(spec: &str) -> Result<Vec<u8>> {
+    let spec = spec.trim();
+    if let Ok(count) = spec.parse::<u8>() {
+        if count == 0 || count >= BROADCAST_ID {
+            bail!("Invalid servo count: {}", count);
+        }
+        return Ok((1..=count).collect());
+    }
+
+    let mut ids = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (part, part),
+        };
+        let first: u8 = first.parse().with_context(|| format!("Invalid servo ID in '{}'", part))?;
+        let last: u8 = last.parse().with_context(|| format!("Invalid servo ID in '{}'", part))?;
+        if first == 0 || last >= BROADCAST_ID || first > last {
+            bail!("Invalid servo ID range: {}", part);
+        }
+        ids.extend(first..=last);
+    }
+    if ids.is_empty() {
+        bail!("No servo IDs in '{}'", spec);
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Source of inertial measurements.
+pub trait ImuSource: Send + Sync {
+    fn read_data(&self) -> Result<IMUData>;
+}
+
+/// The servo bus selected at runtime.
+///
+/// `SERVO_BACKEND` picks the implementation (`milkv` or `serial`); without it the
+/// MilkV firmware backend is used when compiled in, and the serial backend otherwise.
+/// A `SERVO_PORT` of the form `sim://16` or `sim://1-4,7` overrides both and runs
+/// against simulated servos with those IDs.
+///
+/// Fault bits seen in readouts and command replies are latched per servo, see
+/// [`ServoBus::faults`]. Servos are identified when `scan` finds them; `SERVO_MODELS`
+/// (e.g. `1-12:sts3215;13:scs`) pins models that can't be identified, and `SERVO_OFFSETS`
+/// (e.g. `3:-12;7:40`) moves the zero of joints, see [`Servo::set_position_offset`].
+///
+/// Commands that fail on the wire are retried, and writes read back, as set by the
+/// [`RetryPolicy`]; retries show up in [`ServoBus::bus_stats`].
+///
+/// The mode each servo was last put in is remembered, so wheel, PWM and step commands only
+/// touch `OperationMode` when the mode changes. Turns are counted from every position reading
+/// returned through it, see [`Servo::revolutions`].
+pub struct Servo {
+    bus: Box<dyn ServoBus>,
+    faults: Mutex<FaultLog>,
+    models: HashMap<u8, &'static ServoProfile>,
+    detected: Mutex<HashMap<u8, &'static ServoProfile>>,
+    retry: Mutex<RetryPolicy>,
+    reliability: Mutex<BusStats>,
+    modes: Mutex<HashMap<u8, ServoMode>>,
+    revolutions: Mutex<RevolutionCounter>,
+    offsets: Mutex<HashMap<u8, i32>>,
+}
+
+impl Servo {
+    pub fn new() -> Result<Self> {
+        let mut servo = Self::open()?;
+        if let Ok(spec) = env::var("SERVO_MODELS") {
+            servo.models = parse_servo_models(&spec).context("Failed to parse SERVO_MODELS")?;
+        }
+        if let Ok(spec) = env::var("SERVO_OFFSETS") {
+            *servo.offsets.get_mut().unwrap() = parse_servo_offsets(&spec).context("Failed to parse SERVO_OFFSETS")?;
+        }
+        servo.set_retry_policy(RetryPolicy::from_env()?);
+        Ok(servo)
+    }
+
+    fn open() -> Result<Self> {
+        if let Some(spec) = sim_spec() {
+            return Ok(Self::with_bus(crate::hal_sim::Servo::from_spec(&spec)?));
+        }
+
+        let backend = env::var("SERVO_BACKEND").unwrap_or_else(|_| default_backend().to_string());
+
+        match backend.as_str() {
+            #[cfg(all(target_arch = "riscv64", target_os = "linux", feature = "milkv"))]
+            "milkv" => Ok(Self::with_bus(crate::hal_risc::Servo::new()?)),
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
+            "serial" => Ok(Self::with_bus(crate::hal_serial::Servo::new()?)),
+            other => anyhow::bail!("Unsupported servo backend: {}", other),
+        }
+    }
+
+    pub fn with_bus<B: ServoBus + 'static>(bus: B) -> Self {
+        Servo {
+            bus: Box::new(bus),
+            faults: Mutex::new(FaultLog::default()),
+            models: HashMap::new(),
+            detected: Mutex::new(HashMap::new()),
+            retry: Mutex::new(RetryPolicy::default()),
+            reliability: Mutex::new(BusStats::default()),
+            modes: Mutex::new(HashMap::new()),
+            revolutions: Mutex::new(RevolutionCounter::default()),
+            offsets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        *self.retry.lock().unwrap()
+    }
+
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.retry.lock().unwrap() = policy;
+    }
+
+    // Runs `op` up to the policy's attempts, backing off between tries, as long as it fails
+    // with errors that may go away. Operations that aren't `idempotent` may get only one.
+    fn with_retry<T>(&self, id: u8, idempotent: bool, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let policy = self.retry_policy();
+        let attempts = policy.attempts_for(idempotent);
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < attempts && is_transient(&e) => {
+                    self.reliability.lock().unwrap().servo(id).retries += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) if attempt > 1 => return Err(e.context(format!("Gave up after {} attempts", attempt))),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Turns of `id` counted from the readings `read_info` and `read_continuous` returned,
+    /// or `None` before its first reading. Only calls through this `Servo` count, not the
+    /// backend's own readout, so while a servo spins one of them has to be called more than
+    /// twice per turn, see [`RevolutionCounter`]. Turns are lost otherwise.
+    pub fn revolutions(&self, id: u8) -> Option<Revolutions> {
+        self.revolutions.lock().unwrap().get(id)
+    }
+
+    /// Counts turns of `id` from zero again, starting at its next reading.
+    pub fn reset_revolutions(&self, id: u8) {
+        self.revolutions.lock().unwrap().reset(id);
+    }
+
+    /// Moves the zero that [`ServoBus::units`] measures angles of `id` from by `offset`
+    /// steps from the center of its range, for joints whose neutral pose isn't centered.
+    pub fn set_position_offset(&self, id: u8, offset: i32) {
+        self.offsets.lock().unwrap().insert(id, offset);
+    }
+
+    // Reads back what was just written
+    fn verify(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+        let read = self.bus.read(id, register, data.len() as u8)?;
+        if read != data {
+            self.reliability.lock().unwrap().servo(id).verify_failures += 1;
+            return Err(ServoError::VerifyFailed { id, register, written: data.to_vec(), read }.into());
+        }
+        Ok(())
+    }
+
+    // Latches the faults carried by a command's status error
+    fn observe<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result {
+            if let Some(ServoError::Status { id, faults }) = e.downcast_ref::<ServoError>() {
+                self.faults.lock().unwrap().observe(*id, *faults);
+            }
+        }
+        result
+    }
+
+    /// Stages every move, then starts them together with one ACTION. Unlike `write_multiple`
+    /// each servo can have its own acceleration, time and speed. If a servo doesn't take its
+    /// move, the servos staged before it are re-staged with their current setpoints and
+    /// nothing is started.
+    pub fn move_staged(&self, moves: &[StagedMove]) -> Result<()> {
+        for (i, staged) in moves.iter().enumerate() {
+            if let Err(e) = self.stage_move(staged) {
+                for staged in &moves[..i] {
+                    let _ = self.unstage(staged.id);
+                }
+                return Err(e.context(format!("Failed to stage move for servo {}", staged.id)));
+            }
+        }
+        self.action()
+    }
+
+    // Queues the servo's present setpoints, so a stray ACTION leaves it where it is
+    fn unstage(&self, id: u8) -> Result<()> {
+        let info = self.bus.read_info(id)?;
+        self.bus.stage_move(&StagedMove {
+            id,
+            acceleration: info.acceleration,
+            position: info.target_location,
+            time: info.running_time,
+            speed: info.running_speed,
+        })
+    }
+}
+
+fn sim_spec() -> Option<String> {
+    let port = env::var("SERVO_PORT").ok()?;
+    port.strip_prefix("sim://").map(str::to_string)
+}
+
+fn default_backend() -> &'static str {
+    if cfg!(all(target_arch = "riscv64", target_os = "linux", feature = "milkv")) {
+        "milkv"
+    } else {
+        "serial"
+    }
+}
+
+impl fmt::Debug for Servo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Servo")
+         .field("bus", &self.bus)
+         .field("faults", &self.faults)
+         .field("models", &self.models)
+         .field("detected", &self.detected)
+         .finish()
+    }
+}
+
+impl ServoBus for Servo {
+    fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+        let mode = ServoRegister::OperationMode as usize;
+        if (register as usize..register as usize + data.len()).contains(&mode) {
+            let mut modes = self.modes.lock().unwrap();
+            if id == BROADCAST_ID {
+                modes.clear();
+            } else {
+                modes.remove(&id);
+            }
+        }
+        // Nothing answers a broadcast, so there is nothing to retry or read back
+        if id == BROADCAST_ID {
+            return self.observe(self.bus.write(id, register, data));
+        }
+        let verify = self.retry_policy().verifies(register);
+        self.with_retry(id, is_idempotent_write(register), || {
+            self.observe(self.bus.write(id, register, data))?;
+            if register.info().eeprom {
+                thread::sleep(EEPROM_WRITE_DELAY);
+            }
+            if verify {
+                self.verify(id, register, data)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+        self.with_retry(id, true, || self.bus.read(id, register, length))
+    }
+
+    fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
+        self.with_retry(id, true, || {
+            self.observe(self.bus.move_servo(id, position, time, speed))
+        })
+    }
+
+    fn read_info(&self, id: u8) -> Result<ServoInfo> {
+        let info = self.with_retry(id, true, || self.bus.read_info(id))?;
+        self.faults.lock().unwrap().observe(id, info.faults());
+        self.revolutions.lock().unwrap().observe(id, info.current_location, self.profile(id).resolution);
+        Ok(info)
+    }
+
+    fn read_continuous(&self) -> Result<ServoData> {
+        let data = self.bus.read_continuous()?;
+        self.faults.lock().unwrap().observe_data(&data);
+        self.revolutions.lock().unwrap().observe_data(&data, |id| self.profile(id).resolution);
+        Ok(data)
+    }
+
+    fn ids(&self) -> Vec<u8> {
+        self.bus.ids()
+    }
+
+    fn last_update(&self, id: u8) -> Option<Instant> {
+        self.bus.last_update(id)
+    }
+
+    fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
+        self.bus.write_multiple(cmd)
+    }
+
+    fn stage_move(&self, staged: &StagedMove) -> Result<()> {
+        self.with_retry(staged.id, true, || self.observe(self.bus.stage_move(staged)))
+    }
+
+    fn action(&self) -> Result<()> {
+        self.bus.action()
+    }
+
+    fn faults(&self, id: u8) -> Option<FaultRecord> {
+        self.faults.lock().unwrap().get(id).cloned()
+    }
+
+    fn clear_faults(&self, id: u8) {
+        self.faults.lock().unwrap().clear(id);
+    }
+
+    fn bus_stats(&self) -> BusStats {
+        let mut stats = self.bus.bus_stats();
+        stats.merge(&self.reliability.lock().unwrap());
+        stats
+    }
+
+    fn clear_bus_stats(&self) {
+        self.bus.clear_bus_stats();
+        *self.reliability.lock().unwrap() = BusStats::default();
+    }
+
+    fn enable_readout(&self) -> Result<()> {
+        self.bus.enable_readout()
+    }
+
+    fn disable_readout(&self) -> Result<()> {
+        self.bus.disable_readout()
+    }
+
+    fn enable_movement(&self) -> Result<()> {
+        self.bus.enable_movement()
+    }
+
+    fn disable_movement(&self) -> Result<()> {
+        self.bus.disable_movement()
+    }
+
+    fn baud_rate(&self) -> Option<u32> {
+        self.bus.baud_rate()
+    }
+
+    fn migrate_baud_rate(&self, baud_rate: u32) -> Result<()> {
+        self.bus.migrate_baud_rate(baud_rate)
+    }
+
+    fn profile(&self, id: u8) -> &'static ServoProfile {
+        if let Some(profile) = self.models.get(&id) {
+            return profile;
+        }
+        self.detected.lock().unwrap().get(&id).copied().unwrap_or(&STS3215)
+    }
+
+    fn units(&self, id: u8) -> ServoUnits {
+        let offset = self.offsets.lock().unwrap().get(&id).copied().unwrap_or(0);
+        ServoUnits::new(self.profile(id)).with_offset(offset)
+    }
+
+    fn identify(&self, id: u8) -> Result<&'static ServoProfile> {
+        self.with_retry(id, true, || self.bus.identify(id))
+    }
+
+    fn set_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
+        self.profile(id).validate(ServoRegister::OperationMode, mode as i32)?;
+        self.modes.lock().unwrap().remove(&id);
+        self.with_retry(id, true, || self.bus.set_mode(id, mode))?;
+        self.modes.lock().unwrap().insert(id, mode);
+        Ok(())
+    }
+
+    fn mode(&self, id: u8) -> Result<ServoMode> {
+        if let Some(&mode) = self.modes.lock().unwrap().get(&id) {
+            return Ok(mode);
+        }
+        let mode = self.with_retry(id, true, || self.bus.mode(id))?;
+        self.modes.lock().unwrap().insert(id, mode);
+        Ok(mode)
+    }
+
+    fn move_steps(&self, id: u8, steps: i16, speed: u16) -> Result<()> {
+        ensure_mode(self, id, ServoMode::StepServo)?;
+        // A relative move whose reply was lost would be made twice, so it isn't retried
+        let data = step_move(steps, speed)?;
+        self.observe(self.bus.write(id, ServoRegister::TargetLocation, &data))
+    }
+
+    fn set_speed(&self, id: u8, speed: u16, direction: ServoDirection) -> Result<()> {
+        self.with_retry(id, true, || self.bus.set_speed(id, speed, direction))
+    }
+
+    // PID, lock, angle limit, torque and raw memory access keep the default implementations,
+    // which no backend overrides, so they go through `read` and `write` above
+
+    fn scan(&self, id: u8) -> Result<bool> {
+        let found = self.bus.scan(id)?;
+        if found {
+            match self.bus.identify(id) {
+                Ok(profile) => { self.detected.lock().unwrap().insert(id, profile); }
+                Err(_) => { self.detected.lock().unwrap().remove(&id); }
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The IMU selected at runtime, following the same backend choice as [`Servo`].
+pub struct IMU {
+    source: Box<dyn ImuSource>,
+}
+
+impl IMU {
+    pub fn new() -> Result<Self> {
+        if sim_spec().is_some() {
+            return Ok(Self::with_source(crate::hal_sim::IMU::new()?));
+        }
+
+        let backend = env::var("SERVO_BACKEND").unwrap_or_else(|_| default_backend().to_string());
+
+        match backend.as_str() {
+            #[cfg(all(target_arch = "riscv64", target_os = "linux", feature = "milkv"))]
+            "milkv" => Ok(Self::with_source(crate::hal_risc::IMU::new()?)),
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
+            "serial" => Ok(Self::with_source(crate::hal_serial::IMU::new()?)),
+            other => anyhow::bail!("Unsupported IMU backend: {}", other),
+        }
+    }
+
+    pub fn with_source<I: ImuSource + 'static>(source: I) -> Self {
+        IMU { source: Box::new(source) }
+    }
+}
+
+impl fmt::Debug for IMU {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IMU").finish_non_exhaustive()
+    }
+}
+
+impl ImuSource for IMU {
+    fn read_data(&self) -> Result<IMUData> {
+        self.source.read_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use crate::hal::ServoFaults;
+
+    // What the test wants the next reads and writes to do, and when they were made
+    #[derive(Debug, Default)]
+    struct Script {
+        failures: VecDeque<ServoError>,
+        // Writes acknowledged without reaching the servo
+        lost_writes: u32,
+        calls: Vec<Instant>,
+    }
+
+    // Simulated servos that fail reads and writes as scripted
+    #[derive(Debug)]
+    struct FlakyBus {
+        sim: crate::hal_sim::Servo,
+        script: Arc<Mutex<Script>>,
+    }
+
+    impl FlakyBus {
+        fn call(&self) -> Result<()> {
+            let mut script = self.script.lock().unwrap();
+            script.calls.push(Instant::now());
+            match script.failures.pop_front() {
+                Some(e) => Err(e.into()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl ServoBus for FlakyBus {
+        fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+            self.call()?;
+            let mut script = self.script.lock().unwrap();
+            if script.lost_writes > 0 {
+                script.lost_writes -= 1;
+                return Ok(());
+            }
+            drop(script);
+            self.sim.write(id, register, data)
+        }
+
+        fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+            self.call()?;
+            self.sim.read(id, register, length)
+        }
+
+        fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
+            self.sim.move_servo(id, position, time, speed)
+        }
+
+        fn read_info(&self, id: u8) -> Result<ServoInfo> {
+            self.sim.read_info(id)
+        }
+
+        fn read_continuous(&self) -> Result<ServoData> {
+            self.sim.read_continuous()
+        }
+
+        fn ids(&self) -> Vec<u8> {
+            self.sim.ids()
+        }
+
+        fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
+            self.sim.write_multiple(cmd)
+        }
+
+        fn enable_readout(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn disable_readout(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn flaky(failures: impl IntoIterator<Item = ServoError>, policy: RetryPolicy) -> (Servo, Arc<Mutex<Script>>) {
+        let script = Arc::new(Mutex::new(Script { failures: failures.into_iter().collect(), ..Script::default() }));
+        let servo = Servo::with_bus(FlakyBus { sim: crate::hal_sim::Servo::new([1]), script: script.clone() });
+        servo.set_retry_policy(policy);
+        (servo, script)
+    }
+
+    fn retries(servo: &Servo) -> u64 {
+        servo.bus_stats().get(1).map_or(0, |stats| stats.retries)
+    }
+
+    #[test]
+    fn doubles_the_backoff_between_attempts() {
+        let policy = RetryPolicy { backoff: Duration::from_millis(20), ..RetryPolicy::default() };
+        let (servo, script) = flaky([ServoError::Timeout { id: 1 }, ServoError::ChecksumMismatch { id: 1, expected: 0, received: 1 }], policy);
+        assert_eq!(servo.read(1, ServoRegister::TorqueLimit, 2).unwrap(), 1000u16.to_le_bytes());
+        let calls = script.lock().unwrap().calls.clone();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[1] - calls[0] >= Duration::from_millis(20));
+        assert!(calls[2] - calls[1] >= Duration::from_millis(40));
+        assert_eq!(retries(&servo), 2);
+    }
+
+    #[test]
+    fn does_not_retry_errors_the_servo_reports() {
+        let status = ServoError::Status { id: 1, faults: ServoFaults::OVERLOAD };
+        let (servo, script) = flaky([status], RetryPolicy::default());
+        let e = servo.read(1, ServoRegister::TorqueLimit, 2).unwrap_err();
+        assert!(matches!(e.downcast_ref::<ServoError>(), Some(ServoError::Status { .. })));
+        assert!(!e.to_string().contains("Gave up"));
+        assert_eq!(script.lock().unwrap().calls.len(), 1);
+        assert_eq!(retries(&servo), 0);
+    }
+
+    #[test]
+    fn gives_up_after_the_policy_attempts() {
+        let (servo, script) = flaky((0..5).map(|_| ServoError::Timeout { id: 1 }), RetryPolicy::default());
+        let e = servo.read(1, ServoRegister::TorqueLimit, 2).unwrap_err();
+        assert_eq!(e.to_string(), "Gave up after 3 attempts");
+        assert!(matches!(e.downcast_ref::<ServoError>(), Some(ServoError::Timeout { id: 1 })));
+        assert_eq!(script.lock().unwrap().calls.len(), 3);
+        assert_eq!(retries(&servo), 2);
+    }
+
+    #[test]
+    fn retries_reads_of_addressing_registers_but_not_writes() {
+        let (servo, _) = flaky([ServoError::Timeout { id: 1 }], RetryPolicy::default());
+        assert_eq!(servo.read(1, ServoRegister::ID, 1).unwrap(), [1]);
+
+        let (servo, script) = flaky([ServoError::Timeout { id: 1 }], RetryPolicy::default());
+        let e = servo.write(1, ServoRegister::ID, &[2]).unwrap_err();
+        assert!(!e.to_string().contains("Gave up"));
+        assert_eq!(script.lock().unwrap().calls.len(), 1);
+    }
+
+    #[test]
+    fn reports_writes_that_read_back_differently() {
+        let policy = RetryPolicy { attempts: 1, ..RetryPolicy::default() };
+        let (servo, script) = flaky([], policy);
+        script.lock().unwrap().lost_writes = 1;
+        let e = servo.write(1, ServoRegister::ReturnDelay, &[5]).unwrap_err();
+        match e.downcast_ref::<ServoError>() {
+            Some(ServoError::VerifyFailed { id: 1, register: ServoRegister::ReturnDelay, written, read }) => {
+                assert_eq!(written, &[5]);
+                assert_eq!(read, &[0]);
+            }
+            other => panic!("expected VerifyFailed, got {:?}", other),
+        }
+        assert_eq!(servo.bus_stats().get(1).unwrap().verify_failures, 1);
+
+        // The next attempt writes again and reads back what it wrote
+        let (servo, script) = flaky([], RetryPolicy::default());
+        script.lock().unwrap().lost_writes = 1;
+        servo.write(1, ServoRegister::ReturnDelay, &[5]).unwrap();
+        assert_eq!(servo.read(1, ServoRegister::ReturnDelay, 1).unwrap(), [5]);
+        assert_eq!(retries(&servo), 1);
+    }
+
+    #[test]
+    fn moves_with_acceleration_in_one_write() {
+        let (servo, script) = flaky([], RetryPolicy::default());
+        // -100 steps, in TargetLocation's sign-magnitude
+        servo.move_servo_with_acceleration(1, (0x8000u16 | 100) as i16, 0x0203, 0x0405, 50).unwrap();
+        // Acceleration, then TargetLocation, RunningTime and RunningSpeed
+        assert_eq!(servo.read(1, ServoRegister::Acceleration, 7).unwrap(), [50, 100, 0x80, 0x03, 0x02, 0x05, 0x04]);
+        // One write and the read
+        assert_eq!(script.lock().unwrap().calls.len(), 2);
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Result, bail, Context};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::hal::{ServoFaults, REGISTERS};
+
+const CAPTURE_HEADER: &str = "# sts capture v1";
+
+// Instructions as they appear in requests
+const INSTRUCTIONS: [(u8, &str); 8] = [
+    (0x01, "PING"),
+    (0x02, "READ"),
+    (0x03, "WRITE"),
+    (0x04, "REG_WRITE"),
+    (0x05, "ACTION"),
+    (0x06, "RESET"),
+    (0x82, "SYNC_READ"),
+    (0x83, "SYNC_WRITE"),
+];
+const READ: u8 = 0x02;
+const SYNC_READ: u8 = 0x82;
+const SYNC_WRITE: u8 = 0x83;
+const BROADCAST_ID: u8 = 0xFE;
+
+/// Which way a captured packet went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn symbol(self) -> char {
+        match self {
+            Direction::Sent => '>',
+            Direction::Received => '<',
+        }
+    }
+}
+
+/// One packet read back from a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// Time since the capture started.
+    pub time: Duration,
+    pub port: String,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Timestamped log of every packet sent and received on the serial buses, enabled with
+/// `SERVO_CAPTURE=<file>`. Each line holds the seconds since the capture started, the
+/// port, `>` for sent or `<` for received, and the packet in hex. Received packets are
+/// logged as framed, so corrupted replies and echoes of our own requests are included.
+/// `sts_decode` prints captures as instructions.
+#[derive(Debug)]
+pub struct Capture {
+    file: Mutex<LineWriter<File>>,
+    started: Instant,
+}
+
+impl Capture {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut file = LineWriter::new(file);
+        writeln!(file, "{} started {}", CAPTURE_HEADER, chrono::Local::now().to_rfc3339())?;
+        Ok(Capture { file: Mutex::new(file), started: Instant::now() })
+    }
+
+    pub fn record(&self, port: &str, direction: Direction, bytes: &[u8]) {
+        let time = self.started.elapsed();
+        let mut file = self.file.lock().unwrap();
+        // A full disk shouldn't stop the servos
+        let _ = writeln!(file, "{:.6} {} {} {}", time.as_secs_f64(), port, direction.symbol(), hex(bytes));
+    }
+}
+
+/// Reads a file written by [`Capture`].
+pub fn read_capture(path: &Path) -> Result<Vec<CapturedPacket>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if !text.starts_with(CAPTURE_HEADER) {
+        bail!("{} is not a servo capture", path.display());
+    }
+
+    let mut packets = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        packets.push(parse_line(line).with_context(|| format!("Line {} of {}", number + 1, path.display()))?);
+    }
+    Ok(packets)
+}
+
+fn parse_line(line: &str) -> Result<CapturedPacket> {
+    let mut fields = line.split_whitespace();
+    let (Some(time), Some(port), Some(direction)) = (fields.next(), fields.next(), fields.next()) else {
+        bail!("Expected time, port, direction and bytes");
+    };
+    let time = Duration::from_secs_f64(time.parse().with_context(|| format!("Invalid time '{}'", time))?);
+    let direction = match direction {
+        ">" => Direction::Sent,
+        "<" => Direction::Received,
+        other => bail!("Invalid direction '{}'", other),
+    };
+    let bytes = fields
+        .map(|byte| u8::from_str_radix(byte, 16).with_context(|| format!("Invalid byte '{}'", byte)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(CapturedPacket { time, port: port.to_string(), direction, bytes })
+}
+
+/// Bytes as space-separated hex, the way captures store them.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// Turns captured packets into readable Feetech instructions. Replies are decoded against
+/// the last request sent on the same port, so packets have to be fed in capture order.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    requests: HashMap<String, Vec<u8>>,
+}
+
+impl Decoder {
+    pub fn decode(&mut self, packet: &CapturedPacket) -> String {
+        let bytes = &packet.bytes;
+        if bytes.len() < 6 || bytes[0] != 0xFF || bytes[1] != 0xFF || bytes[3] as usize != bytes.len() - 4 {
+            return format!("malformed: {}", hex(bytes));
+        }
+
+        let id = bytes[2];
+        let params = &bytes[5..bytes.len() - 1];
+        let checksum = checksum(bytes);
+        let received = bytes[bytes.len() - 1];
+        let validity = if checksum == received {
+            String::new()
+        } else {
+            format!("  BAD CHECKSUM (0x{:02X}, expected 0x{:02X})", received, checksum)
+        };
+        let target = if id == BROADCAST_ID { "all".to_string() } else { id.to_string() };
+
+        let description = match packet.direction {
+            Direction::Sent => {
+                self.requests.insert(packet.port.clone(), bytes.clone());
+                describe_request(bytes[4], params)
+            }
+            Direction::Received => {
+                let request = self.requests.get(&packet.port);
+                if request == Some(bytes) {
+                    "echo of the request".to_string()
+                } else {
+                    describe_reply(id, ServoFaults::from_bits_retain(bytes[4]), params, request.map(Vec::as_slice))
+                }
+            }
+        };
+        format!("{:>3} {}{}", target, description, validity)
+    }
+}
+
+fn checksum(packet: &[u8]) -> u8 {
+    let sum: u32 = packet[2..packet.len() - 1].iter().map(|&byte| byte as u32).sum();
+    !(sum as u8)
+}
+
+fn describe_request(instruction: u8, params: &[u8]) -> String {
+    let name = INSTRUCTIONS.iter().find(|(code, _)| *code == instruction).map(|(_, name)| *name);
+    let Some(name) = name else {
+        return format!("instruction 0x{:02X} {}", instruction, hex(params));
+    };
+
+    match (instruction, params) {
+        (READ, [address, length]) => format!("{} {}", name, span(*address, *length)),
+        (0x03 | 0x04, [address, data @ ..]) => format!("{} {}", name, values(*address, data)),
+        (SYNC_READ, [address, length, ids @ ..]) => {
+            format!("{} {} from {:?}", name, span(*address, *length), ids)
+        }
+        (SYNC_WRITE, [address, length, entries @ ..]) if *length > 0 => {
+            let entries: Vec<String> = entries
+                .chunks(*length as usize + 1)
+                .map(|entry| format!("{}: {}", entry[0], values(*address, &entry[1..])))
+                .collect();
+            format!("{} {}", name, entries.join("; "))
+        }
+        (_, []) => name.to_string(),
+        _ => format!("{} {}", name, hex(params)),
+    }
+}
+
+fn describe_reply(id: u8, faults: ServoFaults, params: &[u8], request: Option<&[u8]>) -> String {
+    let status = if faults.is_empty() { String::new() } else { format!(" [{}]", faults) };
+    // Parameters are register contents when they answer a read of this servo
+    let address = request.and_then(|request| match (request.get(4).copied(), request.get(5).copied()) {
+        (Some(READ), Some(address)) if request[2] == id => Some(address),
+        (Some(SYNC_READ), Some(address)) if request.get(7..request.len() - 1).is_some_and(|ids| ids.contains(&id)) => Some(address),
+        _ => None,
+    });
+
+    match address {
+        _ if params.is_empty() => format!("reply ok{}", status),
+        Some(address) => format!("reply {}{}", values(address, params), status),
+        None => format!("reply {}{}", hex(params), status),
+    }
+}
+
+// Register range covered by a read
+fn span(address: u8, length: u8) -> String {
+    match REGISTERS.iter().find(|info| info.address == address) {
+        Some(info) if length == info.size => info.name.to_string(),
+        Some(info) => format!("{} +{} bytes", info.name, length),
+        None => format!("0x{:02X} +{} bytes", address, length),
+    }
+}
+
+// Decodes `data` as the registers from `address` on, falling back to raw bytes where
+// the data doesn't line up with a register
+fn values(address: u8, data: &[u8]) -> String {
+    let mut values = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let at = address.wrapping_add(offset as u8);
+        let info = REGISTERS.iter().find(|info| info.address == at && offset + info.size as usize <= data.len());
+        match info {
+            Some(info) => {
+                let raw = match info.size {
+                    1 => data[offset] as u16,
+                    _ => u16::from_le_bytes([data[offset], data[offset + 1]]),
+                };
+                values.push(format!("{}={}", info.name, info.decode(raw)));
+                offset += info.size as usize;
+            }
+            None => {
+                values.push(format!("0x{:02X}=0x{:02X}", at, data[offset]));
+                offset += 1;
+            }
+        }
+    }
+    values.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(id: u8, instruction: u8, params: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xFF, 0xFF, id, params.len() as u8 + 2, instruction];
+        packet.extend_from_slice(params);
+        packet.push(0);
+        let last = packet.len() - 1;
+        packet[last] = checksum(&packet);
+        packet
+    }
+
+    fn sent(bytes: Vec<u8>) -> CapturedPacket {
+        CapturedPacket { time: Duration::ZERO, port: "test".to_string(), direction: Direction::Sent, bytes }
+    }
+
+    fn received(bytes: Vec<u8>) -> CapturedPacket {
+        CapturedPacket { direction: Direction::Received, ..sent(bytes) }
+    }
+
+    #[test]
+    fn decodes_read_and_its_reply() {
+        let mut decoder = Decoder::default();
+        let request = packet(1, READ, &[0x38, 2]);
+        assert_eq!(decoder.decode(&sent(request.clone())), "  1 READ CurrentLocation");
+        assert_eq!(decoder.decode(&received(request)), "  1 echo of the request");
+        assert_eq!(decoder.decode(&received(packet(1, 0, &[0x05, 0x88]))), "  1 reply CurrentLocation=-2053");
+    }
+
+    #[test]
+    fn decodes_sync_write_entries() {
+        let mut decoder = Decoder::default();
+        let request = packet(BROADCAST_ID, SYNC_WRITE, &[0x2A, 2, 1, 0x00, 0x08, 2, 0x10, 0x80]);
+        assert_eq!(decoder.decode(&sent(request)), "all SYNC_WRITE 1: TargetLocation=2048; 2: TargetLocation=-16");
+    }
+
+    #[test]
+    fn decodes_sync_read_replies_by_id() {
+        let mut decoder = Decoder::default();
+        decoder.decode(&sent(packet(BROADCAST_ID, SYNC_READ, &[0x3E, 1, 1, 2])));
+        assert_eq!(decoder.decode(&received(packet(2, 0x20, &[120]))), "  2 reply CurrentVoltage=120 [overload]");
+        // Not one of the servos asked
+        assert_eq!(decoder.decode(&received(packet(3, 0, &[120]))), "  3 reply 78");
+    }
+
+    #[test]
+    fn survives_short_sync_read() {
+        let mut decoder = Decoder::default();
+        assert_eq!(decoder.decode(&sent(packet(BROADCAST_ID, SYNC_READ, &[]))), "all SYNC_READ");
+        assert_eq!(decoder.decode(&received(packet(1, 0, &[5]))), "  1 reply 05");
+    }
+
+    #[test]
+    fn flags_bad_checksums_and_malformed_packets() {
+        let mut decoder = Decoder::default();
+        let mut reply = packet(1, 0, &[]);
+        reply[5] ^= 0xFF;
+        assert_eq!(decoder.decode(&received(reply)), "  1 reply ok  BAD CHECKSUM (0x03, expected 0xFC)");
+        assert_eq!(decoder.decode(&received(vec![0xFF, 0xFF, 1, 9, 0])), "malformed: FF FF 01 09 00");
+    }
+
+    #[test]
+    fn parses_capture_lines() {
+        let packet = parse_line("1.500000 /dev/ttyUSB0 < FF FF 01 02 00 FC").unwrap();
+        assert_eq!(packet.time, Duration::from_millis(1500));
+        assert_eq!(packet.port, "/dev/ttyUSB0");
+        assert_eq!(packet.direction, Direction::Received);
+        assert_eq!(hex(&packet.bytes), "FF FF 01 02 00 FC");
+        assert!(parse_line("0.1 port ? FF").is_err());
+    }
+}
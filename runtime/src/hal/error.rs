@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io;
+use crate::hal::{ServoFaults, ServoRegister};
+
+/// Failure of a single bus transaction.
+///
+/// Backends return these wrapped in `anyhow::Error`; use `downcast_ref::<ServoError>()`
+/// to tell them apart.
+#[derive(Debug)]
+pub enum ServoError {
+    /// No reply before the deadline.
+    Timeout { id: u8 },
+    ChecksumMismatch { id: u8, expected: u8, received: u8 },
+    /// A reply arrived from a different servo than the one addressed.
+    IdMismatch { expected: u8, received: u8 },
+    /// The reply ended early or its length field disagrees with the request.
+    TruncatedPacket { id: u8, expected: usize, received: usize },
+    /// The servo executed the command but reported errors in its status byte.
+    Status { id: u8, faults: ServoFaults },
+    /// The servo acknowledged a write but reads back something else.
+    VerifyFailed { id: u8, register: ServoRegister, written: Vec<u8>, read: Vec<u8> },
+    InvalidInput(String),
+    Io(io::Error),
+}
+
+impl ServoError {
+    /// The servo the failed transaction was addressed to, if any.
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            ServoError::Timeout { id }
+            | ServoError::ChecksumMismatch { id, .. }
+            | ServoError::TruncatedPacket { id, .. }
+            | ServoError::Status { id, .. }
+            | ServoError::VerifyFailed { id, .. } => Some(*id),
+            ServoError::IdMismatch { expected, .. } => Some(*expected),
+            ServoError::InvalidInput(_) | ServoError::Io(_) => None,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ServoError::Timeout { .. })
+    }
+
+    /// Whether the servo reported any of `fault`.
+    pub fn has_fault(&self, fault: ServoFaults) -> bool {
+        matches!(self, ServoError::Status { faults, .. } if faults.intersects(fault))
+    }
+}
+
+impl fmt::Display for ServoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServoError::Timeout { id } => write!(f, "Servo {} did not respond", id),
+            ServoError::ChecksumMismatch { id, expected, received } => write!(
+                f,
+                "Checksum mismatch in reply from servo {}: expected 0x{:02X}, got 0x{:02X}",
+                id, expected, received
+            ),
+            ServoError::IdMismatch { expected, received } => {
+                write!(f, "Expected reply from servo {}, got servo {}", expected, received)
+            }
+            ServoError::TruncatedPacket { id, expected, received } => write!(
+                f,
+                "Truncated reply from servo {}: expected {} bytes, got {}",
+                id, expected, received
+            ),
+            ServoError::Status { id, faults } => {
+                write!(f, "Servo {} reported {} error (status {:#010b})", id, faults, faults.bits())
+            }
+            ServoError::VerifyFailed { id, register, written, read } => write!(
+                f,
+                "Servo {} reads {} as {:02X?} after writing {:02X?}",
+                id, register.info().name, read, written
+            ),
+            ServoError::InvalidInput(message) => write!(f, "Invalid input: {}", message),
+            ServoError::Io(e) => write!(f, "Serial I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServoError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServoError {
+    fn from(e: io::Error) -> Self {
+        ServoError::Io(e)
+    }
+}
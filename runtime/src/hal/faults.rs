@@ -0,0 +1,188 @@
+use bitflags::bitflags;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::SystemTime;
+use crate::hal::{ServoData, ServoInfo};
+
+// Transitions kept per servo
+const FAULT_HISTORY_LENGTH: usize = 32;
+
+bitflags! {
+    /// Error bits of the status byte, as reported in every reply and in `ServoInfo::servo_status`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct ServoFaults: u8 {
+        const VOLTAGE = 0x01;
+        const ANGLE_SENSOR = 0x02;
+        const OVERHEAT = 0x04;
+        const OVERCURRENT = 0x08;
+        const OVERLOAD = 0x20;
+    }
+}
+
+const FAULT_NAMES: [(ServoFaults, &str, char); 5] = [
+    (ServoFaults::VOLTAGE, "voltage", 'V'),
+    (ServoFaults::ANGLE_SENSOR, "angle sensor", 'A'),
+    (ServoFaults::OVERHEAT, "overheat", 'T'),
+    (ServoFaults::OVERCURRENT, "overcurrent", 'C'),
+    (ServoFaults::OVERLOAD, "overload", 'L'),
+];
+
+impl ServoFaults {
+    /// Names of the known faults that are set.
+    pub fn names(&self) -> Vec<&'static str> {
+        FAULT_NAMES.iter().filter(|(fault, _, _)| self.contains(*fault)).map(|(_, name, _)| *name).collect()
+    }
+
+    /// One letter per fault (VATCL), `-` where clear, for narrow displays.
+    pub fn letters(&self) -> String {
+        FAULT_NAMES.iter().map(|(fault, _, letter)| if self.contains(*fault) { *letter } else { '-' }).collect()
+    }
+}
+
+impl fmt::Display for ServoFaults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.names();
+        if self.bits() & !Self::all().bits() != 0 {
+            names.push("unknown");
+        }
+        if names.is_empty() {
+            return write!(f, "none");
+        }
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+impl ServoInfo {
+    pub fn faults(&self) -> ServoFaults {
+        ServoFaults::from_bits_retain(self.servo_status)
+    }
+
+    pub fn has_fault(&self, fault: ServoFaults) -> bool {
+        self.faults().intersects(fault)
+    }
+}
+
+/// A change in the faults a servo reports.
+#[derive(Debug, Clone, Copy)]
+pub struct FaultEvent {
+    pub time: SystemTime,
+    pub raised: ServoFaults,
+    pub cleared: ServoFaults,
+}
+
+/// Fault state of one servo as seen by the HAL.
+#[derive(Debug, Clone, Default)]
+pub struct FaultRecord {
+    /// Faults in the most recent status byte.
+    pub active: ServoFaults,
+    /// Every fault seen since the record was last cleared.
+    pub latched: ServoFaults,
+    /// Most recent transitions, oldest first.
+    pub history: VecDeque<FaultEvent>,
+}
+
+/// Latches fault transitions per servo ID.
+#[derive(Debug, Default)]
+pub struct FaultLog {
+    servos: HashMap<u8, FaultRecord>,
+}
+
+impl FaultLog {
+    pub fn observe(&mut self, id: u8, faults: ServoFaults) {
+        let record = self.servos.entry(id).or_default();
+        if faults != record.active {
+            if record.history.len() == FAULT_HISTORY_LENGTH {
+                record.history.pop_front();
+            }
+            record.history.push_back(FaultEvent {
+                time: SystemTime::now(),
+                raised: faults - record.active,
+                cleared: record.active - faults,
+            });
+            record.active = faults;
+        }
+        record.latched |= faults;
+    }
+
+    pub fn observe_data(&mut self, data: &ServoData) {
+        for (&id, info) in &data.servos {
+            self.observe(id, info.faults());
+        }
+    }
+
+    pub fn get(&self, id: u8) -> Option<&FaultRecord> {
+        self.servos.get(&id)
+    }
+
+    /// Forgets latched faults and history, keeping the active faults.
+    pub fn clear(&mut self, id: u8) {
+        if let Some(record) = self.servos.get_mut(&id) {
+            record.latched = record.active;
+            record.history.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_raised_and_cleared_faults() {
+        let mut log = FaultLog::default();
+        log.observe(1, ServoFaults::empty());
+        assert!(log.get(1).unwrap().history.is_empty());
+
+        log.observe(1, ServoFaults::OVERHEAT);
+        log.observe(1, ServoFaults::OVERHEAT);
+        log.observe(1, ServoFaults::OVERLOAD | ServoFaults::OVERHEAT);
+        log.observe(1, ServoFaults::OVERLOAD);
+        let record = log.get(1).unwrap();
+        let transitions: Vec<_> = record.history.iter().map(|event| (event.raised, event.cleared)).collect();
+        assert_eq!(transitions, [
+            (ServoFaults::OVERHEAT, ServoFaults::empty()),
+            (ServoFaults::OVERLOAD, ServoFaults::empty()),
+            (ServoFaults::empty(), ServoFaults::OVERHEAT),
+        ]);
+        assert_eq!(record.active, ServoFaults::OVERLOAD);
+        assert_eq!(record.latched, ServoFaults::OVERLOAD | ServoFaults::OVERHEAT);
+        assert!(log.get(2).is_none());
+    }
+
+    #[test]
+    fn keeps_the_latest_transitions() {
+        let mut log = FaultLog::default();
+        for i in 0..FAULT_HISTORY_LENGTH + 5 {
+            log.observe(1, if i % 2 == 0 { ServoFaults::VOLTAGE } else { ServoFaults::empty() });
+        }
+        let history = &log.get(1).unwrap().history;
+        assert_eq!(history.len(), FAULT_HISTORY_LENGTH);
+        // The first five transitions are gone, the sixth cleared the fault
+        assert_eq!(history.front().unwrap().cleared, ServoFaults::VOLTAGE);
+        assert_eq!(history.back().unwrap().raised, ServoFaults::VOLTAGE);
+    }
+
+    #[test]
+    fn clears_all_but_the_active_faults() {
+        let mut log = FaultLog::default();
+        log.observe(1, ServoFaults::OVERHEAT);
+        log.observe(1, ServoFaults::VOLTAGE);
+        log.clear(1);
+        let record = log.get(1).unwrap();
+        assert_eq!(record.latched, ServoFaults::VOLTAGE);
+        assert!(record.history.is_empty());
+
+        // Seeing a still active fault again is no transition
+        log.observe(1, ServoFaults::VOLTAGE);
+        assert!(log.get(1).unwrap().history.is_empty());
+        log.clear(2);
+        assert!(log.get(2).is_none());
+    }
+
+    #[test]
+    fn names_faults() {
+        assert_eq!(ServoFaults::empty().to_string(), "none");
+        assert_eq!(ServoFaults::from_bits_retain(0x41).letters(), "V----");
+        assert!(ServoFaults::from_bits_retain(0x41).to_string().ends_with("unknown"));
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use crate::hal::{AngularAcceleration, AngularVelocity, ServoError, ServoMultipleWriteCommand, ServoProfile, ServoRegister, ServoTarget, ServoUnits};
+
+// Share of a synchronized move spent speeding up, and again slowing down
+const RAMP_SHARE: f32 = 0.25;
+// Highest `Acceleration` value; 0 would lift the limit altogether
+const MAX_ACCELERATION: u8 = 254;
+
+/// One servo's part in a synchronized move.
+#[derive(Debug, Clone, Copy)]
+pub struct JointMove {
+    pub id: u8,
+    /// `CurrentLocation` when the move starts.
+    pub from: i16,
+    pub to: i16,
+    pub profile: &'static ServoProfile,
+}
+
+impl JointMove {
+    fn distance(&self) -> f32 {
+        let from = ServoRegister::CurrentLocation.info().decode(self.from as u16);
+        (self.to as i32 - from).abs() as f32
+    }
+}
+
+/// Targets that bring every joint of a move to its goal at the same time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncMovePlan {
+    /// The requested duration, or longer if a joint couldn't make it in time.
+    pub duration: Duration,
+    pub targets: Vec<ServoTarget>,
+}
+
+/// Plans `joints` so they all arrive after `duration`. Each one follows a trapezoidal
+/// velocity profile, speeding up over the first quarter of the move and slowing down over
+/// the last. A joint that can't cover its distance that fast within its profile's speed
+/// and acceleration limits stretches the whole move, so the joints still arrive together.
+pub fn plan_sync_move(joints: &[JointMove], duration: Duration) -> SyncMovePlan {
+    let cruise_share = 1.0 - RAMP_SHARE;
+    let mut seconds = duration.as_secs_f32();
+    for joint in joints {
+        // Cruising at distance / (cruise_share * T), reached after RAMP_SHARE * T
+        let max_acceleration = MAX_ACCELERATION as f32 * joint.profile.acceleration_lsb;
+        seconds = seconds
+            .max(joint.distance() / (cruise_share * joint.profile.max_speed))
+            .max((joint.distance() / (cruise_share * RAMP_SHARE * max_acceleration)).sqrt());
+    }
+
+    let targets = joints
+        .iter()
+        .map(|joint| {
+            let (speed, acceleration) = if seconds > 0.0 {
+                let speed = joint.distance() / (cruise_share * seconds);
+                (speed, speed / (RAMP_SHARE * seconds))
+            } else {
+                (0.0, 0.0)
+            };
+            // Both registers take 0 as "no limit", so joints that stay put get the lowest
+            ServoTarget {
+                id: joint.id,
+                position: joint.to,
+                time: 0,
+                speed: (speed / joint.profile.speed_lsb).round().max(1.0) as u16,
+                acceleration: (acceleration / joint.profile.acceleration_lsb).ceil().clamp(1.0, MAX_ACCELERATION as f32) as u8,
+            }
+        })
+        .collect();
+    SyncMovePlan { duration: Duration::from_secs_f32(seconds), targets }
+}
+
+/// The writes that move `plain` and `paced` targets: one that sets only the positions of
+/// `plain`, so those servos keep the speed and acceleration they had, and one that sets all
+/// three for `paced`. Commands without targets are left out.
+pub fn position_writes(plain: Vec<ServoTarget>, paced: Vec<ServoTarget>) -> Vec<ServoMultipleWriteCommand> {
+    let plain = ServoMultipleWriteCommand { only_write_positions: true, write_accelerations: false, targets: plain };
+    let paced = ServoMultipleWriteCommand { only_write_positions: false, write_accelerations: true, targets: paced };
+    [plain, paced].into_iter().filter(|cmd| !cmd.targets.is_empty()).collect()
+}
+
+/// How joints get to their targets: each servo ramps its speed up at `acceleration`,
+/// cruises at up to `max_speed` and ramps down again before the target, a trapezoidal
+/// velocity profile run by the servo itself.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MotionProfile {
+    /// Degrees per second squared.
+    pub acceleration: f32,
+    /// Degrees per second; the servo's top speed when left out.
+    pub max_speed: Option<f32>,
+}
+
+impl MotionProfile {
+    /// `RunningSpeed` and `Acceleration` for a servo with `units`.
+    pub fn registers(&self, units: &ServoUnits) -> (u16, u8) {
+        let speed = self.max_speed.map_or(0, |speed| {
+            units.velocity_raw(AngularVelocity::from_degrees_per_second(speed)).unsigned_abs().max(1)
+        });
+        let acceleration = units.acceleration_raw(AngularAcceleration::from_degrees_per_second_squared(self.acceleration));
+        (speed, acceleration)
+    }
+
+    /// A move of `id` to `position` with this profile, for a command that writes accelerations.
+    pub fn target(&self, units: &ServoUnits, id: u8, position: i16) -> ServoTarget {
+        let (speed, acceleration) = self.registers(units);
+        ServoTarget { id, position, time: 0, speed, acceleration }
+    }
+}
+
+/// Motion profiles by name, from the `[motion]` table of a robot config such as
+/// `config/stompymicro.toml`; the rest of the file is ignored.
+///
+/// ```toml
+/// [motion.gentle]
+/// acceleration = 300.0
+/// max_speed = 90.0
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MotionProfiles {
+    profiles: BTreeMap<String, MotionProfile>,
+}
+
+#[derive(Deserialize)]
+struct RobotConfig {
+    #[serde(default)]
+    motion: BTreeMap<String, MotionProfile>,
+}
+
+impl MotionProfiles {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: RobotConfig = toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(MotionProfiles { profiles: config.motion })
+    }
+
+    /// The profiles in the file named by `SERVO_MOTION_PROFILES`, or none without it.
+    pub fn from_env() -> Result<Self> {
+        match env::var("SERVO_MOTION_PROFILES") {
+            Ok(path) => Self::load(Path::new(&path)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<MotionProfile, ServoError> {
+        self.profiles.get(name).copied().ok_or_else(|| {
+            if self.profiles.is_empty() {
+                ServoError::InvalidInput(format!("No motion profile '{}', none are loaded (see SERVO_MOTION_PROFILES)", name))
+            } else {
+                ServoError::InvalidInput(format!("No motion profile '{}', expected one of {}", name, self.names().join(", ")))
+            }
+        })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::{ServoBus, StagedMove, STS3215};
+
+    fn joint(id: u8, from: i16, to: i16) -> JointMove {
+        JointMove { id, from, to, profile: &STS3215 }
+    }
+
+    #[test]
+    fn scales_each_joint_to_arrive_together() {
+        let plan = plan_sync_move(&[joint(1, 1000, 2000), joint(2, 2000, 1500)], Duration::from_secs(1));
+        assert_eq!(plan.duration, Duration::from_secs(1));
+        assert_eq!(plan.targets, vec![
+            ServoTarget { id: 1, position: 2000, time: 0, speed: 1333, acceleration: 54 },
+            ServoTarget { id: 2, position: 1500, time: 0, speed: 667, acceleration: 27 },
+        ]);
+    }
+
+    #[test]
+    fn stretches_moves_beyond_top_speed() {
+        let plan = plan_sync_move(&[joint(1, 0, 4000), joint(2, 0, 400)], Duration::from_millis(100));
+        // 4000 steps cruising at 3400 steps/s for three quarters of the move
+        assert!((plan.duration.as_secs_f32() - 4000.0 / (0.75 * 3400.0)).abs() < 1e-3);
+        assert_eq!(plan.targets[0].speed, 3400);
+        assert_eq!(plan.targets[1].speed, 340);
+    }
+
+    #[test]
+    fn stretches_moves_beyond_top_acceleration() {
+        let plan = plan_sync_move(&[joint(1, 0, 1000)], Duration::from_millis(100));
+        let seconds = (1000.0f32 / (0.75 * 0.25 * 25400.0)).sqrt();
+        assert!((plan.duration.as_secs_f32() - seconds).abs() < 1e-3);
+        assert_eq!(plan.targets[0].acceleration, MAX_ACCELERATION);
+    }
+
+    #[test]
+    fn reads_negative_start_positions() {
+        let from = (0x8000u16 | 100) as i16;
+        let plan = plan_sync_move(&[joint(1, from, 100)], Duration::from_secs(1));
+        // 200 steps, not the raw difference
+        assert_eq!(plan.targets[0].speed, 267);
+    }
+
+    #[test]
+    fn never_lifts_the_limits_of_idle_joints() {
+        let plan = plan_sync_move(&[joint(1, 2048, 2048)], Duration::ZERO);
+        assert_eq!(plan.duration, Duration::ZERO);
+        assert_eq!((plan.targets[0].speed, plan.targets[0].acceleration), (1, 1));
+    }
+
+    fn registers(acceleration: u8, position: i16, time: u16, speed: u16) -> [u8; 7] {
+        StagedMove { id: 0, acceleration, position, time, speed }.to_registers()
+    }
+
+    fn robot_config() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("config/stompymicro.toml")
+    }
+
+    #[test]
+    fn keeps_the_pace_of_joints_that_only_move() {
+        let sim = crate::hal_sim::Servo::new([1, 2]);
+        sim.write(2, ServoRegister::Acceleration, &registers(30, 2048, 0, 500)).unwrap();
+
+        let writes = position_writes(
+            vec![ServoTarget { id: 2, position: 1000, ..Default::default() }],
+            vec![ServoTarget { id: 1, position: 3000, time: 0, speed: 800, acceleration: 20 }],
+        );
+        assert_eq!(writes.len(), 2);
+        for cmd in &writes {
+            sim.write_multiple(cmd).unwrap();
+        }
+
+        assert_eq!(sim.read(1, ServoRegister::Acceleration, 7).unwrap(), registers(20, 3000, 0, 800));
+        assert_eq!(sim.read(2, ServoRegister::Acceleration, 7).unwrap(), registers(30, 1000, 0, 500));
+        assert!(position_writes(Vec::new(), Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn loads_the_robot_config_profiles() {
+        let profiles = MotionProfiles::load(&robot_config()).unwrap();
+        assert_eq!(profiles.names(), ["gentle", "normal", "snappy"]);
+        assert_eq!(profiles.get("gentle").unwrap(), MotionProfile { acceleration: 300.0, max_speed: Some(90.0) });
+
+        let units = ServoUnits::new(&STS3215);
+        // 90°/s is 1024 steps/s, 300°/s² about 34.1 LSB, rounded up
+        assert_eq!(profiles.get("gentle").unwrap().registers(&units), (1024, 35));
+    }
+
+    #[test]
+    fn names_the_profiles_it_knows() {
+        let profiles = MotionProfiles::load(&robot_config()).unwrap();
+        let e = profiles.get("brisk").unwrap_err();
+        assert!(matches!(e, ServoError::InvalidInput(_)));
+        assert_eq!(e.to_string(), "Invalid input: No motion profile 'brisk', expected one of gentle, normal, snappy");
+        assert!(MotionProfiles::default().get("gentle").unwrap_err().to_string().contains("SERVO_MOTION_PROFILES"));
+    }
+}
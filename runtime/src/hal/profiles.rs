@@ -0,0 +1,118 @@
+use anyhow::{Result, Context};
+use std::collections::HashMap;
+use crate::hal::{parse_servo_ids, ServoError, ServoMode, ServoRegister};
+
+/// Scaling and valid ranges of one servo model.
+#[derive(Debug)]
+pub struct ServoProfile {
+    pub name: &'static str,
+    /// `ServoMainVersion`/`ServoSubVersion` pairs reported by this model.
+    pub versions: &'static [(u8, u8)],
+    /// Encoder steps over `range_degrees`.
+    pub resolution: u16,
+    pub range_degrees: f32,
+    /// Speed registers count steps per second times this.
+    pub speed_lsb: f32,
+    /// Fastest a position move can run, in steps per second.
+    pub max_speed: f32,
+    /// Steps per second squared per `Acceleration` LSB, up to 254 LSB.
+    pub acceleration_lsb: f32,
+    /// `None` for models without current sensing.
+    pub current_lsb_ma: Option<f32>,
+    pub voltage_lsb: f32,
+    pub modes: &'static [ServoMode],
+    /// Largest value for `MinAngleLimit` and `MaxAngleLimit`.
+    pub max_angle_limit: u16,
+}
+
+pub static STS3215: ServoProfile = ServoProfile {
+    name: "sts3215",
+    versions: &[(9, 3)],
+    resolution: 4096,
+    range_degrees: 360.0,
+    speed_lsb: 1.0,
+    max_speed: 3400.0,
+    acceleration_lsb: 100.0,
+    current_lsb_ma: Some(6.5),
+    voltage_lsb: 0.1,
+    modes: &[ServoMode::Position, ServoMode::ConstantSpeed, ServoMode::PWMOpenLoop, ServoMode::StepServo],
+    max_angle_limit: 4095,
+};
+
+/// The smaller STS servo, with the STS3215's encoder, scaling and modes. Its version
+/// registers aren't known yet, so it's only used where `SERVO_MODELS` names it.
+pub static STS3032: ServoProfile = ServoProfile {
+    name: "sts3032",
+    versions: &[],
+    resolution: 4096,
+    range_degrees: 360.0,
+    speed_lsb: 1.0,
+    max_speed: 3400.0,
+    acceleration_lsb: 100.0,
+    current_lsb_ma: Some(6.5),
+    voltage_lsb: 0.1,
+    modes: &[ServoMode::Position, ServoMode::ConstantSpeed, ServoMode::PWMOpenLoop, ServoMode::StepServo],
+    max_angle_limit: 4095,
+};
+
+/// Potentiometer-based SCS servos: 1024 steps over 300°, position mode only and no
+/// current sensing. Like the STS3032 they're only used where `SERVO_MODELS` names them.
+pub static SCS: ServoProfile = ServoProfile {
+    name: "scs",
+    versions: &[],
+    resolution: 1024,
+    range_degrees: 300.0,
+    speed_lsb: 1.0,
+    max_speed: 1000.0,
+    acceleration_lsb: 100.0,
+    current_lsb_ma: None,
+    voltage_lsb: 0.1,
+    modes: &[ServoMode::Position],
+    max_angle_limit: 1023,
+};
+
+/// Every known model. Servos reporting an unknown version are treated as the first, and
+/// models without `versions` are never identified, only assigned.
+pub static PROFILES: &[&ServoProfile] = &[&STS3215, &STS3032, &SCS];
+
+impl ServoProfile {
+    pub fn identify(main_version: u8, sub_version: u8) -> Option<&'static ServoProfile> {
+        PROFILES.iter().copied().find(|profile| profile.versions.contains(&(main_version, sub_version)))
+    }
+
+    pub fn by_name(name: &str) -> Option<&'static ServoProfile> {
+        PROFILES.iter().copied().find(|profile| profile.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Checks a value for `register` against this model's limits.
+    pub fn validate(&self, register: ServoRegister, value: i32) -> Result<(), ServoError> {
+        let valid = match register {
+            ServoRegister::MinAngleLimit | ServoRegister::MaxAngleLimit => (0..=self.max_angle_limit as i32).contains(&value),
+            ServoRegister::OperationMode => self.modes.iter().any(|&mode| mode as i32 == value),
+            _ => true,
+        };
+        if !valid {
+            return Err(ServoError::InvalidInput(format!("{} does not support {} = {}", self.name, register.info().name, value)));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a model assignment such as `1-12:sts3215;13:scs`, for servos whose version
+/// doesn't identify them.
+pub fn parse_servo_models(spec: &str) -> Result<HashMap<u8, &'static ServoProfile>> {
+    let mut models = HashMap::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (ids, name) = entry.rsplit_once(':').with_context(|| format!("Expected IDS:MODEL, got '{}'", entry))?;
+        let profile = ServoProfile::by_name(name.trim()).with_context(|| format!("Unknown servo model '{}'", name))?;
+        // A bare number is one ID here, not a count
+        let ids = match ids.trim().parse::<u8>() {
+            Ok(id) => vec![id],
+            Err(_) => parse_servo_ids(ids)?,
+        };
+        for id in ids {
+            models.insert(id, profile);
+        }
+    }
+    Ok(models)
+}
@@ -0,0 +1,218 @@
+use crate::hal::{ServoError, ServoRegister};
+
+/// How a register's raw bits map to a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterEncoding {
+    Unsigned,
+    /// Magnitude in the bits below `n`, with bit `n` set for negative values.
+    SignMagnitude(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterAccess {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Layout and meaning of one entry in the servo control table.
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterInfo {
+    pub register: ServoRegister,
+    pub name: &'static str,
+    pub address: u8,
+    /// Width in bytes, little-endian.
+    pub size: u8,
+    pub encoding: RegisterEncoding,
+    /// Size of one LSB, empty for flags, modes and coefficients.
+    pub unit: &'static str,
+    pub access: RegisterAccess,
+    /// Stored in EEPROM, so writes need `LockMark` cleared and survive power cycles.
+    pub eeprom: bool,
+}
+
+/// How `RunningTime` holds the duty in PWM mode. In position mode it's an unsigned time.
+pub const PWM_DUTY_ENCODING: RegisterEncoding = RegisterEncoding::SignMagnitude(10);
+
+impl RegisterEncoding {
+    pub fn decode(self, raw: u16) -> i32 {
+        match self {
+            RegisterEncoding::Unsigned => raw as i32,
+            RegisterEncoding::SignMagnitude(bit) => {
+                let magnitude = (raw & ((1 << bit) - 1)) as i32;
+                if raw & (1 << bit) != 0 { -magnitude } else { magnitude }
+            }
+        }
+    }
+
+    /// Raw bits for `value` in a register of `size` bytes, or `None` if it doesn't fit.
+    pub fn encode(self, value: i32, size: u8) -> Option<u16> {
+        match self {
+            RegisterEncoding::Unsigned => {
+                let max = if size == 1 { u8::MAX as i32 } else { u16::MAX as i32 };
+                (0..=max).contains(&value).then_some(value as u16)
+            }
+            RegisterEncoding::SignMagnitude(bit) => {
+                let magnitude = value.unsigned_abs();
+                let sign = if value < 0 { 1 << bit } else { 0 };
+                (magnitude < 1 << bit).then_some(magnitude as u16 | sign)
+            }
+        }
+    }
+}
+
+impl RegisterInfo {
+    pub fn decode(&self, raw: u16) -> i32 {
+        self.encoding.decode(raw)
+    }
+
+    pub fn encode(&self, value: i32) -> Result<u16, ServoError> {
+        self.encoding
+            .encode(value, self.size)
+            .ok_or_else(|| ServoError::InvalidInput(format!("{} out of range for {}", value, self.name)))
+    }
+}
+
+impl ServoRegister {
+    pub fn info(self) -> &'static RegisterInfo {
+        REGISTERS.iter().find(|info| info.register == self).expect("every register is in REGISTERS")
+    }
+
+    pub fn from_address(address: u8) -> Option<Self> {
+        REGISTERS.iter().find(|info| info.address == address).map(|info| info.register)
+    }
+
+    /// Looks a register up by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        REGISTERS.iter().find(|info| info.name.eq_ignore_ascii_case(name)).map(|info| info.register)
+    }
+}
+
+macro_rules! registers {
+    ($($register:ident, $size:expr, $encoding:expr, $unit:expr, $access:ident, $eeprom:expr;)*) => {
+        /// The STS3215 control table, in address order.
+        pub const REGISTERS: &[RegisterInfo] = &[
+            $(RegisterInfo {
+                register: ServoRegister::$register,
+                name: stringify!($register),
+                address: ServoRegister::$register as u8,
+                size: $size,
+                encoding: $encoding,
+                unit: $unit,
+                access: RegisterAccess::$access,
+                eeprom: $eeprom,
+            },)*
+        ];
+    };
+}
+
+use RegisterEncoding::{SignMagnitude, Unsigned};
+
+registers! {
+    FirmwareMajorVersion, 1, Unsigned, "", ReadOnly, true;
+    FirmwareSubVersion, 1, Unsigned, "", ReadOnly, true;
+    ServoMainVersion, 1, Unsigned, "", ReadOnly, true;
+    ServoSubVersion, 1, Unsigned, "", ReadOnly, true;
+    ID, 1, Unsigned, "", ReadWrite, true;
+    BaudRate, 1, Unsigned, "", ReadWrite, true;
+    ReturnDelay, 1, Unsigned, "2 us", ReadWrite, true;
+    ResponseStatusLevel, 1, Unsigned, "", ReadWrite, true;
+    MinAngleLimit, 2, Unsigned, "step", ReadWrite, true;
+    MaxAngleLimit, 2, Unsigned, "step", ReadWrite, true;
+    MaxTemperatureLimit, 1, Unsigned, "°C", ReadWrite, true;
+    MaxInputVoltage, 1, Unsigned, "0.1 V", ReadWrite, true;
+    MinInputVoltage, 1, Unsigned, "0.1 V", ReadWrite, true;
+    MaxTorque, 2, Unsigned, "0.1 %", ReadWrite, true;
+    Phase, 1, Unsigned, "", ReadWrite, true;
+    UnloadingCondition, 1, Unsigned, "", ReadWrite, true;
+    LEDAlarmCondition, 1, Unsigned, "", ReadWrite, true;
+    PProportionalCoeff, 1, Unsigned, "", ReadWrite, true;
+    DDifferentialCoeff, 1, Unsigned, "", ReadWrite, true;
+    IIntegralCoeff, 1, Unsigned, "", ReadWrite, true;
+    MinStartupForce, 2, Unsigned, "0.1 %", ReadWrite, true;
+    ClockwiseInsensitiveArea, 1, Unsigned, "step", ReadWrite, true;
+    CounterclockwiseInsensitiveArea, 1, Unsigned, "step", ReadWrite, true;
+    ProtectionCurrent, 2, Unsigned, "6.5 mA", ReadWrite, true;
+    AngularResolution, 1, Unsigned, "", ReadWrite, true;
+    PositionCorrection, 2, SignMagnitude(11), "step", ReadWrite, true;
+    OperationMode, 1, Unsigned, "", ReadWrite, true;
+    ProtectiveTorque, 1, Unsigned, "%", ReadWrite, true;
+    ProtectionTime, 1, Unsigned, "10 ms", ReadWrite, true;
+    OverloadTorque, 1, Unsigned, "%", ReadWrite, true;
+    SpeedClosedLoopPCoeff, 1, Unsigned, "", ReadWrite, true;
+    OverCurrentProtectionTime, 1, Unsigned, "10 ms", ReadWrite, true;
+    VelocityClosedLoopICoeff, 1, Unsigned, "", ReadWrite, true;
+    TorqueSwitch, 1, Unsigned, "", ReadWrite, false;
+    Acceleration, 1, Unsigned, "100 step/s²", ReadWrite, false;
+    TargetLocation, 2, SignMagnitude(15), "step", ReadWrite, false;
+    // Holds the duty in PWM mode instead, see PWM_DUTY_ENCODING
+    RunningTime, 2, Unsigned, "ms", ReadWrite, false;
+    RunningSpeed, 2, SignMagnitude(15), "step/s", ReadWrite, false;
+    TorqueLimit, 2, Unsigned, "0.1 %", ReadWrite, false;
+    LockMark, 1, Unsigned, "", ReadWrite, false;
+    CurrentLocation, 2, SignMagnitude(15), "step", ReadOnly, false;
+    CurrentSpeed, 2, SignMagnitude(15), "step/s", ReadOnly, false;
+    CurrentLoad, 2, SignMagnitude(10), "0.1 %", ReadOnly, false;
+    CurrentVoltage, 1, Unsigned, "0.1 V", ReadOnly, false;
+    CurrentTemperature, 1, Unsigned, "°C", ReadOnly, false;
+    AsyncWriteFlag, 1, Unsigned, "", ReadOnly, false;
+    ServoStatus, 1, Unsigned, "", ReadOnly, false;
+    MobileSign, 1, Unsigned, "", ReadOnly, false;
+    CurrentCurrent, 2, Unsigned, "6.5 mA", ReadOnly, false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_sign_at_each_bit() {
+        assert_eq!(ServoRegister::TargetLocation.info().decode(0x8000 | 100), -100);
+        assert_eq!(ServoRegister::CurrentLoad.info().decode(0x0400 | 100), -100);
+        assert_eq!(ServoRegister::PositionCorrection.info().decode(0x0800 | 100), -100);
+        assert_eq!(ServoRegister::CurrentLoad.info().decode(1023), 1023);
+    }
+
+    #[test]
+    fn ignores_bits_above_sign() {
+        // Bit 11 is above the sign of CurrentLoad, so it isn't part of the magnitude
+        assert_eq!(ServoRegister::CurrentLoad.info().decode(0x0800 | 5), 5);
+    }
+
+    #[test]
+    fn encodes_sign_at_each_bit() {
+        assert_eq!(ServoRegister::TargetLocation.info().encode(-100).unwrap(), 0x8000 | 100);
+        assert_eq!(ServoRegister::CurrentLoad.info().encode(-100).unwrap(), 0x0400 | 100);
+        assert_eq!(ServoRegister::PositionCorrection.info().encode(-2047).unwrap(), 0x0FFF);
+        assert_eq!(ServoRegister::TargetLocation.info().encode(32767).unwrap(), 0x7FFF);
+    }
+
+    #[test]
+    fn round_trips_through_encode() {
+        for register in [ServoRegister::TargetLocation, ServoRegister::CurrentLoad, ServoRegister::PositionCorrection] {
+            let info = register.info();
+            for value in [-1023, -1, 0, 1, 1023] {
+                assert_eq!(info.decode(info.encode(value).unwrap()), value, "{}", info.name);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert!(ServoRegister::CurrentLoad.info().encode(1024).is_err());
+        assert!(ServoRegister::PositionCorrection.info().encode(-2048).is_err());
+        assert!(ServoRegister::TargetLocation.info().encode(-32768).is_err());
+        assert!(ServoRegister::Acceleration.info().encode(256).is_err());
+        assert!(ServoRegister::TorqueLimit.info().encode(-1).is_err());
+        assert_eq!(ServoRegister::TorqueLimit.info().encode(65535).unwrap(), 65535);
+    }
+
+    #[test]
+    fn running_time_is_unsigned_outside_pwm_mode() {
+        let info = ServoRegister::RunningTime.info();
+        assert_eq!(info.encode(1500).unwrap(), 1500);
+        assert_eq!(info.decode(1500), 1500);
+        assert_eq!(PWM_DUTY_ENCODING.encode(-500, 2), Some(0x0400 | 500));
+        assert_eq!(PWM_DUTY_ENCODING.decode(0x0400 | 500), -500);
+        assert_eq!(PWM_DUTY_ENCODING.encode(1024, 2), None);
+    }
+}
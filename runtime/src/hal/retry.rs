@@ -0,0 +1,157 @@
+use anyhow::{Result, bail, Context};
+use std::env;
+use std::time::Duration;
+use crate::hal::{ServoError, ServoRegister};
+
+/// Which writes the [`Servo`](crate::hal::Servo) facade reads back before reporting success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyWrites {
+    Never,
+    /// Writes that start at an EEPROM register, where a lost write would persist unnoticed.
+    #[default]
+    Eeprom,
+    Always,
+}
+
+/// How the [`Servo`](crate::hal::Servo) facade retries transactions that failed on the wire.
+///
+/// Only timeouts, corrupted or short replies, replies from the wrong servo and failed
+/// verifications are retried; errors the servo reports itself are not. Writes that can't
+/// safely be repeated, those to `ID` and `BaudRate`, get one attempt unless
+/// `retry_non_idempotent` is set, and scans and readout polls are never retried. Reads
+/// are always retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries per operation, including the first.
+    pub attempts: u32,
+    /// Wait before the second try, doubled before each one after it.
+    pub backoff: Duration,
+    pub retry_non_idempotent: bool,
+    pub verify: VerifyWrites,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(5),
+            retry_non_idempotent: false,
+            verify: VerifyWrites::Eeprom,
+        }
+    }
+}
+
+// Registers that change how the servo is addressed
+const ADDRESSING: [ServoRegister; 2] = [ServoRegister::ID, ServoRegister::BaudRate];
+
+/// Whether a write starting at `register` can be repeated. Writes to `ID` and `BaudRate`
+/// can't: once one lands, the servo answers at another address or rate.
+pub fn is_idempotent_write(register: ServoRegister) -> bool {
+    !ADDRESSING.contains(&register)
+}
+
+impl RetryPolicy {
+    /// The default policy, adjusted by `SERVO_RETRIES` (attempts), `SERVO_RETRY_BACKOFF_MS`
+    /// and `SERVO_VERIFY_WRITES` (`never`, `eeprom` or `always`).
+    pub fn from_env() -> Result<Self> {
+        let mut policy = RetryPolicy::default();
+        if let Ok(attempts) = env::var("SERVO_RETRIES") {
+            policy.attempts = attempts.parse().context("Failed to parse SERVO_RETRIES")?;
+            if policy.attempts == 0 {
+                bail!("SERVO_RETRIES must be at least 1");
+            }
+        }
+        if let Ok(backoff) = env::var("SERVO_RETRY_BACKOFF_MS") {
+            policy.backoff = Duration::from_millis(backoff.parse().context("Failed to parse SERVO_RETRY_BACKOFF_MS")?);
+        }
+        if let Ok(verify) = env::var("SERVO_VERIFY_WRITES") {
+            policy.verify = match verify.to_ascii_lowercase().as_str() {
+                "never" | "0" => VerifyWrites::Never,
+                "eeprom" => VerifyWrites::Eeprom,
+                "always" | "1" => VerifyWrites::Always,
+                other => bail!("SERVO_VERIFY_WRITES must be never, eeprom or always, not '{}'", other),
+            };
+        }
+        Ok(policy)
+    }
+
+    /// Tries an operation gets, depending on whether it can safely be repeated.
+    pub fn attempts_for(&self, idempotent: bool) -> u32 {
+        if !idempotent && !self.retry_non_idempotent {
+            1
+        } else {
+            self.attempts.max(1)
+        }
+    }
+
+    /// Whether a write starting at `register` is read back. `ID` and `BaudRate` can't be,
+    /// since the servo stops answering at the old address or rate.
+    pub fn verifies(&self, register: ServoRegister) -> bool {
+        if !is_idempotent_write(register) {
+            return false;
+        }
+        match self.verify {
+            VerifyWrites::Never => false,
+            VerifyWrites::Eeprom => register.info().eeprom,
+            VerifyWrites::Always => true,
+        }
+    }
+}
+
+/// Whether trying `error` again could succeed.
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<ServoError>(),
+        Some(
+            ServoError::Timeout { .. }
+                | ServoError::ChecksumMismatch { .. }
+                | ServoError::IdMismatch { .. }
+                | ServoError::TruncatedPacket { .. }
+                | ServoError::VerifyFailed { .. }
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The only test that sets these, since the environment is shared between test threads
+    #[test]
+    fn reads_the_policy_from_the_environment() {
+        let with = |vars: &[(&str, &str)]| {
+            for (name, value) in vars {
+                env::set_var(name, value);
+            }
+            let policy = RetryPolicy::from_env();
+            for (name, _) in vars {
+                env::remove_var(name);
+            }
+            policy
+        };
+
+        assert_eq!(with(&[]).unwrap(), RetryPolicy::default());
+        let policy = with(&[("SERVO_RETRIES", "5"), ("SERVO_RETRY_BACKOFF_MS", "12"), ("SERVO_VERIFY_WRITES", "Always")]).unwrap();
+        assert_eq!(policy.attempts, 5);
+        assert_eq!(policy.backoff, Duration::from_millis(12));
+        assert_eq!(policy.verify, VerifyWrites::Always);
+        assert_eq!(with(&[("SERVO_VERIFY_WRITES", "0")]).unwrap().verify, VerifyWrites::Never);
+        assert_eq!(with(&[("SERVO_VERIFY_WRITES", "eeprom")]).unwrap().verify, VerifyWrites::Eeprom);
+
+        assert!(with(&[("SERVO_RETRIES", "0")]).is_err());
+        assert!(with(&[("SERVO_RETRIES", "many")]).is_err());
+        assert!(with(&[("SERVO_VERIFY_WRITES", "sometimes")]).is_err());
+    }
+
+    #[test]
+    fn verifies_no_addressing_writes() {
+        let policy = RetryPolicy { verify: VerifyWrites::Always, ..RetryPolicy::default() };
+        assert!(!policy.verifies(ServoRegister::ID));
+        assert!(!policy.verifies(ServoRegister::BaudRate));
+        assert!(policy.verifies(ServoRegister::TorqueSwitch));
+        assert!(RetryPolicy::default().verifies(ServoRegister::ReturnDelay));
+        assert!(!RetryPolicy::default().verifies(ServoRegister::TorqueSwitch));
+        assert_eq!(RetryPolicy::default().attempts_for(false), 1);
+        assert_eq!(RetryPolicy { retry_non_idempotent: true, ..RetryPolicy::default() }.attempts_for(false), 3);
+    }
+}
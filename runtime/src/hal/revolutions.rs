@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use crate::hal::{ServoData, ServoRegister};
+
+/// Where a continuously turning servo is: whole turns since counting started, plus the
+/// position within the current turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revolutions {
+    pub turns: i64,
+    /// Steps into the current turn, 0 to `resolution - 1`.
+    pub position: u16,
+    /// Steps per turn.
+    pub resolution: u16,
+}
+
+impl Revolutions {
+    /// Steps travelled since counting started, from position 0 of turn 0.
+    pub fn steps(&self) -> i64 {
+        self.turns * self.resolution as i64 + self.position as i64
+    }
+
+    pub fn degrees(&self) -> f64 {
+        self.steps() as f64 * 360.0 / self.resolution as f64
+    }
+}
+
+/// Counts turns from the position readings of each servo. A reading more than half a turn
+/// away from the previous one is taken as a wraparound, so each servo has to be observed
+/// more than twice per turn: at least every 0.6 s for an STS3215 at full speed.
+#[derive(Debug, Default)]
+pub struct RevolutionCounter {
+    servos: HashMap<u8, Revolutions>,
+}
+
+impl RevolutionCounter {
+    /// Takes a raw `CurrentLocation` reading of a servo with `resolution` steps per turn.
+    /// Readings beyond one turn, as step mode can report, are folded into a turn first.
+    pub fn observe(&mut self, id: u8, location: i16, resolution: u16) {
+        let location = ServoRegister::CurrentLocation.info().decode(location as u16);
+        let position = location.rem_euclid(resolution as i32) as u16;
+        let revolutions = self.servos.entry(id).or_insert(Revolutions { turns: 0, position, resolution });
+        if revolutions.resolution != resolution {
+            *revolutions = Revolutions { turns: 0, position, resolution };
+        }
+
+        let delta = position as i32 - revolutions.position as i32;
+        let half_turn = resolution as i32 / 2;
+        if delta > half_turn {
+            revolutions.turns -= 1;
+        } else if delta < -half_turn {
+            revolutions.turns += 1;
+        }
+        revolutions.position = position;
+    }
+
+    pub fn observe_data(&mut self, data: &ServoData, resolution: impl Fn(u8) -> u16) {
+        for (&id, info) in &data.servos {
+            self.observe(id, info.current_location, resolution(id));
+        }
+    }
+
+    pub fn get(&self, id: u8) -> Option<Revolutions> {
+        self.servos.get(&id).copied()
+    }
+
+    /// Starts counting again from turn 0 at the next reading.
+    pub fn reset(&mut self, id: u8) {
+        self.servos.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(id: u8, locations: &[i16]) -> RevolutionCounter {
+        let mut counter = RevolutionCounter::default();
+        for &location in locations {
+            counter.observe(id, location, 4096);
+        }
+        counter
+    }
+
+    #[test]
+    fn counts_wraps_forward_and_back() {
+        let forward = counter(1, &[3000, 4000, 500, 2000, 3500, 100]);
+        assert_eq!(forward.get(1), Some(Revolutions { turns: 2, position: 100, resolution: 4096 }));
+
+        let back = counter(1, &[100, 3900, 2000, 100, 3000]);
+        assert_eq!(back.get(1).unwrap().turns, -2);
+        assert_eq!(back.get(1).unwrap().steps(), -2 * 4096 + 3000);
+    }
+
+    #[test]
+    fn takes_half_a_turn_or_less_as_movement() {
+        // Exactly half a turn either way isn't a wrap
+        let counter = counter(1, &[0, 2048, 0, 2049]);
+        assert_eq!(counter.get(1).unwrap().turns, -1);
+    }
+
+    #[test]
+    fn folds_sign_magnitude_readings() {
+        // Step mode reports -100 as bit 15 plus the magnitude
+        let counter = counter(1, &[50, (0x8000u16 | 100) as i16]);
+        assert_eq!(counter.get(1), Some(Revolutions { turns: -1, position: 3996, resolution: 4096 }));
+    }
+
+    #[test]
+    fn counts_servos_separately_and_resets() {
+        let mut counter = counter(1, &[4000, 100]);
+        counter.observe(2, 100, 4096);
+        counter.observe(2, 4000, 4096);
+        assert_eq!(counter.get(1).unwrap().turns, 1);
+        assert_eq!(counter.get(2).unwrap().turns, -1);
+
+        counter.reset(1);
+        assert_eq!(counter.get(1), None);
+        counter.observe(1, 300, 4096);
+        assert_eq!(counter.get(1).unwrap().turns, 0);
+        assert_eq!(counter.get(1).unwrap().degrees(), 300.0 * 360.0 / 4096.0);
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::{Result, bail, Context};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use crate::hal::backup::is_restorable;
+use crate::hal::{RegisterChange, Servo, ServoBus, ServoRegister};
+
+const PID_REGISTERS: [ServoRegister; 3] = [
+    ServoRegister::PProportionalCoeff,
+    ServoRegister::IIntegralCoeff,
+    ServoRegister::DDifferentialCoeff,
+];
+
+/// Desired EEPROM settings, read from a TOML file by `sts_config`.
+///
+/// ```toml
+/// [defaults]
+/// pid = { p = 32, i = 0, d = 32 }
+///
+/// [servos.right_knee]
+/// id = 2
+/// angle_limits = [1024, 3072]
+///
+/// [servos.16]
+/// registers = { PositionCorrection = -12 }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServoConfig {
+    /// Applied to every servo listed in `servos`, which can override any of it.
+    #[serde(default)]
+    pub defaults: ServoSettings,
+    /// Keyed by servo ID, or by joint name with the ID given as `id`.
+    #[serde(default)]
+    pub servos: BTreeMap<String, ServoSettings>,
+}
+
+/// Settings for one servo; anything left out is not touched.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServoSettings {
+    pub id: Option<u8>,
+    pub pid: Option<PidGains>,
+    /// `MaxTorque`, the torque limit the servo starts with, in 0.1 %.
+    pub torque_limit: Option<u16>,
+    /// `ProtectionCurrent`, in 6.5 mA steps.
+    pub protection_current: Option<u16>,
+    /// `MinAngleLimit` and `MaxAngleLimit`, in steps.
+    pub angle_limits: Option<[u16; 2]>,
+    /// Any other EEPROM register by name, e.g. `PositionCorrection`.
+    #[serde(default)]
+    pub registers: BTreeMap<String, i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PidGains {
+    pub p: u8,
+    pub i: u8,
+    pub d: u8,
+}
+
+impl ServoConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// The register values wanted on each servo, by ID.
+    pub fn resolve(&self) -> Result<BTreeMap<u8, Vec<(ServoRegister, i32)>>> {
+        let defaults = self.defaults.registers().context("Invalid defaults")?;
+        let mut resolved = BTreeMap::new();
+        for (key, settings) in &self.servos {
+            let id = match (key.parse::<u8>(), settings.id) {
+                (Ok(id), None) => id,
+                (Ok(id), Some(other)) if id == other => id,
+                (Ok(id), Some(other)) => bail!("Servo {} is also given id = {}", id, other),
+                (Err(_), Some(id)) => id,
+                (Err(_), None) => bail!("Joint '{}' needs an id", key),
+            };
+
+            let mut wanted = defaults.clone();
+            for (register, value) in settings.registers().with_context(|| format!("Invalid settings for {}", key))? {
+                wanted.retain(|(other, _)| *other != register);
+                wanted.push((register, value));
+            }
+            if resolved.insert(id, wanted).is_some() {
+                bail!("Servo {} is configured twice", id);
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+impl ServoSettings {
+    fn registers(&self) -> Result<Vec<(ServoRegister, i32)>> {
+        let mut registers = Vec::new();
+        if let Some(pid) = self.pid {
+            registers.extend(PID_REGISTERS.into_iter().zip([pid.p, pid.i, pid.d].map(i32::from)));
+        }
+        if let Some(torque_limit) = self.torque_limit {
+            registers.push((ServoRegister::MaxTorque, torque_limit as i32));
+        }
+        if let Some(protection_current) = self.protection_current {
+            registers.push((ServoRegister::ProtectionCurrent, protection_current as i32));
+        }
+        if let Some([min, max]) = self.angle_limits {
+            registers.push((ServoRegister::MinAngleLimit, min as i32));
+            registers.push((ServoRegister::MaxAngleLimit, max as i32));
+        }
+        for (name, &value) in &self.registers {
+            let register = ServoRegister::from_name(name).with_context(|| format!("Unknown register '{}'", name))?;
+            if !is_restorable(register) {
+                bail!("{} can't be configured, only writable EEPROM registers other than ID and BaudRate can", name);
+            }
+            if registers.iter().any(|(other, _)| *other == register) {
+                bail!("{} is set twice", name);
+            }
+            registers.push((register, value));
+        }
+        for &(register, value) in &registers {
+            register.info().encode(value)?;
+        }
+        Ok(registers)
+    }
+}
+
+impl Servo {
+    /// Reads every configured servo and lists the registers that differ from `config`.
+    pub fn plan(&self, config: &ServoConfig) -> Result<Vec<RegisterChange>> {
+        let mut changes = Vec::new();
+        for (id, wanted) in config.resolve()? {
+            let live = self.read_eeprom(id).with_context(|| format!("Failed to read servo {}", id))?;
+            for (register, wanted) in wanted {
+                self.profile(id).validate(register, wanted)?;
+                let live = live.iter().find(|(other, _)| *other == register).map_or(0, |&(_, value)| value);
+                if live != wanted {
+                    changes.push(RegisterChange { id, register, live, wanted });
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Writes the changes from `plan`, one servo at a time. PID gains go through `set_pid`,
+    /// everything else through [`write_eeprom`](Servo::write_eeprom).
+    pub fn apply(&self, changes: &[RegisterChange]) -> Result<()> {
+        let mut ids: Vec<u8> = changes.iter().map(|change| change.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        for id in ids {
+            let changes: Vec<_> = changes.iter().filter(|change| change.id == id).collect();
+
+            if changes.iter().any(|change| PID_REGISTERS.contains(&change.register)) {
+                let (mut p, mut i, mut d) = self.read_pid(id)?;
+                for change in &changes {
+                    match change.register {
+                        ServoRegister::PProportionalCoeff => p = change.wanted as u8,
+                        ServoRegister::IIntegralCoeff => i = change.wanted as u8,
+                        ServoRegister::DDifferentialCoeff => d = change.wanted as u8,
+                        _ => {}
+                    }
+                }
+                self.set_pid(id, p, i, d).with_context(|| format!("Failed to set PID of servo {}", id))?;
+            }
+
+            let others: Vec<RegisterChange> = changes.iter()
+                .filter(|change| !PID_REGISTERS.contains(&change.register))
+                .map(|&&change| change)
+                .collect();
+            self.write_eeprom(&others).with_context(|| format!("Failed to configure servo {}", id))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal_sim;
+
+    fn config(text: &str) -> ServoConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn overrides_defaults_per_servo() {
+        let resolved = config("
+            [defaults]
+            pid = { p = 32, i = 0, d = 32 }
+            torque_limit = 800
+
+            [servos.right_knee]
+            id = 2
+            pid = { p = 20, i = 1, d = 10 }
+
+            [servos.3]
+            registers = { MaxTorque = 500, PositionCorrection = -12 }
+        ").resolve().unwrap();
+
+        assert_eq!(resolved.keys().copied().collect::<Vec<_>>(), [2, 3]);
+        let value = |id: u8, register| resolved[&id].iter().find(|(other, _)| *other == register).map(|&(_, value)| value);
+        assert_eq!(value(2, ServoRegister::PProportionalCoeff), Some(20));
+        assert_eq!(value(2, ServoRegister::IIntegralCoeff), Some(1));
+        assert_eq!(value(2, ServoRegister::MaxTorque), Some(800));
+        assert_eq!(value(2, ServoRegister::PositionCorrection), None);
+        assert_eq!(value(3, ServoRegister::PProportionalCoeff), Some(32));
+        assert_eq!(value(3, ServoRegister::MaxTorque), Some(500));
+        assert_eq!(value(3, ServoRegister::PositionCorrection), Some(-12));
+        assert_eq!(resolved[&3].len(), 5);
+    }
+
+    #[test]
+    fn refuses_conflicting_ids() {
+        let error = |text| format!("{:#}", config(text).resolve().unwrap_err());
+        assert_eq!(error("[servos.3]\nid = 4"), "Servo 3 is also given id = 4");
+        assert_eq!(error("[servos.knee]\ntorque_limit = 500"), "Joint 'knee' needs an id");
+        assert_eq!(error("[servos.3]\n[servos.knee]\nid = 3"), "Servo 3 is configured twice");
+        assert!(config("[servos.3]\nid = 3").resolve().is_ok());
+    }
+
+    #[test]
+    fn refuses_registers_that_cant_be_configured() {
+        let error = |text| format!("{:#}", config(text).resolve().unwrap_err());
+        assert!(error("[servos.1]\nregisters = { Speed = 1 }").contains("Unknown register 'Speed'"));
+        assert!(error("[servos.1]\nregisters = { ID = 2 }").contains("ID can't be configured"));
+        assert!(error("[servos.1]\nregisters = { TargetLocation = 2 }").contains("TargetLocation can't be configured"));
+        assert!(error("[servos.1]\ntorque_limit = 500\nregisters = { MaxTorque = 500 }").contains("MaxTorque is set twice"));
+        assert!(error("[defaults]\nregisters = { MaxTorque = 70000 }").starts_with("Invalid defaults"));
+    }
+
+    #[test]
+    fn applies_only_what_differs() {
+        let servo = Servo::with_bus(hal_sim::Servo::new([1]));
+        let config = config("
+            [servos.1]
+            pid = { p = 20, i = 0, d = 32 }
+            angle_limits = [1024, 3072]
+        ");
+
+        let changes = servo.plan(&config).unwrap();
+        assert_eq!(changes, [
+            RegisterChange { id: 1, register: ServoRegister::PProportionalCoeff, live: 32, wanted: 20 },
+            RegisterChange { id: 1, register: ServoRegister::MinAngleLimit, live: 0, wanted: 1024 },
+            RegisterChange { id: 1, register: ServoRegister::MaxAngleLimit, live: 4095, wanted: 3072 },
+        ]);
+        servo.apply(&changes).unwrap();
+        assert!(servo.plan(&config).unwrap().is_empty());
+        assert_eq!(servo.read_reg::<u8>(1, ServoRegister::LockMark).unwrap(), 1);
+    }
+}
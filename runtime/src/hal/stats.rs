@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use crate::hal::ServoError;
+
+/// Upper bounds of the latency histogram buckets, in microseconds. Replies slower than
+/// the last bound go in one extra bucket.
+pub const LATENCY_BUCKETS_US: [u64; 9] = [100, 250, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000];
+
+/// Round-trip times of the replies from one servo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Replies per bucket of [`LATENCY_BUCKETS_US`], then the overflow bucket.
+    pub buckets: [u64; LATENCY_BUCKETS_US.len() + 1],
+    pub total_us: u64,
+    pub max_us: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US.iter().position(|&bound| us <= bound).unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket] += 1;
+        self.total_us += us;
+        self.max_us = self.max_us.max(us);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| Duration::from_micros(self.total_us / count))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        (self.count() > 0).then(|| Duration::from_micros(self.max_us))
+    }
+
+    /// Upper bound of the bucket holding quantile `q` (0 to 1), or the maximum if that
+    /// is lower or the quantile falls in the overflow bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let bound = LATENCY_BUCKETS_US.get(bucket).copied().unwrap_or(self.max_us);
+                return Some(Duration::from_micros(bound.min(self.max_us)));
+            }
+        }
+        self.max()
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, n) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += n;
+        }
+        self.total_us += other.total_us;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+}
+
+/// Traffic with one servo since the counters were last cleared.
+///
+/// Timeouts point at a missing servo, a broken cable or the wrong baud rate, checksum and
+/// length errors at noise on the line, and ID mismatches at two servos sharing an ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServoStats {
+    /// Requests that expected a reply from this servo.
+    pub transactions: u64,
+    pub timeouts: u64,
+    pub checksum_errors: u64,
+    /// Replies from another servo while this one was expected.
+    pub id_mismatches: u64,
+    /// Replies that ended early or had the wrong length.
+    pub truncated: u64,
+    /// Requests addressed to this servo; broadcasts are only counted in [`BusStats`].
+    pub bytes_sent: u64,
+    /// Valid replies from this servo.
+    pub bytes_received: u64,
+    /// Time from sending the request to the end of the reply.
+    pub latency: LatencyHistogram,
+    /// Operations the facade tried again, see [`RetryPolicy`](crate::hal::RetryPolicy).
+    pub retries: u64,
+    /// Writes the servo acknowledged but that read back differently.
+    pub verify_failures: u64,
+}
+
+impl ServoStats {
+    /// Failed transactions; verification failures are counted separately.
+    pub fn errors(&self) -> u64 {
+        self.timeouts + self.checksum_errors + self.id_mismatches + self.truncated
+    }
+
+    /// Fraction of transactions that failed.
+    pub fn error_rate(&self) -> f64 {
+        if self.transactions == 0 {
+            return 0.0;
+        }
+        self.errors() as f64 / self.transactions as f64
+    }
+
+    /// Counts a failed transaction. Errors the servo reports itself are faults, not bus
+    /// errors, and are left to [`FaultLog`](crate::hal::FaultLog).
+    pub fn record_error(&mut self, error: &ServoError) {
+        match error {
+            ServoError::Timeout { .. } => self.timeouts += 1,
+            ServoError::ChecksumMismatch { .. } => self.checksum_errors += 1,
+            ServoError::IdMismatch { .. } => self.id_mismatches += 1,
+            ServoError::TruncatedPacket { .. } => self.truncated += 1,
+            ServoError::VerifyFailed { .. } => self.verify_failures += 1,
+            ServoError::Status { .. } | ServoError::InvalidInput(_) | ServoError::Io(_) => {}
+        }
+    }
+
+    pub fn merge(&mut self, other: &ServoStats) {
+        self.transactions += other.transactions;
+        self.timeouts += other.timeouts;
+        self.checksum_errors += other.checksum_errors;
+        self.id_mismatches += other.id_mismatches;
+        self.truncated += other.truncated;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.latency.merge(&other.latency);
+        self.retries += other.retries;
+        self.verify_failures += other.verify_failures;
+    }
+}
+
+/// Transaction counters per servo, as kept by backends that talk to the wire, and the
+/// retries the facade added on top. See [`ServoBus::bus_stats`](crate::hal::ServoBus::bus_stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BusStats {
+    pub servos: BTreeMap<u8, ServoStats>,
+    /// Everything written, broadcasts included.
+    pub bytes_sent: u64,
+    /// Everything read, including noise and echoes of our own requests.
+    pub bytes_received: u64,
+}
+
+impl BusStats {
+    pub fn get(&self, id: u8) -> Option<&ServoStats> {
+        self.servos.get(&id)
+    }
+
+    pub fn servo(&mut self, id: u8) -> &mut ServoStats {
+        self.servos.entry(id).or_default()
+    }
+
+    pub fn merge(&mut self, other: &BusStats) {
+        for (&id, stats) in &other.servos {
+            self.servo(id).merge(stats);
+        }
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Result, Context};
+use std::collections::HashMap;
+use std::fmt;
+use crate::hal::{parse_servo_ids, ServoInfo, ServoProfile, ServoRegister};
+
+macro_rules! quantity {
+    ($(#[$doc:meta])* $name:ident, $from:ident, $value:ident, $format:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+        pub struct $name(f32);
+
+        impl $name {
+            pub fn $from(value: f32) -> Self {
+                $name(value)
+            }
+
+            pub fn $value(self) -> f32 {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, $format, self.0)
+            }
+        }
+    };
+}
+
+quantity!(
+    /// An angle in radians.
+    Angle, from_radians, radians, "{:.3} rad"
+);
+quantity!(
+    /// An angular velocity in radians per second.
+    AngularVelocity, from_radians_per_second, radians_per_second, "{:.3} rad/s"
+);
+quantity!(
+    /// An angular acceleration in radians per second squared.
+    AngularAcceleration, from_radians_per_second_squared, radians_per_second_squared, "{:.3} rad/s²"
+);
+quantity!(Current, from_amps, amps, "{:.3} A");
+quantity!(Voltage, from_volts, volts, "{:.1} V");
+quantity!(Temperature, from_celsius, celsius, "{:.0} °C");
+quantity!(
+    /// Torque output as a percentage of the maximum, negative when pushing the other way.
+    Load, from_percent, percent, "{:+.1} %"
+);
+
+impl Angle {
+    pub fn from_degrees(degrees: f32) -> Self {
+        Angle(degrees.to_radians())
+    }
+
+    pub fn degrees(self) -> f32 {
+        self.0.to_degrees()
+    }
+}
+
+impl AngularVelocity {
+    pub fn from_degrees_per_second(degrees: f32) -> Self {
+        AngularVelocity(degrees.to_radians())
+    }
+
+    pub fn degrees_per_second(self) -> f32 {
+        self.0.to_degrees()
+    }
+}
+
+impl AngularAcceleration {
+    pub fn from_degrees_per_second_squared(degrees: f32) -> Self {
+        AngularAcceleration(degrees.to_radians())
+    }
+
+    pub fn degrees_per_second_squared(self) -> f32 {
+        self.0.to_degrees()
+    }
+}
+
+impl Current {
+    pub fn milliamps(self) -> f32 {
+        self.0 * 1000.0
+    }
+}
+
+/// Converts the registers of one servo to physical units and back, scaled by its model and
+/// with angles measured from its zero: the center of its range, moved by `offset` steps.
+#[derive(Debug, Clone, Copy)]
+pub struct ServoUnits {
+    pub profile: &'static ServoProfile,
+    pub offset: i32,
+}
+
+impl ServoUnits {
+    pub fn new(profile: &'static ServoProfile) -> Self {
+        ServoUnits { profile, offset: 0 }
+    }
+
+    pub fn with_offset(self, offset: i32) -> Self {
+        ServoUnits { offset, ..self }
+    }
+
+    fn radians_per_step(&self) -> f32 {
+        (self.profile.range_degrees / self.profile.resolution as f32).to_radians()
+    }
+
+    fn zero(&self) -> i32 {
+        (self.profile.resolution / 2) as i32 + self.offset
+    }
+
+    /// A position register (`CurrentLocation`, `TargetLocation` or an angle limit) as an angle.
+    pub fn position(&self, raw: i16) -> Angle {
+        let steps = ServoRegister::CurrentLocation.info().decode(raw as u16);
+        Angle((steps - self.zero()) as f32 * self.radians_per_step())
+    }
+
+    /// Position register value for `angle`, limited to the servo's range.
+    pub fn position_raw(&self, angle: Angle) -> i16 {
+        let steps = (angle.0 / self.radians_per_step()).round() as i32 + self.zero();
+        steps.clamp(0, self.profile.resolution as i32 - 1) as i16
+    }
+
+    /// `CurrentSpeed` or a wheel-mode `RunningSpeed` as a velocity.
+    pub fn velocity(&self, raw: i16) -> AngularVelocity {
+        let steps = ServoRegister::CurrentSpeed.info().decode(raw as u16);
+        AngularVelocity(steps as f32 * self.profile.speed_lsb * self.radians_per_step())
+    }
+
+    /// Signed speed for `velocity` in steps per second, as `set_wheel_velocity` takes it.
+    /// Position moves take the magnitude as their speed limit.
+    pub fn velocity_raw(&self, velocity: AngularVelocity) -> i16 {
+        let steps = velocity.0 / (self.profile.speed_lsb * self.radians_per_step());
+        steps.round().clamp(-0x7FFF as f32, 0x7FFF as f32) as i16
+    }
+
+    /// `Acceleration` register value for `acceleration`, between 1 and 254. Rounds up so
+    /// ramps never take longer than asked.
+    pub fn acceleration_raw(&self, acceleration: AngularAcceleration) -> u8 {
+        let steps = acceleration.0.abs() / (self.profile.acceleration_lsb * self.radians_per_step());
+        steps.ceil().clamp(1.0, 254.0) as u8
+    }
+
+    pub fn load(&self, raw: i16) -> Load {
+        Load(ServoRegister::CurrentLoad.info().decode(raw as u16) as f32 / 10.0)
+    }
+
+    pub fn voltage(&self, raw: u8) -> Voltage {
+        Voltage(raw as f32 * self.profile.voltage_lsb)
+    }
+
+    pub fn temperature(&self, raw: u8) -> Temperature {
+        Temperature(raw as f32)
+    }
+
+    /// `None` for models without current sensing.
+    pub fn current(&self, raw: u16) -> Option<Current> {
+        self.profile.current_lsb_ma.map(|lsb| Current(raw as f32 * lsb / 1000.0))
+    }
+
+    pub fn readout(&self, info: &ServoInfo) -> Readout {
+        Readout {
+            position: self.position(info.current_location),
+            target: self.position(info.target_location),
+            velocity: self.velocity(info.current_speed),
+            load: self.load(info.current_load),
+            voltage: self.voltage(info.current_voltage),
+            temperature: self.temperature(info.current_temperature),
+            current: self.current(info.current_current),
+        }
+    }
+}
+
+/// Parses position offsets in steps such as `1-4:-12;7:40`, for joints whose neutral pose
+/// isn't at the center of the servo's range.
+pub fn parse_servo_offsets(spec: &str) -> Result<HashMap<u8, i32>> {
+    let mut offsets = HashMap::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (ids, offset) = entry.rsplit_once(':').with_context(|| format!("Expected IDS:STEPS, got '{}'", entry))?;
+        let offset: i32 = offset.trim().parse().with_context(|| format!("Invalid offset '{}'", offset))?;
+        // A bare number is one ID here, not a count
+        let ids = match ids.trim().parse::<u8>() {
+            Ok(id) => vec![id],
+            Err(_) => parse_servo_ids(ids)?,
+        };
+        for id in ids {
+            offsets.insert(id, offset);
+        }
+    }
+    Ok(offsets)
+}
+
+/// The feedback registers of a [`ServoInfo`] in physical units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Readout {
+    pub position: Angle,
+    pub target: Angle,
+    pub velocity: AngularVelocity,
+    pub load: Load,
+    pub voltage: Voltage,
+    pub temperature: Temperature,
+    pub current: Option<Current>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::STS3215;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn measures_positions_from_the_zero() {
+        let units = ServoUnits::new(&STS3215);
+        assert_eq!(units.position(2048).degrees(), 0.0);
+        assert!(close(units.position(3072).degrees(), 90.0));
+        // Step mode can report past the end of the range, in sign-magnitude
+        assert!(close(units.position((0x8000u16 | 2048) as i16).degrees(), -360.0));
+
+        let offset = units.with_offset(100);
+        assert_eq!(offset.position(2148).degrees(), 0.0);
+        assert_eq!(offset.position_raw(Angle::from_degrees(0.0)), 2148);
+    }
+
+    #[test]
+    fn limits_positions_to_the_range() {
+        let units = ServoUnits::new(&STS3215);
+        assert_eq!(units.position_raw(Angle::from_degrees(90.0)), 3072);
+        assert_eq!(units.position_raw(Angle::from_degrees(-90.0)), 1024);
+        assert_eq!(units.position_raw(Angle::from_degrees(200.0)), 4095);
+        assert_eq!(units.position_raw(Angle::from_degrees(-200.0)), 0);
+    }
+
+    #[test]
+    fn converts_speeds_and_accelerations() {
+        let units = ServoUnits::new(&STS3215);
+        assert!(close(units.velocity(4096).degrees_per_second(), 360.0));
+        assert!(close(units.velocity((0x8000u16 | 1024) as i16).degrees_per_second(), -90.0));
+        assert_eq!(units.velocity_raw(AngularVelocity::from_degrees_per_second(-90.0)), -1024);
+
+        // 4096 steps/s² is 40.96 LSB, rounded up
+        assert_eq!(units.acceleration_raw(AngularAcceleration::from_degrees_per_second_squared(360.0)), 41);
+        assert_eq!(units.acceleration_raw(AngularAcceleration::from_degrees_per_second_squared(0.0)), 1);
+        assert_eq!(units.acceleration_raw(AngularAcceleration::from_degrees_per_second_squared(1e6)), 254);
+    }
+
+    #[test]
+    fn converts_feedback() {
+        let units = ServoUnits::new(&STS3215);
+        assert!(close(units.load((0x0400 | 500) as i16).percent(), -50.0));
+        assert!(close(units.voltage(120).volts(), 12.0));
+        assert!(close(units.current(100).unwrap().amps(), 0.65));
+        assert_eq!(units.temperature(40).celsius(), 40.0);
+    }
+
+    #[test]
+    fn parses_offsets() {
+        let offsets = parse_servo_offsets("1-2:-12; 7:40").unwrap();
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(offsets[&1], -12);
+        assert_eq!(offsets[&2], -12);
+        assert_eq!(offsets[&7], 40);
+        assert!(parse_servo_offsets("3").is_err());
+        assert!(parse_servo_offsets("3:x").is_err());
+    }
+}
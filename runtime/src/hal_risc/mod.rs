@@ -3,11 +3,31 @@ use std::os::raw::{c_int, c_short, c_uchar, c_ushort, c_uint};
 use std::error::Error;
 use i2cdev::linux::LinuxI2CDevice;
 use i2cdev::core::I2CDevice;
-use crate::hal::{ServoInfo, ServoData, ServoMultipleWriteCommand, ServoMode, ServoDirection, ServoRegister, MemoryLockState, TorqueMode, IMUData, MAX_SERVOS};
+use crate::hal::{ServoBus, ImuSource, ServoInfo, ServoData, ServoMultipleWriteCommand, ServoTarget, StagedMove, ServoMode, ServoDirection, ServoRegister, IMUData};
 use std::sync::{Arc, Mutex};
 use std::fmt;
 use crate::hal_risc::qmi8658::QMI8658;
 
+// The firmware reads out and commands a fixed table of servos 1 to 16
+const MAX_SERVOS: usize = 16;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct RawServoData {
+    servo: [ServoInfo; MAX_SERVOS],
+    task_run_count: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct RawServoMultipleWriteCommand {
+    only_write_positions: c_uchar,
+    ids: [c_uchar; MAX_SERVOS],
+    positions: [c_short; MAX_SERVOS],
+    times: [c_ushort; MAX_SERVOS],
+    speeds: [c_ushort; MAX_SERVOS],
+}
+
 #[link(name = "sts3215")]
 extern "C" {
     fn servo_init() -> c_int;
@@ -22,13 +42,14 @@ extern "C" {
     fn set_servo_mode(id: c_uchar, mode: c_uchar) -> c_int;
     fn set_servo_speed(id: c_uchar, speed: c_ushort, direction: c_int) -> c_int;
     fn servo_read_info(id: c_uchar, info: *mut ServoInfo) -> c_int;
-    fn read_servo_positions(servo_data: *mut ServoData) -> c_int;
-    fn servo_write_multiple(cmd: *const ServoMultipleWriteCommand) -> c_int;
+    fn read_servo_positions(servo_data: *mut RawServoData) -> c_int;
+    fn servo_write_multiple(cmd: *const RawServoMultipleWriteCommand) -> c_int;
 }
 
 #[derive(Debug)]
 pub struct Servo {
-    _private: (), // Prevent direct construction
+    // The firmware can't send REG_WRITE, so staged moves wait here for `action`
+    staged: Mutex<Vec<StagedMove>>,
 }
 
 impl Servo {
@@ -37,10 +58,12 @@ impl Servo {
         if result != 0 {
             anyhow::bail!("Failed to initialize servo");
         }
-        Ok(Servo { _private: () })
+        Ok(Servo { staged: Mutex::new(Vec::new()) })
     }
+}
 
-    pub fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+impl ServoBus for Servo {
+    fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
         let _result = unsafe { servo_write(id, register.clone() as u8, data.as_ptr(), data.len() as c_uchar) };
         let result = unsafe { servo_write(id, register as u8, data.as_ptr(), data.len() as c_uchar) };
 
@@ -50,7 +73,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+    fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
         let mut data = vec![0u8; length as usize];
         let result = unsafe { servo_read(id, register as u8, length, data.as_mut_ptr()) };
         if result != 0 {
@@ -59,7 +82,7 @@ impl Servo {
         Ok(data)
     }
 
-    pub fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
+    fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
         let result = unsafe { servo_move(id, position, time, speed) };
         if result != 0 {
             anyhow::bail!("Failed to move servo");
@@ -67,7 +90,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn enable_readout(&self) -> Result<()> {
+    fn enable_readout(&self) -> Result<()> {
         let result = unsafe { enable_servo_readout() };
         if result != 0 {
             anyhow::bail!("Failed to enable servo readout");
@@ -75,7 +98,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn disable_readout(&self) -> Result<()> {
+    fn disable_readout(&self) -> Result<()> {
         let result = unsafe { disable_servo_readout() };
         if result != 0 {
             anyhow::bail!("Failed to disable servo readout");
@@ -83,7 +106,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn enable_movement(&self) -> Result<()> {
+    fn enable_movement(&self) -> Result<()> {
         let result = unsafe { enable_servo_movement() };
         if result != 0 {
             anyhow::bail!("Failed to enable servo movement");
@@ -91,7 +114,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn disable_movement(&self) -> Result<()> {
+    fn disable_movement(&self) -> Result<()> {
         let result = unsafe { disable_servo_movement() };
         if result != 0 {
             anyhow::bail!("Failed to disable servo movement");
@@ -99,7 +122,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn set_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
+    fn set_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
         let result = unsafe { set_servo_mode(id, mode as u8) };
         if result != 0 {
             anyhow::bail!("Failed to set servo mode");
@@ -107,7 +130,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn set_speed(&self, id: u8, speed: u16, direction: ServoDirection) -> Result<()> {
+    fn set_speed(&self, id: u8, speed: u16, direction: ServoDirection) -> Result<()> {
         let direction = if direction == ServoDirection::Clockwise { 1 } else { -1 };
         let result = unsafe { set_servo_speed(id, speed, direction as i32) };
         if result != 0 {
@@ -116,7 +139,7 @@ impl Servo {
         Ok(())
     }
 
-    pub fn read_info(&self, id: u8) -> Result<ServoInfo> {
+    fn read_info(&self, id: u8) -> Result<ServoInfo> {
         let mut info = ServoInfo {
             torque_switch: 0,
             acceleration: 0,
@@ -144,118 +167,95 @@ impl Servo {
         Ok(info)
     }
 
-    pub fn read_continuous(&self) -> Result<ServoData> {
-        let mut data = ServoData {
-            servo: [ServoInfo {
-                torque_switch: 0,
-                acceleration: 0,
-                target_location: 0,
-                running_time: 0,
-                running_speed: 0,
-                torque_limit: 0,
-                reserved1: [0; 6],
-                lock_mark: 0,
-                current_location: 0,
-                current_speed: 0,
-                current_load: 0,
-                current_voltage: 0,
-                current_temperature: 0,
-                async_write_flag: 0,
-                servo_status: 0,
-                mobile_sign: 0,
-                reserved2: [0; 2],
-                current_current: 0,
-            }; MAX_SERVOS],
+    fn read_continuous(&self) -> Result<ServoData> {
+        let mut data = RawServoData {
+            servo: [ServoInfo::default(); MAX_SERVOS],
             task_run_count: 0,
         };
         let result = unsafe { read_servo_positions(&mut data) };
         if result != 0 {
             anyhow::bail!("Failed to read continuous servo data");
         }
-        Ok(data)
-    }
 
-    pub fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
-        let result = unsafe { servo_write_multiple(cmd) };
-        if result != 0 {
-            anyhow::bail!("Failed to write multiple servo positions");
-        }
-        Ok(())
-    }
-
-    pub fn read_pid(&self, id: u8) -> Result<(u8, u8, u8)> {
-        let p = self.read(id, ServoRegister::PProportionalCoeff, 1)?[0];
-        let i = self.read(id, ServoRegister::IIntegralCoeff, 1)?[0];
-        let d = self.read(id, ServoRegister::DDifferentialCoeff, 1)?[0];
-        Ok((p, i, d))
+        // Empty slots have no servo behind them; a powered servo always reports its supply
+        let servos = data.servo.iter()
+            .enumerate()
+            .filter(|(_, info)| info.current_voltage != 0)
+            .map(|(i, info)| (i as u8 + 1, *info))
+            .collect();
+        Ok(ServoData { servos, task_run_count: data.task_run_count })
     }
 
-    pub fn set_pid(&self, id: u8, p: u8, i: u8, d: u8) -> Result<()> {
-        // Unlock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Unlocked as u8])?;
-
-        // Set PID parameters
-        self.write(id, ServoRegister::PProportionalCoeff, &[p])?;
-        self.write(id, ServoRegister::IIntegralCoeff, &[i])?;
-        self.write(id, ServoRegister::DDifferentialCoeff, &[d])?;
-
-        // Lock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Locked as u8])?;
-
-        Ok(())
+    fn ids(&self) -> Vec<u8> {
+        (1..=MAX_SERVOS as u8).collect()
     }
 
-    pub fn set_memory_lock(&self, id: u8, state: MemoryLockState) -> Result<()> {
-        self.write(id, ServoRegister::LockMark, &[state as u8])
-    }
+    fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
+        if cmd.targets.len() > MAX_SERVOS || cmd.targets.iter().any(|target| target.id as usize > MAX_SERVOS) {
+            anyhow::bail!("The MilkV firmware only drives servos 1 to {}", MAX_SERVOS);
+        }
 
-    pub fn read_angle_limits(&self, id: u8) -> Result<(i16, i16)> {
-        let min_limit = i16::from_le_bytes(self.read(id, ServoRegister::MinAngleLimit, 2)?.try_into().unwrap());
-        let max_limit = i16::from_le_bytes(self.read(id, ServoRegister::MaxAngleLimit, 2)?.try_into().unwrap());
-        Ok((min_limit, max_limit))
-    }
+        let Some(&first) = cmd.targets.first() else { return Ok(()) };
 
-    pub fn set_torque_mode(&self, id: u8, mode: TorqueMode) -> Result<()> {
-        self.write(id, ServoRegister::TorqueSwitch, &[mode as u8])
-    }
+        // The firmware's sync write starts at the target, so accelerations go out one by one
+        if cmd.write_accelerations && !cmd.only_write_positions {
+            for target in &cmd.targets {
+                self.write(target.id, ServoRegister::Acceleration, &[target.acceleration])?;
+            }
+        }
 
-    pub fn write_servo_memory(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
-        let data = [(value & 0xFF) as u8, ((value >> 8) & 0xFF) as u8];
-        self.write(id, register, &data)
-    }
+        // The firmware sends all 16 entries, so unused ones repeat the first target
+        let mut raw = RawServoMultipleWriteCommand {
+            only_write_positions: cmd.only_write_positions as c_uchar,
+            ids: [first.id; MAX_SERVOS],
+            positions: [first.position; MAX_SERVOS],
+            times: [first.time; MAX_SERVOS],
+            speeds: [first.speed; MAX_SERVOS],
+        };
+        for (i, target) in cmd.targets.iter().enumerate() {
+            raw.ids[i] = target.id;
+            raw.positions[i] = target.position;
+            raw.times[i] = target.time;
+            raw.speeds[i] = target.speed;
+        }
 
-    pub fn scan(&self, id: u8) -> Result<bool> {
-        // Try to read the servo ID from memory address 0x5 (ServoRegister::ID)
-        match self.read(id, ServoRegister::ID, 1) {
-            Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
-            Ok(_) => Ok(false), // Received data, but it doesn't match the ID
-            Err(_) => Ok(false), // No response, assume no servo at this ID
+        let result = unsafe { servo_write_multiple(&raw) };
+        if result != 0 {
+            anyhow::bail!("Failed to write multiple servo positions");
         }
+        Ok(())
     }
 
-    pub fn degrees_to_raw(degrees: f32) -> u16 {
-        // Ensure the input is within the valid range
-        let clamped_degrees = degrees.max(-180.0).min(180.0);
-        
-        // Convert degrees to raw value
-        let raw = (clamped_degrees + 180.0) / 360.0 * 4096.0;
-        
-        // Round to nearest integer and ensure it's within the valid range
-        raw.round().max(0.0).min(4095.0) as u16
+    fn stage_move(&self, staged: &StagedMove) -> Result<()> {
+        if staged.id as usize > MAX_SERVOS {
+            anyhow::bail!("The MilkV firmware only drives servos 1 to {}", MAX_SERVOS);
+        }
+        let mut queue = self.staged.lock().unwrap();
+        queue.retain(|queued| queued.id != staged.id);
+        queue.push(*staged);
+        Ok(())
     }
 
-    pub fn raw_to_degrees(raw: u16) -> f32 {
-        // Ensure the input is within the valid range
-        let clamped_raw = raw.max(0).min(4095);
-        
-        // Convert raw value to degrees
-        let degrees = (clamped_raw as f32 / 4096.0) * 360.0 - 180.0;
-        
-        // Round to two decimal places
-        (degrees * 100.0).round() / 100.0;
-
-        // clamp to -180.0 to 180.0
-        degrees.max(-180.0).min(180.0)
+    /// Writes the staged accelerations, then starts every staged move with one SYNC_WRITE.
+    /// Like `write_multiple`, this is dropped by the firmware while movement is disabled.
+    /// The moves stay staged if the write fails, so `action` can be retried.
+    fn action(&self) -> Result<()> {
+        let mut staged = self.staged.lock().unwrap();
+        self.write_multiple(&ServoMultipleWriteCommand {
+            only_write_positions: false,
+            write_accelerations: true,
+            targets: staged.iter()
+                .map(|queued| ServoTarget {
+                    id: queued.id,
+                    position: queued.position,
+                    time: queued.time,
+                    speed: queued.speed,
+                    acceleration: queued.acceleration,
+                })
+                .collect(),
+        })?;
+        staged.clear();
+        Ok(())
     }
 }
 
@@ -279,7 +279,10 @@ impl IMU {
         })
     }
 
-    pub fn read_data(&self) -> Result<IMUData> {
+}
+
+impl ImuSource for IMU {
+    fn read_data(&self) -> Result<IMUData> {
         let mut qmi = self.qmi.lock().unwrap();
         let data = qmi.read_data()
             .map_err(|e| anyhow::anyhow!("Failed to read QMI8658 data: {}", e))?;
@@ -1,16 +1,31 @@
-use serialport::SerialPort;
-use std::time::Duration;
-use std::io::{Read, Write};
-use anyhow::{Result, bail, Context};
+use serialport::{SerialPort, ClearBuffer};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::io::{ErrorKind, Read, Write};
+use anyhow::{Result, Context, bail};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::sync::Mutex;
-use crate::hal::{ServoInfo, ServoRegister, ServoData, ServoMultipleWriteCommand, TorqueMode, ServoMode, ServoDirection, MemoryLockState, IMUData, MAX_SERVOS};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use crate::hal::{baud_rate_code, parse_servo_ids, BusStats, Capture, Direction, ServoBus, MemoryLockState, BAUD_RATES, EEPROM_WRITE_DELAY, ServoError, ServoFaults, ImuSource, ServoInfo, ServoRegister, ServoData, ServoMultipleWriteCommand, ServoTarget, StagedMove, IMUData, SERVO_INFO_LENGTH, SERVO_FEEDBACK_LENGTH};
 use std::env;
 
+mod replay;
+
+pub use replay::{Replay, ReplayPort};
+
 // Constants
 const SERVO_START_BYTE: u8 = 0xFF;
 const SERVO_BROADCAST_ID: u8 = 0xFE;
 const MAX_SERVO_COMMAND_DATA: usize = 256;
+const MAX_SYNC_READ_IDS: usize = MAX_SERVO_COMMAND_DATA - 6;
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_millis(30);
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+// Servos 1 to this are polled when SERVO_IDS is not set
+const DEFAULT_SERVO_COUNT: u8 = 16;
+// Reply timeout while probing rates; at the right rate servos answer well within it
+const PROBE_TIMEOUT: Duration = Duration::from_millis(5);
 
 // Servo commands
 const SERVO_CMD_PING: u8 = 0x01;
@@ -18,11 +33,13 @@ const SERVO_CMD_READ: u8 = 0x02;
 const SERVO_CMD_WRITE: u8 = 0x03;
 const SERVO_CMD_REG_WRITE: u8 = 0x04;
 const SERVO_CMD_ACTION: u8 = 0x05;
+const SERVO_CMD_SYNC_READ: u8 = 0x82;
 const SERVO_CMD_SYNC_WRITE: u8 = 0x83;
 const SERVO_CMD_RESET: u8 = 0x06;
 
 // Memory addresses
 const SERVO_ADDR_TORQUE_SWITCH: u8 = 0x28;
+const SERVO_ADDR_ACCELERATION: u8 = 0x29;
 const SERVO_ADDR_TARGET_POSITION: u8 = 0x2A;
 const SERVO_ADDR_CURRENT_POSITION: u8 = 0x38;
 const SERVO_ADDR_CURRENT_LOAD: u8 = 0x3C;
@@ -44,17 +61,138 @@ pub struct ServoCommand {
 #[derive(Debug)]
 pub struct ServoSerial {
     port: Box<dyn SerialPort>,
+    port_name: String,
+    baud_rate: u32,
+    reply_timeout: Duration,
+    last_sent: Vec<u8>,
+    sent_at: Instant,
+    stats: BusStats,
+    capture: Option<Arc<Capture>>,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum FrameState {
+    #[default]
+    Header,
+    SecondHeader,
+    Id,
+    Length,
+    Body(usize),
+}
 
+/// Incremental framer for status packets. Hunts for the `0xFF 0xFF` header, then
+/// collects ID, length and body; anything that doesn't fit is skipped.
+#[derive(Debug, Default)]
+struct PacketFramer {
+    state: FrameState,
+    packet: Vec<u8>,
+}
+
+impl PacketFramer {
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match self.state {
+            FrameState::Header => {
+                if byte == SERVO_START_BYTE {
+                    self.state = FrameState::SecondHeader;
+                }
+            }
+            FrameState::SecondHeader => {
+                self.state = if byte == SERVO_START_BYTE { FrameState::Id } else { FrameState::Header };
+            }
+            FrameState::Id => {
+                // Extra 0xFF padding before the ID is allowed
+                if byte != SERVO_START_BYTE {
+                    self.packet = vec![SERVO_START_BYTE, SERVO_START_BYTE, byte];
+                    self.state = FrameState::Length;
+                }
+            }
+            FrameState::Length => {
+                // Every packet carries at least the instruction/status byte and the checksum
+                if byte < 2 {
+                    self.state = FrameState::Header;
+                } else {
+                    self.packet.push(byte);
+                    self.state = FrameState::Body(byte as usize);
+                }
+            }
+            FrameState::Body(remaining) => {
+                self.packet.push(byte);
+                if remaining == 1 {
+                    self.state = FrameState::Header;
+                    return Some(std::mem::take(&mut self.packet));
+                }
+                self.state = FrameState::Body(remaining - 1);
+            }
+        }
+        None
+    }
+
+    // Bytes of a packet whose header has been seen but which isn't complete yet
+    fn partial(&self) -> Option<(usize, usize)> {
+        match self.state {
+            FrameState::Length => Some((4, self.packet.len())),
+            FrameState::Body(remaining) => Some((self.packet.len() + remaining, self.packet.len())),
+            _ => None,
+        }
+    }
+}
 
 impl ServoSerial {
     pub fn new(port_name: &str, baud_rate: u32) -> Result<Self, Box<dyn std::error::Error>> {
 
         let port = serialport::new(port_name, baud_rate)
-            .timeout(Duration::from_millis(100))
+            .timeout(DEFAULT_REPLY_TIMEOUT)
             .open()?;
-        Ok(ServoSerial { port })
+        Ok(Self::with_port(port, port_name, baud_rate))
+    }
+
+    /// Talks to servos through an already open port, such as a [`ReplayPort`].
+    pub fn with_port(port: Box<dyn SerialPort>, port_name: &str, baud_rate: u32) -> Self {
+        ServoSerial {
+            port,
+            port_name: port_name.to_string(),
+            baud_rate,
+            reply_timeout: DEFAULT_REPLY_TIMEOUT,
+            last_sent: Vec::new(),
+            sent_at: Instant::now(),
+            stats: BusStats::default(),
+            capture: None,
+        }
+    }
+
+    /// How long to wait for a reply beyond the time the bytes take on the wire.
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
+    }
+
+    pub fn reply_timeout(&self) -> Duration {
+        self.reply_timeout
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Transactions on this port since it was opened or the counters were cleared.
+    pub fn stats(&self) -> &BusStats {
+        &self.stats
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.stats = BusStats::default();
+    }
+
+    /// Logs every packet sent and framed on this port to `capture`.
+    pub fn set_capture(&mut self, capture: Arc<Capture>) {
+        self.capture = Some(capture);
+    }
+
+    /// Reconfigures the port for `baud_rate`, dropping anything received at the old rate.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), ServoError> {
+        self.port.set_baud_rate(baud_rate).map_err(std::io::Error::from)?;
+        self.port.clear(ClearBuffer::Input).map_err(std::io::Error::from)?;
+        self.baud_rate = baud_rate;
+        Ok(())
     }
 
     fn calculate_checksum(&self, packet: &[u8]) -> u8 {
@@ -62,27 +200,154 @@ impl ServoSerial {
         !((sum & 0xFF) as u8)
     }
 
-    fn send_packet(&mut self, packet: &[u8]) -> Result<(), std::io::Error> {
-        self.port.write_all(packet)
+    // The checksum covers everything after the header, so append a placeholder first
+    fn push_checksum(&self, packet: &mut Vec<u8>) {
+        packet.push(0);
+        let last = packet.len() - 1;
+        packet[last] = self.calculate_checksum(packet);
     }
 
-    fn receive_packet(&mut self, max_length: usize) -> Result<Vec<u8>, std::io::Error> {
-        let mut packet = Vec::with_capacity(max_length);
-        let mut buffer = [0u8; 1];
+    fn send_packet(&mut self, packet: &[u8]) -> Result<(), ServoError> {
+        // Whatever is still buffered belongs to an earlier transaction
+        self.port.clear(ClearBuffer::Input).map_err(std::io::Error::from)?;
+        self.port.write_all(packet)?;
+        self.sent_at = Instant::now();
+        self.last_sent = packet.to_vec();
+        if let Some(capture) = &self.capture {
+            capture.record(&self.port_name, Direction::Sent, packet);
+        }
 
-        while packet.len() < max_length {
-            self.port.read_exact(&mut buffer)?;
-            packet.push(buffer[0]);
+        self.stats.bytes_sent += packet.len() as u64;
+        if packet[2] != SERVO_BROADCAST_ID {
+            self.stats.servo(packet[2]).bytes_sent += packet.len() as u64;
+        }
+        Ok(())
+    }
+
+    // Time to clock `bytes` bytes through the UART, 10 bits each
+    fn transfer_time(&self, bytes: usize) -> Duration {
+        Duration::from_micros(bytes as u64 * 10_000_000 / self.baud_rate.max(1) as u64)
+    }
+
+    fn receive_packet(&mut self, id: u8, expected_length: usize) -> Result<Vec<u8>, ServoError> {
+        self.receive_packets(&[id], expected_length)?.remove(0)
+    }
 
-            if packet.len() >= 4 && packet.len() == packet[3] as usize + 4 {
-                break;
+    /// Collects one valid packet from each of `ids` until the reply deadline. Bytes before a
+    /// header, packets with a bad checksum, our own echoed request and replies from other
+    /// servos are skipped; a servo that never answers gets the last problem seen while
+    /// waiting for it. Only I/O failures fail the whole call.
+    fn receive_packets(&mut self, ids: &[u8], expected_length: usize) -> Result<Vec<Result<Vec<u8>, ServoError>>, ServoError> {
+        let deadline = Instant::now()
+            + self.reply_timeout
+            + self.transfer_time(self.last_sent.len() + expected_length * ids.len());
+        let mut framer = PacketFramer::default();
+        let mut input = VecDeque::new();
+        let mut replies: Vec<Option<Vec<u8>>> = vec![None; ids.len()];
+        let mut latencies = vec![Duration::ZERO; ids.len()];
+        let mut failures: Vec<Option<ServoError>> = ids.iter().map(|_| None).collect();
+        let mut buffer = [0u8; 64];
+
+        loop {
+            while let Some(byte) = input.pop_front() {
+                let packet = match framer.push(byte) {
+                    Some(packet) => packet,
+                    None => continue,
+                };
+                if let Some(capture) = &self.capture {
+                    capture.record(&self.port_name, Direction::Received, &packet);
+                }
+
+                let slot = ids.iter().zip(&replies).position(|(&id, reply)| id == packet[2] && reply.is_none());
+                let waiting = replies.iter().position(Option::is_none);
+
+                let checksum = self.calculate_checksum(&packet);
+                let received = packet[packet.len() - 1];
+                if checksum != received {
+                    if let Some(i) = slot.or(waiting) {
+                        failures[i] = Some(ServoError::ChecksumMismatch { id: ids[i], expected: checksum, received });
+                    }
+                    // The header may have been noise, rescan what followed it
+                    for &byte in packet[2..].iter().rev() {
+                        input.push_front(byte);
+                    }
+                    continue;
+                }
+                if packet == self.last_sent {
+                    continue;
+                }
+                match (slot, waiting) {
+                    (Some(i), _) => {
+                        latencies[i] = self.sent_at.elapsed();
+                        replies[i] = Some(packet);
+                    }
+                    (None, Some(i)) => failures[i] = Some(ServoError::IdMismatch { expected: ids[i], received: packet[2] }),
+                    (None, None) => {}
+                }
+            }
+
+            let now = Instant::now();
+            let done = replies.iter().all(Option::is_some);
+            if done || now >= deadline {
+                if let (Some((expected, received)), Some(i)) = (framer.partial(), replies.iter().position(Option::is_none)) {
+                    failures[i] = Some(ServoError::TruncatedPacket { id: ids[i], expected, received });
+                }
+                let replies: Vec<_> = replies
+                    .into_iter()
+                    .zip(failures)
+                    .zip(ids)
+                    .map(|((reply, failure), &id)| reply.ok_or_else(|| failure.unwrap_or(ServoError::Timeout { id })))
+                    .collect();
+                for ((reply, &id), latency) in replies.iter().zip(ids).zip(latencies) {
+                    let stats = self.stats.servo(id);
+                    stats.transactions += 1;
+                    match reply {
+                        Ok(packet) => {
+                            stats.bytes_received += packet.len() as u64;
+                            stats.latency.record(latency);
+                        }
+                        Err(e) => stats.record_error(e),
+                    }
+                }
+                return Ok(replies);
+            }
+
+            self.port.set_timeout(deadline - now).map_err(std::io::Error::from)?;
+            match self.port.read(&mut buffer) {
+                Ok(n) => {
+                    self.stats.bytes_received += n as u64;
+                    input.extend(&buffer[..n]);
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(ServoError::Io(e)),
             }
         }
+    }
+
+    /// Receives the status packet for a request to `id` carrying `length` parameter bytes,
+    /// returning the status byte and the parameters.
+    fn receive_reply(&mut self, id: u8, length: usize) -> Result<(u8, Vec<u8>), ServoError> {
+        let expected = length + 6;
+        let response = self.receive_packet(id, expected)?;
+        if response.len() != expected {
+            let error = ServoError::TruncatedPacket { id, expected, received: response.len() };
+            self.stats.servo(id).record_error(&error);
+            return Err(error);
+        }
 
-        Ok(packet)
+        Ok((response[4], response[5..expected - 1].to_vec()))
     }
 
-    pub fn servo_ping(&mut self, id: u8) -> Result<(), std::io::Error> {
+    // Commands succeed only if the servo reports no errors
+    fn receive_ack(&mut self, id: u8) -> Result<(), ServoError> {
+        let (status, _) = self.receive_reply(id, 0)?;
+        if status != 0 {
+            return Err(ServoError::Status { id, faults: ServoFaults::from_bits_retain(status) });
+        }
+        Ok(())
+    }
+
+    pub fn servo_ping(&mut self, id: u8) -> Result<(), ServoError> {
         let packet = [
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -96,15 +361,13 @@ impl ServoSerial {
 
         self.send_packet(&packet)?;
 
-        let response = self.receive_packet(6)?;
-        if response.len() != 6 || response[2] != id || response[4] != 0 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
-        }
+        // An answering servo is present even if it reports errors
+        self.receive_reply(id, 0)?;
 
         Ok(())
     }
 
-    pub fn servo_read(&mut self, id: u8, address: u8, length: u8) -> Result<Vec<u8>, std::io::Error> {
+    pub fn servo_read(&mut self, id: u8, address: u8, length: u8) -> Result<Vec<u8>, ServoError> {
         let packet = [
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -120,19 +383,57 @@ impl ServoSerial {
 
         self.send_packet(&packet)?;
 
-        let response = self.receive_packet(256)?;
-        if response.len() < 6 || response[2] != id {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
+        // Register contents are still valid while the servo reports errors; the same
+        // bits are readable from ServoStatus
+        let (_, data) = self.receive_reply(id, length as usize)?;
+
+        Ok(data)
+    }
+
+    /// Reads the same block from every servo in `ids` with one SYNC_READ. The servos answer
+    /// one after another, so a single turnaround covers the whole bus; each servo's outcome
+    /// is reported separately.
+    pub fn servo_sync_read(&mut self, ids: &[u8], address: u8, length: u8) -> Result<Vec<Result<Vec<u8>, ServoError>>, ServoError> {
+        if ids.is_empty() || ids.len() > MAX_SYNC_READ_IDS {
+            return Err(ServoError::InvalidInput(format!("Cannot sync read {} servos", ids.len())));
         }
 
-        Ok(response[5..response.len() - 1].to_vec())
+        let mut packet = vec![
+            SERVO_START_BYTE,
+            SERVO_START_BYTE,
+            SERVO_BROADCAST_ID,
+            (ids.len() + 4) as u8,
+            SERVO_CMD_SYNC_READ,
+            address,
+            length,
+        ];
+        packet.extend_from_slice(ids);
+        self.push_checksum(&mut packet);
+
+        self.send_packet(&packet)?;
+
+        let expected = length as usize + 6;
+        let replies = self.receive_packets(ids, expected)?;
+        Ok(replies
+            .into_iter()
+            .zip(ids)
+            .map(|(reply, &id)| {
+                let reply = reply?;
+                if reply.len() != expected {
+                    let error = ServoError::TruncatedPacket { id, expected, received: reply.len() };
+                    self.stats.servo(id).record_error(&error);
+                    return Err(error);
+                }
+                Ok(reply[5..expected - 1].to_vec())
+            })
+            .collect())
     }
 
-    pub fn servo_read_command(&mut self, cmd: &ServoCommand) -> Result<Vec<u8>, std::io::Error> {
+    pub fn servo_read_command(&mut self, cmd: &ServoCommand) -> Result<Vec<u8>, ServoError> {
         self.servo_read(cmd.id, cmd.address, cmd.length)
     }
 
-    pub fn servo_write(&mut self, id: u8, address: u8, data: &[u8]) -> Result<(), std::io::Error> {
+    pub fn servo_write(&mut self, id: u8, address: u8, data: &[u8]) -> Result<(), ServoError> {
         let mut packet = vec![
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -142,25 +443,22 @@ impl ServoSerial {
             address,
         ];
         packet.extend_from_slice(data);
-        packet.push(self.calculate_checksum(&packet));
+        self.push_checksum(&mut packet);
 
         self.send_packet(&packet)?;
 
         if id != SERVO_BROADCAST_ID {
-            let response = self.receive_packet(6)?;
-            if response.len() != 6 || response[2] != id || response[4] != 0 {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
-            }
+            self.receive_ack(id)?;
         }
 
         Ok(())
     }
 
-    pub fn servo_write_command(&mut self, cmd: &ServoCommand) -> Result<(), std::io::Error> {
+    pub fn servo_write_command(&mut self, cmd: &ServoCommand) -> Result<(), ServoError> {
         self.servo_write(cmd.id, cmd.address, &cmd.data)
     }
 
-    pub fn servo_reg_write(&mut self, id: u8, address: u8, data: &[u8]) -> Result<(), std::io::Error> {
+    pub fn servo_reg_write(&mut self, id: u8, address: u8, data: &[u8]) -> Result<(), ServoError> {
         let mut packet = vec![
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -170,21 +468,18 @@ impl ServoSerial {
             address,
         ];
         packet.extend_from_slice(data);
-        packet.push(self.calculate_checksum(&packet));
+        self.push_checksum(&mut packet);
 
         self.send_packet(&packet)?;
 
         if id != SERVO_BROADCAST_ID {
-            let response = self.receive_packet(6)?;
-            if response.len() != 6 || response[2] != id || response[4] != 0 {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
-            }
+            self.receive_ack(id)?;
         }
 
         Ok(())
     }
 
-    pub fn servo_action(&mut self) -> Result<(), std::io::Error> {
+    pub fn servo_action(&mut self) -> Result<(), ServoError> {
         let packet = [
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -199,7 +494,7 @@ impl ServoSerial {
         self.send_packet(&packet)
     }
 
-    pub fn servo_sync_write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+    pub fn servo_sync_write(&mut self, data: &[u8]) -> Result<(), ServoError> {
         let mut packet = vec![
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -208,12 +503,12 @@ impl ServoSerial {
             SERVO_CMD_SYNC_WRITE,
         ];
         packet.extend_from_slice(data);
-        packet.push(self.calculate_checksum(&packet));
+        self.push_checksum(&mut packet);
 
         self.send_packet(&packet)
     }
 
-    pub fn servo_reset(&mut self, id: u8) -> Result<(), std::io::Error> {
+    pub fn servo_reset(&mut self, id: u8) -> Result<(), ServoError> {
         let packet = [
             SERVO_START_BYTE,
             SERVO_START_BYTE,
@@ -228,16 +523,13 @@ impl ServoSerial {
         self.send_packet(&packet)?;
 
         if id != SERVO_BROADCAST_ID {
-            let response = self.receive_packet(6)?;
-            if response.len() != 6 || response[2] != id || response[4] != 0 {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response"));
-            }
+            self.receive_ack(id)?;
         }
 
         Ok(())
     }
 
-    pub fn servo_move(&mut self, id: u8, position: i16, time: u16, speed: u16) -> Result<(), std::io::Error> {
+    pub fn servo_move(&mut self, id: u8, position: i16, time: u16, speed: u16) -> Result<(), ServoError> {
         let data = [
             (position & 0xFF) as u8,
             ((position >> 8) & 0xFF) as u8,
@@ -249,9 +541,9 @@ impl ServoSerial {
         self.servo_write(id, SERVO_ADDR_TARGET_POSITION, &data)
     }
 
-    pub fn servo_move_multiple(&mut self, ids: &[u8], positions: &[i16]) -> Result<(), std::io::Error> {
+    pub fn servo_move_multiple(&mut self, ids: &[u8], positions: &[i16]) -> Result<(), ServoError> {
         if ids.len() != positions.len() {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Mismatched ids and positions lengths"));
+            return Err(ServoError::InvalidInput("Mismatched ids and positions lengths".to_string()));
         }
 
         let mut data = Vec::with_capacity(1 + 1 + ids.len() * 3);
@@ -267,77 +559,93 @@ impl ServoSerial {
         self.servo_sync_write(&data)
     }
 
-    pub fn servo_move_multiple_sync(&mut self, cmd: &ServoMultipleWriteCommand) -> Result<(), std::io::Error> {
-        if cmd.ids.len() != cmd.positions.len() || cmd.ids.len() != cmd.times.len() || cmd.ids.len() != cmd.speeds.len() {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Mismatched input lengths"));
+    /// Sends `targets` with SYNC_WRITE, in as many packets as the length byte requires.
+    /// With `write_accelerations` the writes start one register earlier, at `Acceleration`.
+    pub fn servo_move_multiple_sync(
+        &mut self,
+        targets: &[ServoTarget],
+        only_write_positions: bool,
+        write_accelerations: bool,
+    ) -> Result<(), ServoError> {
+        if targets.is_empty() {
+            return Err(ServoError::InvalidInput("Invalid count".to_string()));
         }
 
-        let count = cmd.ids.len();
-        if count == 0 || count > MAX_SERVOS {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid count"));
-        }
-
-        let mut packet = Vec::with_capacity(256);
-        packet.extend_from_slice(&[
-            SERVO_START_BYTE,
-            SERVO_START_BYTE,
-            SERVO_BROADCAST_ID,
-            ((cmd.only_write_positions as u8 * 3 + 7) * count as u8 + 4),
-            SERVO_CMD_SYNC_WRITE,
-            SERVO_ADDR_TARGET_POSITION,
-            if cmd.only_write_positions == 1 { 2 } else { 6 }, // Data length per servo
-        ]);
-
-        for i in 0..count {
-            packet.push(cmd.ids[i]);
-            packet.extend_from_slice(&cmd.positions[i].to_le_bytes());
-            if cmd.only_write_positions == 0 {
-                packet.extend_from_slice(&cmd.times[i].to_le_bytes());
-                packet.extend_from_slice(&cmd.speeds[i].to_le_bytes());
+        let write_accelerations = write_accelerations && !only_write_positions;
+        let (address, data_length): (u8, u8) = match (only_write_positions, write_accelerations) {
+            (true, _) => (SERVO_ADDR_TARGET_POSITION, 2),
+            (false, false) => (SERVO_ADDR_TARGET_POSITION, 6),
+            (false, true) => (SERVO_ADDR_ACCELERATION, 7),
+        };
+        // The length byte counts the instruction, address, data length, entries and checksum
+        let per_packet = (u8::MAX as usize - 4) / (data_length as usize + 1);
+        for chunk in targets.chunks(per_packet) {
+            let mut packet = Vec::with_capacity(256);
+            packet.extend_from_slice(&[
+                SERVO_START_BYTE,
+                SERVO_START_BYTE,
+                SERVO_BROADCAST_ID,
+                ((data_length + 1) * chunk.len() as u8 + 4),
+                SERVO_CMD_SYNC_WRITE,
+                address,
+                data_length, // Data length per servo
+            ]);
+
+            for target in chunk {
+                packet.push(target.id);
+                if write_accelerations {
+                    packet.push(target.acceleration);
+                }
+                packet.extend_from_slice(&target.position.to_le_bytes());
+                if !only_write_positions {
+                    packet.extend_from_slice(&target.time.to_le_bytes());
+                    packet.extend_from_slice(&target.speed.to_le_bytes());
+                }
             }
-        }
 
-        packet.push(self.calculate_checksum(&packet));
+            self.push_checksum(&mut packet);
 
-        self.send_packet(&packet)
+            self.send_packet(&packet)?;
+        }
+        Ok(())
     }
 
-    pub fn servo_read_position(&mut self, id: u8) -> Result<i16, std::io::Error> {
+    pub fn servo_read_position(&mut self, id: u8) -> Result<i16, ServoError> {
         let data = self.servo_read(id, SERVO_ADDR_CURRENT_POSITION, 2)?;
         if data.len() != 2 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
+            return Err(ServoError::TruncatedPacket { id, expected: 2, received: data.len() });
         }
         Ok(i16::from_le_bytes([data[0], data[1]]))
     }
 
-    pub fn servo_read_current(&mut self, id: u8) -> Result<u16, std::io::Error> {
+    pub fn servo_read_current(&mut self, id: u8) -> Result<u16, ServoError> {
         let data = self.servo_read(id, SERVO_ADDR_CURRENT_CURRENT, 2)?;
         if data.len() != 2 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
+            return Err(ServoError::TruncatedPacket { id, expected: 2, received: data.len() });
         }
         Ok(u16::from_le_bytes([data[0], data[1]]))
     }
 
-    pub fn servo_read_load(&mut self, id: u8) -> Result<i16, std::io::Error> {
+    pub fn servo_read_load(&mut self, id: u8) -> Result<i16, ServoError> {
         let data = self.servo_read(id, SERVO_ADDR_CURRENT_LOAD, 2)?;
         if data.len() != 2 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
+            return Err(ServoError::TruncatedPacket { id, expected: 2, received: data.len() });
         }
         Ok(i16::from_le_bytes([data[0], data[1]]))
     }
 
-    pub fn servo_read_voltage(&mut self, id: u8) -> Result<u8, std::io::Error> {
+    pub fn servo_read_voltage(&mut self, id: u8) -> Result<u8, ServoError> {
         let data = self.servo_read(id, SERVO_ADDR_CURRENT_VOLTAGE, 1)?;
         if data.len() != 1 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
+            return Err(ServoError::TruncatedPacket { id, expected: 1, received: data.len() });
         }
         Ok(data[0])
     }
 
-    pub fn servo_read_position_and_status(&mut self, id: u8) -> Result<(i16, i16, i16), std::io::Error> {
+    pub fn servo_read_position_and_status(&mut self, id: u8) -> Result<(i16, i16, i16), ServoError> {
         let data = self.servo_read(id, SERVO_ADDR_CURRENT_POSITION, 6)?;
         if data.len() != 6 {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid response length"));
+            return Err(ServoError::TruncatedPacket { id, expected: 6, received: data.len() });
         }
 
         let current_location = i16::from_le_bytes([data[0], data[1]]);
@@ -347,227 +655,661 @@ impl ServoSerial {
         Ok((current_location, current_speed, current_load))
     }
 
-    pub fn servo_set_torque(&mut self, id: u8, torque_state: u8) -> Result<(), std::io::Error> {
+    pub fn servo_set_torque(&mut self, id: u8, torque_state: u8) -> Result<(), ServoError> {
         self.servo_write(id, SERVO_ADDR_TORQUE_SWITCH, &[torque_state])
     }
 
-    pub fn servo_torque_on(&mut self, id: u8) -> Result<(), std::io::Error> {
+    pub fn servo_torque_on(&mut self, id: u8) -> Result<(), ServoError> {
         self.servo_set_torque(id, TORQUE_ON)
     }
 
-    pub fn servo_torque_off(&mut self, id: u8) -> Result<(), std::io::Error> {
+    pub fn servo_torque_off(&mut self, id: u8) -> Result<(), ServoError> {
         self.servo_set_torque(id, TORQUE_OFF)
     }
 }
 
+// Each poll fetches the full state block every this many cycles and only the feedback
+// registers in between
+const FULL_REFRESH_INTERVAL: u32 = 25;
+const DEFAULT_READOUT_RATE: f64 = 50.0;
+// Time the readout thread leaves the bus free between polls for commands
+const COMMAND_WINDOW: Duration = Duration::from_millis(1);
+// read_continuous fails once the readout thread has not completed a poll for this long
+const STALE_SNAPSHOT: Duration = Duration::from_secs(1);
+
+// Whether the servos on the bus answer SYNC_READ
+const SYNC_READ_UNKNOWN: u8 = 0;
+const SYNC_READ_SUPPORTED: u8 = 1;
+const SYNC_READ_UNSUPPORTED: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+struct Readout {
+    info: ServoInfo,
+    updated: Instant,
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    data: ServoData,
+    taken: Instant,
+}
+
+// Bus state shared between Servo and its readout thread
 #[derive(Debug)]
-pub struct Servo {
-    serial: Arc<Mutex<ServoSerial>>,
+struct Poller {
+    port: String,
+    serial: Mutex<ServoSerial>,
+    ids: Vec<u8>,
+    readout: Mutex<HashMap<u8, Readout>>,
+    snapshot: Mutex<Option<Snapshot>>,
+    last_error: Mutex<Option<String>>,
+    sync_read: AtomicU8,
+    task_run_count: AtomicU32,
 }
 
-impl Servo {
-    pub fn new() -> Result<Self> {
-        let port_name = env::var("SERVO_PORT").unwrap_or_else(|_| "/dev/ttyUSB0".to_string());
-        let baud_rate = env::var("SERVO_BAUD_RATE")
-            .unwrap_or_else(|_| "115200".to_string())
-            .parse::<u32>()
-            .context("Failed to parse SERVO_BAUD_RATE")?;
-
-        let serial = ServoSerial::new(&port_name, baud_rate)
-            .map_err(|e| anyhow::anyhow!("Failed to create ServoSerial: {}", e))?;
-        
-        Ok(Servo {
-            serial: Arc::new(Mutex::new(serial)),
-        })
+impl Poller {
+    // Reads the same block from every configured servo, in one SYNC_READ where the
+    // servos support it and one READ per servo otherwise
+    fn read_all(&self, address: u8, length: u8) -> Result<Vec<Result<Vec<u8>, ServoError>>, ServoError> {
+        let mut serial = self.serial.lock().unwrap();
+        let sync_read = self.sync_read.load(Ordering::SeqCst);
+        if sync_read != SYNC_READ_UNSUPPORTED {
+            let mut replies = Vec::with_capacity(self.ids.len());
+            for ids in self.ids.chunks(MAX_SYNC_READ_IDS) {
+                replies.extend(serial.servo_sync_read(ids, address, length)?);
+            }
+            if replies.iter().any(Result::is_ok) {
+                self.sync_read.store(SYNC_READ_SUPPORTED, Ordering::SeqCst);
+                return Ok(replies);
+            }
+            if sync_read == SYNC_READ_SUPPORTED {
+                return Ok(replies);
+            }
+        }
+
+        let replies: Vec<_> = self.ids.iter().map(|&id| serial.servo_read(id, address, length)).collect();
+        // Servos that answer READ but not SYNC_READ run firmware without it
+        if sync_read == SYNC_READ_UNKNOWN && replies.iter().any(Result::is_ok) {
+            self.sync_read.store(SYNC_READ_UNSUPPORTED, Ordering::SeqCst);
+        }
+        Ok(replies)
     }
 
-    pub fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
-        let mut serial = self.serial.lock().unwrap();
-        match serial.servo_write(id, register as u8, data) {
-            Ok(_) => Ok(()),
-            Err(_) => Ok(()),  // Silently ignore errors
+    fn read_info(&self, id: u8) -> Result<ServoInfo> {
+        let data = self.serial.lock().unwrap().servo_read(id, ServoRegister::TorqueSwitch as u8, SERVO_INFO_LENGTH)?;
+        let block = data.as_slice().try_into().map_err(|_| ServoError::TruncatedPacket {
+            id,
+            expected: SERVO_INFO_LENGTH as usize,
+            received: data.len(),
+        })?;
+
+        let info = ServoInfo::from_registers(block);
+        self.readout.lock().unwrap().insert(id, Readout { info, updated: Instant::now() });
+        Ok(info)
+    }
+
+    // Reads every configured servo in one bus turnaround. Servos that did not answer keep
+    // their last values.
+    fn poll(&self) -> Result<ServoData> {
+        let cycle = self.task_run_count.fetch_add(1, Ordering::SeqCst);
+        let full = cycle.is_multiple_of(FULL_REFRESH_INTERVAL);
+        let (register, length) = if full {
+            (ServoRegister::TorqueSwitch, SERVO_INFO_LENGTH)
+        } else {
+            (ServoRegister::CurrentLocation, SERVO_FEEDBACK_LENGTH)
+        };
+
+        let replies = self.read_all(register as u8, length)?;
+        let now = Instant::now();
+        let mut appeared = Vec::new();
+        {
+            let mut readout = self.readout.lock().unwrap();
+            for (&id, reply) in self.ids.iter().zip(replies) {
+                let Ok(block) = reply else { continue };
+                if full {
+                    let info = ServoInfo::from_registers(block.as_slice().try_into()?);
+                    readout.insert(id, Readout { info, updated: now });
+                } else if let Some(entry) = readout.get_mut(&id) {
+                    entry.info.update_feedback(block.as_slice().try_into()?);
+                    entry.updated = now;
+                } else {
+                    appeared.push(id);
+                }
+            }
         }
+
+        // Servos that came online since the last full refresh need their setpoints too
+        for id in appeared {
+            let _ = self.read_info(id);
+        }
+
+        let data = ServoData {
+            servos: self.readout.lock().unwrap().iter().map(|(&id, entry)| (id, entry.info)).collect(),
+            task_run_count: cycle + 1,
+        };
+        *self.snapshot.lock().unwrap() = Some(Snapshot { data: data.clone(), taken: now });
+        Ok(data)
     }
 
-    pub fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+    // Moves the servos that answer at the current rate to `baud_rate` one at a time. If
+    // one fails, every servo touched so far is put back.
+    fn migrate_baud_rate(&self, baud_rate: u32) -> Result<()> {
+        let code = baud_rate_code(baud_rate)
+            .with_context(|| format!("Servos don't support {} baud, use one of {:?}", baud_rate, BAUD_RATES))?;
         let mut serial = self.serial.lock().unwrap();
-        match serial.servo_read(id, register as u8, length) {
-            Ok(data) => Ok(data),
-            Err(_) => Ok(Vec::new()),  // Return empty Vec on error
+        let old_rate = serial.baud_rate();
+        if old_rate == baud_rate {
+            return Ok(());
+        }
+        let old_code = baud_rate_code(old_rate)
+            .with_context(|| format!("Bus runs at {} baud, which servos can't be set back to", old_rate))?;
+
+        let present: Vec<u8> = self.ids.iter().copied().filter(|&id| serial.servo_ping(id).is_ok()).collect();
+        if present.is_empty() {
+            bail!("No servo answers at {} baud", old_rate);
+        }
+
+        for (i, &id) in present.iter().enumerate() {
+            let Err(e) = switch_baud_rate(&mut serial, id, old_rate, baud_rate, code) else { continue };
+
+            // The failing servo may have switched before the error
+            let stranded: Vec<u8> = present[..=i]
+                .iter()
+                .copied()
+                .filter(|&id| restore_baud_rate(&mut serial, id, old_rate, old_code, baud_rate).is_err())
+                .collect();
+            serial.set_baud_rate(old_rate)?;
+            if stranded.is_empty() {
+                return Err(e.context(format!("Failed to move servo {} to {} baud, servos were restored to {} baud", id, baud_rate, old_rate)));
+            }
+            return Err(e.context(format!("Failed to move servo {} to {} baud, servos {:?} could not be restored to {} baud", id, baud_rate, stranded, old_rate)));
         }
+
+        serial.set_baud_rate(baud_rate)?;
+        Ok(())
     }
 
-    pub fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
-        let mut serial = self.serial.lock().unwrap();
-        serial.servo_move(id, position, time, speed)
-            .map_err(|e| anyhow::anyhow!("Failed to move servo: {}", e))
+    // The readout thread's latest snapshot, unless it has stopped producing them
+    fn latest(&self) -> Result<ServoData> {
+        let snapshot = self.snapshot.lock().unwrap().clone().context("No servo readout yet")?;
+        if snapshot.taken.elapsed() > STALE_SNAPSHOT {
+            match self.last_error.lock().unwrap().as_ref() {
+                Some(e) => bail!("Servo readout stalled: {}", e),
+                None => bail!("Servo readout stalled"),
+            }
+        }
+        Ok(snapshot.data)
     }
 
-    pub fn set_mode(&self, id: u8, mode: ServoMode) -> Result<()> {
-        self.write(id, ServoRegister::OperationMode, &[mode as u8])
+    fn run(&self, period: Duration, running: &AtomicBool) {
+        while running.load(Ordering::SeqCst) {
+            let started = Instant::now();
+            let result = self.poll();
+
+            let mut last_error = self.last_error.lock().unwrap();
+            match result {
+                Ok(_) => *last_error = None,
+                Err(e) => {
+                    // Report once per outage rather than every cycle
+                    if last_error.is_none() {
+                        eprintln!("Servo readout on {} failed: {:#}", self.port, e);
+                    }
+                    *last_error = Some(format!("{:#}", e));
+                }
+            }
+            drop(last_error);
+
+            thread::sleep(period.saturating_sub(started.elapsed()).max(COMMAND_WINDOW));
+        }
     }
+}
 
-    pub fn set_speed(&self, id: u8, speed: u16, direction: ServoDirection) -> Result<()> {
-        let speed = if direction == ServoDirection::Clockwise { speed } else { speed | 0x8000 };
-        self.write(id, ServoRegister::RunningSpeed, &speed.to_le_bytes())
+// Finds the rate at which any of `ids` answers, trying the default first, and leaves the
+// port there. Without an answer the port is left at the default.
+fn probe_baud_rate(serial: &mut ServoSerial, ids: &[u8]) -> Result<Option<u32>, ServoError> {
+    let reply_timeout = serial.reply_timeout();
+    serial.set_reply_timeout(PROBE_TIMEOUT.min(reply_timeout));
+
+    let mut found = None;
+    let others = BAUD_RATES.into_iter().filter(|&rate| rate != DEFAULT_BAUD_RATE);
+    for rate in std::iter::once(DEFAULT_BAUD_RATE).chain(others) {
+        serial.set_baud_rate(rate)?;
+        if ids.iter().any(|&id| serial.servo_ping(id).is_ok()) {
+            found = Some(rate);
+            break;
+        }
     }
 
-    pub fn read_info(&self, id: u8) -> Result<ServoInfo> {
-        let mut serial = self.serial.lock().unwrap();
-        let data = serial.servo_read(id, ServoRegister::TorqueSwitch as u8, 30)?;
-        
-        if data.len() != 30 {
-            bail!("Failed to read servo info: incorrect data length");
-        }
-
-        Ok(ServoInfo {
-            torque_switch: data[0],
-            acceleration: data[1],
-            target_location: i16::from_le_bytes([data[2], data[3]]),
-            running_time: u16::from_le_bytes([data[4], data[5]]),
-            running_speed: u16::from_le_bytes([data[6], data[7]]),
-            torque_limit: u16::from_le_bytes([data[8], data[9]]),
-            reserved1: [data[10], data[11], data[12], data[13], data[14], data[15]],
-            lock_mark: data[15],
-            current_location: i16::from_le_bytes([data[16], data[17]]),
-            current_speed: i16::from_le_bytes([data[18], data[19]]),
-            current_load: i16::from_le_bytes([data[20], data[21]]),
-            current_voltage: data[22],
-            current_temperature: data[23],
-            async_write_flag: data[24],
-            servo_status: data[25],
-            mobile_sign: data[26],
-            reserved2: [data[27], data[28]],
-            current_current: u16::from_le_bytes([data[28], data[29]]),
-        })
+    serial.set_reply_timeout(reply_timeout);
+    if found.is_none() {
+        serial.set_baud_rate(DEFAULT_BAUD_RATE)?;
     }
+    Ok(found)
+}
 
-    pub fn read_continuous(&self) -> Result<ServoData> {
-        let mut data = ServoData {
-            servo: [ServoInfo::default(); MAX_SERVOS],
-            task_run_count: 0,
+/// Parses a bus map such as `/dev/ttyUSB0=1-12;/dev/ttyUSB1=13-16` into ports and the servo
+/// IDs on each.
+pub fn parse_servo_buses(spec: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut buses: Vec<(String, Vec<u8>)> = Vec::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (port, ids) = entry.rsplit_once('=').with_context(|| format!("Expected PORT=IDS, got '{}'", entry))?;
+        let port = port.trim();
+        if buses.iter().any(|(other, _)| other == port) {
+            bail!("Port {} is listed more than once", port);
+        }
+        // A bare number is one ID here, not a count
+        let ids = match ids.trim().parse::<u8>() {
+            Ok(id) if id != 0 => vec![id],
+            _ => parse_servo_ids(ids)?,
         };
+        if let Some(&id) = ids.iter().find(|id| buses.iter().any(|(_, other)| other.contains(id))) {
+            bail!("Servo ID {} is assigned to more than one bus", id);
+        }
+        buses.push((port.to_string(), ids));
+    }
+    if buses.is_empty() {
+        bail!("No buses in '{}'", spec);
+    }
+    Ok(buses)
+}
 
-        for i in 0..MAX_SERVOS {
-            if let Ok(info) = self.read_info(i as u8) {
-                data.servo[i] = info;
-            }
+// Moves one servo from `from` to `to` baud through the EEPROM unlock/lock sequence and
+// leaves the port at `from`. The servo switches as soon as it takes the write, so its
+// acknowledgement may be lost; the new rate is verified by reading it back.
+fn switch_baud_rate(serial: &mut ServoSerial, id: u8, from: u32, to: u32, code: u8) -> Result<()> {
+    serial.servo_write(id, ServoRegister::LockMark as u8, &[MemoryLockState::Unlocked as u8])?;
+    let _ = serial.servo_write(id, ServoRegister::BaudRate as u8, &[code]);
+    thread::sleep(EEPROM_WRITE_DELAY);
+
+    serial.set_baud_rate(to)?;
+    let result = match serial.servo_read(id, ServoRegister::BaudRate as u8, 1) {
+        Ok(data) if data == [code] => {
+            let locked = serial.servo_write(id, ServoRegister::LockMark as u8, &[MemoryLockState::Locked as u8]);
+            thread::sleep(EEPROM_WRITE_DELAY);
+            locked.map_err(Into::into)
         }
+        Ok(data) => Err(anyhow::anyhow!("Servo {} reports baud rate setting {:?} after the change", id, data)),
+        Err(e) => Err(anyhow::Error::from(e).context(format!("Servo {} did not answer at {} baud", id, to))),
+    };
+    serial.set_baud_rate(from)?;
+    result
+}
 
-        data.task_run_count += 1;
-        Ok(data)
+// Puts one servo back at `old_rate`, whichever of the two rates it answers at, and locks
+// its EEPROM again
+fn restore_baud_rate(serial: &mut ServoSerial, id: u8, old_rate: u32, old_code: u8, new_rate: u32) -> Result<()> {
+    for rate in [new_rate, old_rate] {
+        serial.set_baud_rate(rate)?;
+        if serial.servo_ping(id).is_err() {
+            continue;
+        }
+        serial.servo_write(id, ServoRegister::LockMark as u8, &[MemoryLockState::Unlocked as u8])?;
+        let _ = serial.servo_write(id, ServoRegister::BaudRate as u8, &[old_code]);
+        thread::sleep(EEPROM_WRITE_DELAY);
+
+        serial.set_baud_rate(old_rate)?;
+        serial.servo_write(id, ServoRegister::LockMark as u8, &[MemoryLockState::Locked as u8])?;
+        thread::sleep(EEPROM_WRITE_DELAY);
+        return Ok(());
     }
+    Err(ServoError::Timeout { id }.into())
+}
 
-    pub fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
-        let mut serial = self.serial.lock().unwrap();
-        let adapted_cmd = ServoMultipleWriteCommand {
-            only_write_positions: cmd.only_write_positions,
-            ids: cmd.ids,
-            positions: cmd.positions,
-            times: cmd.times,
-            speeds: cmd.speeds,
+#[derive(Debug)]
+struct ReadoutThread {
+    running: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+/// Servos on one or more serial ports. `SERVO_BUSES` (e.g. `/dev/ttyUSB0=1-12;/dev/ttyUSB1=13-16`)
+/// routes each servo ID to a port; without it every servo in `SERVO_IDS` is on `SERVO_PORT`.
+/// Commands to IDs that aren't listed go to the first port.
+///
+/// Each port has its own lock, so commands on different buses run in parallel. While readout
+/// is enabled a background thread per bus polls its servos at `SERVO_READOUT_HZ` and
+/// `read_continuous` merges their latest snapshots; other commands are sent between polls.
+#[derive(Debug)]
+pub struct Servo {
+    buses: Vec<Arc<Poller>>,
+    routes: HashMap<u8, usize>,
+    readout_period: Duration,
+    readout_threads: Mutex<Vec<ReadoutThread>>,
+    movement_enabled: AtomicBool,
+    replay: Option<Arc<Replay>>,
+}
+
+impl Servo {
+    pub fn new() -> Result<Self> {
+        match env::var("SERVO_REPLAY") {
+            Ok(path) => Self::from_replay(Arc::new(Replay::load(path.as_ref())?)),
+            Err(_) => Self::open(None),
+        }
+    }
+
+    /// Plays back a capture instead of opening the serial ports, with the bus layout and
+    /// settings taken from the environment as in [`Servo::new`]. `SERVO_PORT` defaults to
+    /// the first port in the capture.
+    pub fn from_replay(replay: Arc<Replay>) -> Result<Self> {
+        Self::open(Some(replay))
+    }
+
+    fn open(replay: Option<Arc<Replay>>) -> Result<Self> {
+        // Without a fixed rate the servos are looked for at every rate they support
+        let baud_rate = match env::var("SERVO_BAUD_RATE") {
+            Ok(rate) if rate != "auto" => Some(rate.parse::<u32>().context("Failed to parse SERVO_BAUD_RATE")?),
+            _ => None,
         };
-        serial.servo_move_multiple_sync(&adapted_cmd)
-            .map_err(|e| anyhow::anyhow!("Failed to write multiple servo positions: {}", e))
+        let reply_timeout = match env::var("SERVO_TIMEOUT_MS") {
+            Ok(timeout) => Some(Duration::from_millis(timeout.parse::<u64>().context("Failed to parse SERVO_TIMEOUT_MS")?)),
+            Err(_) => None,
+        };
+        let layout = match env::var("SERVO_BUSES") {
+            Ok(spec) => parse_servo_buses(&spec).context("Failed to parse SERVO_BUSES")?,
+            Err(_) => {
+                let recorded = replay.as_ref().and_then(|replay| replay.ports().into_iter().next());
+                let port_name = env::var("SERVO_PORT")
+                    .ok()
+                    .or(recorded)
+                    .unwrap_or_else(|| "/dev/ttyUSB0".to_string());
+                let ids = match env::var("SERVO_IDS") {
+                    Ok(spec) => parse_servo_ids(&spec).context("Failed to parse SERVO_IDS")?,
+                    Err(_) => (1..=DEFAULT_SERVO_COUNT).collect(),
+                };
+                vec![(port_name, ids)]
+            }
+        };
+        let readout_rate = match env::var("SERVO_READOUT_HZ") {
+            Ok(rate) => rate.parse::<f64>().context("Failed to parse SERVO_READOUT_HZ")?,
+            Err(_) => DEFAULT_READOUT_RATE,
+        };
+        if readout_rate.is_nan() || readout_rate <= 0.0 {
+            bail!("SERVO_READOUT_HZ must be positive");
+        }
+
+        let capture = match env::var("SERVO_CAPTURE") {
+            Ok(path) => Some(Arc::new(Capture::create(path.as_ref())?)),
+            Err(_) => None,
+        };
+
+        let mut buses = Vec::new();
+        let mut routes = HashMap::new();
+        for (port_name, ids) in layout {
+            let rate = baud_rate.unwrap_or(DEFAULT_BAUD_RATE);
+            let mut serial = match &replay {
+                Some(replay) => ServoSerial::with_port(Box::new(replay.open(&port_name, rate)?), &port_name, rate),
+                None => ServoSerial::new(&port_name, rate)
+                    .map_err(|e| anyhow::anyhow!("Failed to create ServoSerial on {}: {}", port_name, e))?,
+            };
+            if let Some(timeout) = reply_timeout {
+                serial.set_reply_timeout(timeout);
+            }
+            if let Some(capture) = &capture {
+                serial.set_capture(capture.clone());
+            }
+            if baud_rate.is_none() && probe_baud_rate(&mut serial, &ids)?.is_none() {
+                eprintln!("No servo on {} answered at any baud rate, using {}", port_name, DEFAULT_BAUD_RATE);
+            }
+
+            routes.extend(ids.iter().map(|&id| (id, buses.len())));
+            buses.push(Arc::new(Poller {
+                port: port_name,
+                serial: Mutex::new(serial),
+                ids,
+                readout: Mutex::new(HashMap::new()),
+                snapshot: Mutex::new(None),
+                last_error: Mutex::new(None),
+                sync_read: AtomicU8::new(SYNC_READ_UNKNOWN),
+                task_run_count: AtomicU32::new(0),
+            }));
+        }
+
+        Ok(Servo {
+            buses,
+            routes,
+            readout_period: Duration::from_secs_f64(1.0 / readout_rate),
+            readout_threads: Mutex::new(Vec::new()),
+            movement_enabled: AtomicBool::new(true),
+            replay,
+        })
     }
 
-    pub fn read_pid(&self, id: u8) -> Result<(u8, u8, u8)> {
-        let p = self.read(id, ServoRegister::PProportionalCoeff, 1)?[0];
-        let i = self.read(id, ServoRegister::IIntegralCoeff, 1)?[0];
-        let d = self.read(id, ServoRegister::DDifferentialCoeff, 1)?[0];
-        Ok((p, i, d))
+    /// The capture being played back, if any.
+    pub fn replay(&self) -> Option<&Arc<Replay>> {
+        self.replay.as_ref()
     }
 
-    pub fn set_pid(&self, id: u8, p: u8, i: u8, d: u8) -> Result<()> {
-        // Unlock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Unlocked as u8])?;
+    fn bus(&self, id: u8) -> &Poller {
+        &self.buses[self.routes.get(&id).copied().unwrap_or(0)]
+    }
 
-        // Set PID parameters
-        self.write(id, ServoRegister::PProportionalCoeff, &[p])?;
-        self.write(id, ServoRegister::IIntegralCoeff, &[i])?;
-        self.write(id, ServoRegister::DDifferentialCoeff, &[d])?;
+    // Runs `f` on every bus, in parallel when there is more than one
+    fn each_bus<T: Send>(&self, f: impl Fn(&Poller) -> T + Sync) -> Vec<T> {
+        if let [bus] = self.buses.as_slice() {
+            return vec![f(bus)];
+        }
+        thread::scope(|scope| {
+            let handles: Vec<_> = self.buses.iter().map(|bus| scope.spawn(|| f(bus))).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        })
+    }
 
-        // Lock flash
-        self.write(id, ServoRegister::LockMark, &[MemoryLockState::Locked as u8])?;
+    // Combines per-bus readouts into one
+    fn merge(&self, parts: Vec<Result<ServoData>>) -> Result<ServoData> {
+        let mut data = ServoData {
+            servos: BTreeMap::new(),
+            task_run_count: u32::MAX,
+        };
+        for (bus, part) in self.buses.iter().zip(parts) {
+            let part = part.with_context(|| format!("Servo readout on {} failed", bus.port))?;
+            data.servos.extend(part.servos);
+            data.task_run_count = data.task_run_count.min(part.task_run_count);
+        }
+        Ok(data)
+    }
+}
 
-        Ok(())
+impl Drop for Servo {
+    fn drop(&mut self) {
+        let _ = self.disable_readout();
+        // Tools don't check replays themselves, so say when one didn't line up
+        if let Some(Err(e)) = self.replay.as_ref().map(|replay| replay.finish()) {
+            eprintln!("{:#}", e);
+        }
     }
+}
 
-    pub fn set_memory_lock(&self, id: u8, state: MemoryLockState) -> Result<()> {
-        self.write(id, ServoRegister::LockMark, &[state as u8])
+impl ServoBus for Servo {
+    fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+        if id == SERVO_BROADCAST_ID {
+            for result in self.each_bus(|bus| bus.serial.lock().unwrap().servo_write(id, register as u8, data)) {
+                result?;
+            }
+            return Ok(());
+        }
+        let mut serial = self.bus(id).serial.lock().unwrap();
+        Ok(serial.servo_write(id, register as u8, data)?)
     }
 
-    pub fn read_angle_limits(&self, id: u8) -> Result<(i16, i16)> {
-        let min_limit = i16::from_le_bytes(self.read(id, ServoRegister::MinAngleLimit, 2)?.try_into().unwrap());
-        let max_limit = i16::from_le_bytes(self.read(id, ServoRegister::MaxAngleLimit, 2)?.try_into().unwrap());
-        Ok((min_limit, max_limit))
+    fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+        let mut serial = self.bus(id).serial.lock().unwrap();
+        Ok(serial.servo_read(id, register as u8, length)?)
     }
 
-    pub fn set_torque_mode(&self, id: u8, mode: TorqueMode) -> Result<()> {
-        self.write(id, ServoRegister::TorqueSwitch, &[mode as u8])
+    fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
+        let mut serial = self.bus(id).serial.lock().unwrap();
+        Ok(serial.servo_move(id, position, time, speed)?)
     }
 
-    pub fn write_servo_memory(&self, id: u8, register: ServoRegister, value: u16) -> Result<()> {
-        let data = value.to_le_bytes();
-        self.write(id, register, &data)
+    fn read_info(&self, id: u8) -> Result<ServoInfo> {
+        self.bus(id).read_info(id)
     }
 
-    pub fn scan(&self, id: u8) -> Result<bool> {
-        match self.read(id, ServoRegister::ID, 1) {
-            Ok(data) if data.len() == 1 && data[0] == id => Ok(true),
-            Ok(_) => Ok(false),
-            Err(_) => Ok(false),
+    /// The readout threads' latest snapshots, or a fresh poll of every bus while readout is
+    /// disabled. Each poll fetches the feedback registers of a bus's servos with one
+    /// SYNC_READ; the setpoints are refreshed every `FULL_REFRESH_INTERVAL` polls.
+    fn read_continuous(&self) -> Result<ServoData> {
+        if self.readout_threads.lock().unwrap().is_empty() {
+            return self.merge(self.each_bus(Poller::poll));
         }
+        self.merge(self.buses.iter().map(|bus| bus.latest()).collect())
     }
 
-    pub fn degrees_to_raw(degrees: f32) -> u16 {
-        // Ensure the input is within the valid range
-        let clamped_degrees = degrees.max(-180.0).min(180.0);
-        
-        // Convert degrees to raw value
-        let raw = (clamped_degrees + 180.0) / 360.0 * 4096.0;
-        
-        // Round to nearest integer and ensure it's within the valid range
-        raw.round().max(0.0).min(4095.0) as u16
+    fn ids(&self) -> Vec<u8> {
+        let mut ids: Vec<u8> = self.routes.keys().copied().collect();
+        ids.sort_unstable();
+        ids
     }
 
-    pub fn raw_to_degrees(raw: u16) -> f32 {
-        // Ensure the input is within the valid range
-        let clamped_raw = raw.max(0).min(4095);
-        
-        // Convert raw value to degrees
-        let degrees = (clamped_raw as f32 / 4096.0) * 360.0 - 180.0;
-        
-        // Round to two decimal places
-        (degrees * 100.0).round() / 100.0;
+    fn last_update(&self, id: u8) -> Option<Instant> {
+        self.bus(id).readout.lock().unwrap().get(&id).map(|entry| entry.updated)
+    }
+
+    fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
+        // Mirror the MilkV firmware: position streams are dropped while movement is disabled
+        if !self.movement_enabled.load(Ordering::SeqCst) {
+            return Ok(());
+        }
 
-        // clamp to -180.0 to 180.0
-        degrees.max(-180.0).min(180.0)
+        // One SYNC_WRITE per bus, carrying only that bus's servos
+        let mut targets = vec![Vec::new(); self.buses.len()];
+        for target in &cmd.targets {
+            targets[self.routes.get(&target.id).copied().unwrap_or(0)].push(*target);
+        }
+        for (bus, targets) in self.buses.iter().zip(&targets) {
+            if !targets.is_empty() {
+                bus.serial.lock().unwrap().servo_move_multiple_sync(targets, cmd.only_write_positions, cmd.write_accelerations)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn enable_readout(&self) -> Result<()> {
+    fn stage_move(&self, staged: &StagedMove) -> Result<()> {
+        let mut serial = self.bus(staged.id).serial.lock().unwrap();
+        Ok(serial.servo_reg_write(staged.id, ServoRegister::Acceleration as u8, &staged.to_registers())?)
+    }
+
+    /// Broadcasts ACTION on every bus at once.
+    fn action(&self) -> Result<()> {
+        for result in self.each_bus(|bus| bus.serial.lock().unwrap().servo_action()) {
+            result?;
+        }
         Ok(())
     }
 
-    pub fn disable_readout(&self) -> Result<()> {
+    fn enable_readout(&self) -> Result<()> {
+        let mut readout_threads = self.readout_threads.lock().unwrap();
+        if !readout_threads.is_empty() {
+            return Ok(());
+        }
+
+        // Poll once up front so read_continuous has data as soon as this returns
+        self.merge(self.each_bus(Poller::poll))?;
+
+        for bus in &self.buses {
+            let running = Arc::new(AtomicBool::new(true));
+            let poller = bus.clone();
+            let period = self.readout_period;
+            let handle = thread::Builder::new()
+                .name("servo-readout".to_string())
+                .spawn({
+                    let running = running.clone();
+                    move || poller.run(period, &running)
+                })
+                .context("Failed to start servo readout thread")?;
+            readout_threads.push(ReadoutThread { running, handle });
+        }
         Ok(())
     }
-}
 
-impl Drop for Servo {
-    fn drop(&mut self) {
-        // No need for explicit deinitialization in this implementation
+    fn disable_readout(&self) -> Result<()> {
+        let readout_threads = std::mem::take(&mut *self.readout_threads.lock().unwrap());
+        for ReadoutThread { running, .. } in &readout_threads {
+            running.store(false, Ordering::SeqCst);
+        }
+        for ReadoutThread { handle, .. } in readout_threads {
+            handle.join().map_err(|_| anyhow::anyhow!("Servo readout thread panicked"))?;
+        }
+        Ok(())
+    }
+
+    fn enable_movement(&self) -> Result<()> {
+        self.movement_enabled.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn disable_movement(&self) -> Result<()> {
+        self.movement_enabled.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn bus_stats(&self) -> BusStats {
+        let mut stats = BusStats::default();
+        for bus in &self.buses {
+            stats.merge(bus.serial.lock().unwrap().stats());
+        }
+        stats
+    }
+
+    fn clear_bus_stats(&self) {
+        for bus in &self.buses {
+            bus.serial.lock().unwrap().clear_stats();
+        }
+    }
+
+    /// The rate all buses run at, or `None` if they differ.
+    fn baud_rate(&self) -> Option<u32> {
+        let rates: Vec<u32> = self.buses.iter().map(|bus| bus.serial.lock().unwrap().baud_rate()).collect();
+        rates.iter().all(|&rate| rate == rates[0]).then_some(rates[0])
+    }
+
+    /// Migrates the configured servos that answer, one bus after another; servos left out
+    /// stay at the old rate. If a bus fails, the buses migrated before it are put back.
+    fn migrate_baud_rate(&self, baud_rate: u32) -> Result<()> {
+        // Polls would stall on the bus while servos are between rates
+        let reading = !self.readout_threads.lock().unwrap().is_empty();
+        self.disable_readout()?;
+
+        let mut result = Ok(());
+        let mut migrated = Vec::new();
+        for bus in &self.buses {
+            let old_rate = bus.serial.lock().unwrap().baud_rate();
+            let Err(e) = bus.migrate_baud_rate(baud_rate) else {
+                migrated.push((bus, old_rate));
+                continue;
+            };
+
+            let stranded: Vec<&str> = migrated
+                .iter()
+                .rev()
+                .filter(|(bus, old_rate)| bus.migrate_baud_rate(*old_rate).is_err())
+                .map(|(bus, _)| bus.port.as_str())
+                .collect();
+            result = Err(if stranded.is_empty() {
+                e.context(format!("Baud rate migration on {} failed, the other buses were put back", bus.port))
+            } else {
+                e.context(format!("Baud rate migration on {} failed, buses {:?} could not be put back", bus.port, stranded))
+            });
+            break;
+        }
+        if reading {
+            let restarted = self.enable_readout();
+            result?;
+            return restarted;
+        }
+        result
     }
 }
 
 pub struct IMU {}
 
 impl IMU {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new() -> Result<Self> {
         Ok(IMU {})
     }
+}
 
-    pub fn read_data(&mut self) -> Result<IMUData, Box<dyn std::error::Error>> {
+impl ImuSource for IMU {
+    fn read_data(&self) -> Result<IMUData> {
         Ok(IMUData {
             acc_x: 0.0,
             acc_y: 0.0,
@@ -577,4 +1319,146 @@ impl IMU {
             gyro_z: 0.0,
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::hal::hex;
+
+    // A checksummed packet: instruction or status byte, then parameters
+    fn packet(id: u8, instruction: u8, params: &[u8]) -> Vec<u8> {
+        let mut packet = vec![SERVO_START_BYTE, SERVO_START_BYTE, id, params.len() as u8 + 2, instruction];
+        packet.extend_from_slice(params);
+        let sum: u32 = packet[2..].iter().map(|&byte| byte as u32).sum();
+        packet.push(!(sum as u8));
+        packet
+    }
+
+    fn read_request(id: u8) -> Vec<u8> {
+        packet(id, SERVO_CMD_READ, &[SERVO_ADDR_CURRENT_POSITION, 2])
+    }
+
+    // A port on which each request is answered with exactly the given bytes
+    fn serial(name: &str, exchanges: &[(Vec<u8>, Vec<u8>)]) -> ServoSerial {
+        let mut text = String::from("# sts capture v1\n");
+        for (request, reply) in exchanges {
+            text += &format!("0.000000 test > {}\n", hex(request));
+            if !reply.is_empty() {
+                text += &format!("0.000000 test < {}\n", hex(reply));
+            }
+        }
+        let path = std::env::temp_dir().join(format!("sts-serial-{}-{}.cap", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let replay = Arc::new(Replay::load(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let port = replay.open("test", 1_000_000).unwrap();
+        let mut serial = ServoSerial::with_port(Box::new(port), "test", 1_000_000);
+        serial.set_reply_timeout(Duration::from_millis(10));
+        serial
+    }
+
+    fn frame(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut framer = PacketFramer::default();
+        bytes.iter().filter_map(|&byte| framer.push(byte)).collect()
+    }
+
+    #[test]
+    fn framer_skips_noise_before_header() {
+        let reply = packet(1, 0, &[0x34, 0x12]);
+        let stream = [&[0x00, 0xFF, 0x42][..], &reply].concat();
+        assert_eq!(frame(&stream), vec![reply]);
+    }
+
+    #[test]
+    fn framer_allows_padding_before_id() {
+        let reply = packet(3, 0, &[]);
+        let stream = [&[0xFF][..], &reply].concat();
+        assert_eq!(frame(&stream), vec![reply]);
+    }
+
+    #[test]
+    fn framer_drops_impossible_length() {
+        let reply = packet(2, 0, &[7]);
+        let stream = [&[0xFF, 0xFF, 2, 1][..], &reply].concat();
+        assert_eq!(frame(&stream), vec![reply]);
+    }
+
+    #[test]
+    fn framer_joins_split_packets() {
+        let reply = packet(1, 0, &[1, 2, 3, 4]);
+        let (first, second) = reply.split_at(6);
+        let mut framer = PacketFramer::default();
+        assert!(first.iter().all(|&byte| framer.push(byte).is_none()));
+        assert_eq!(framer.partial(), Some((reply.len(), 6)));
+
+        let packets: Vec<_> = second.iter().filter_map(|&byte| framer.push(byte)).collect();
+        assert_eq!(packets, vec![reply]);
+        assert_eq!(framer.partial(), None);
+    }
+
+    #[test]
+    fn read_skips_echo_and_noise() {
+        let request = read_request(1);
+        let stream = [&request[..], &[0x00, 0x13], &packet(1, 0, &[0x00, 0x08])].concat();
+        let mut serial = serial("echo", &[(request, stream)]);
+        assert_eq!(serial.servo_read(1, SERVO_ADDR_CURRENT_POSITION, 2).unwrap(), vec![0x00, 0x08]);
+    }
+
+    #[test]
+    fn read_rescans_after_bad_checksum() {
+        // A stray header swallows the start of the reply, so that packet fails its checksum
+        let stream = [&[0xFF, 0xFF, 0x01, 0x03][..], &packet(1, 0, &[0x00, 0x08])].concat();
+        let mut serial = serial("rescan", &[(read_request(1), stream)]);
+        assert_eq!(serial.servo_read(1, SERVO_ADDR_CURRENT_POSITION, 2).unwrap(), vec![0x00, 0x08]);
+    }
+
+    #[test]
+    fn read_reports_bad_checksum() {
+        let mut reply = packet(1, 0, &[0x00, 0x08]);
+        *reply.last_mut().unwrap() ^= 0xFF;
+        let mut serial = serial("checksum", &[(read_request(1), reply)]);
+        let result = serial.servo_read(1, SERVO_ADDR_CURRENT_POSITION, 2);
+        assert!(matches!(result, Err(ServoError::ChecksumMismatch { id: 1, .. })), "{:?}", result);
+    }
+
+    #[test]
+    fn read_reports_reply_from_other_servo() {
+        let mut serial = serial("other", &[(read_request(1), packet(2, 0, &[0x00, 0x08]))]);
+        let result = serial.servo_read(1, SERVO_ADDR_CURRENT_POSITION, 2);
+        assert!(matches!(result, Err(ServoError::IdMismatch { expected: 1, received: 2 })), "{:?}", result);
+    }
+
+    #[test]
+    fn read_reports_truncated_reply() {
+        let reply = packet(1, 0, &[0x00, 0x08]);
+        let mut serial = serial("truncated", &[(read_request(1), reply[..5].to_vec())]);
+        let result = serial.servo_read(1, SERVO_ADDR_CURRENT_POSITION, 2);
+        assert!(matches!(result, Err(ServoError::TruncatedPacket { id: 1, expected: 8, received: 5 })), "{:?}", result);
+    }
+
+    #[test]
+    fn read_times_out_at_deadline() {
+        let mut serial = serial("timeout", &[(read_request(1), Vec::new())]);
+        let start = Instant::now();
+        let result = serial.servo_read(1, SERVO_ADDR_CURRENT_POSITION, 2);
+        assert!(matches!(result, Err(ServoError::Timeout { id: 1 })), "{:?}", result);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn sync_read_reports_each_servo() {
+        let request = packet(SERVO_BROADCAST_ID, SERVO_CMD_SYNC_READ, &[SERVO_ADDR_CURRENT_POSITION, 2, 1, 2, 3]);
+        // Out of order, and servo 3 never answers
+        let stream = [packet(2, 0, &[0x02, 0x00]), packet(1, 0, &[0x01, 0x00])].concat();
+        let mut serial = serial("sync", &[(request, stream)]);
+
+        let replies = serial.servo_sync_read(&[1, 2, 3], SERVO_ADDR_CURRENT_POSITION, 2).unwrap();
+        assert_eq!(replies[0].as_ref().unwrap(), &vec![0x01, 0x00]);
+        assert_eq!(replies[1].as_ref().unwrap(), &vec![0x02, 0x00]);
+        assert!(matches!(replies[2], Err(ServoError::Timeout { id: 3 })), "{:?}", replies[2]);
+    }
+}
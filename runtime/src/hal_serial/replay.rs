@@ -0,0 +1,366 @@
+use anyhow::{Result, bail};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::hal::{hex, read_capture, CapturedPacket, Direction};
+
+/// A recorded bus session (see [`Capture`](crate::hal::Capture)) played back in place of
+/// the serial ports, enabled with `SERVO_REPLAY=<file>`.
+///
+/// Every request written to a port has to match the next one recorded on that port, and is
+/// answered with exactly what was received after it, so corrupted replies, echoes,
+/// answers from the wrong servo and timeouts come back as they happened. The first request
+/// that differs is a divergence: it and everything after it on that port fail with an I/O
+/// error. Replays only line up when the session is repeated with the same settings
+/// (`SERVO_IDS`, `SERVO_BAUD_RATE`, retries) and the same commands in the same order.
+///
+/// A regression test opens the serial backend on a replay, runs the steps that were
+/// recorded and checks that the whole capture was used:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use runtime::hal_serial::{Replay, Servo};
+/// # use runtime::hal::ServoBus;
+/// let replay = Arc::new(Replay::load("captures/wrong_id.cap".as_ref())?);
+/// let servo = Servo::from_replay(replay.clone())?;
+/// assert!(servo.read_info(3).is_err());
+/// replay.finish()?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug)]
+pub struct Replay {
+    path: PathBuf,
+    ports: Mutex<HashMap<String, Session>>,
+}
+
+#[derive(Debug, Default)]
+struct Session {
+    packets: Vec<CapturedPacket>,
+    next: usize,
+    divergence: Option<String>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut ports: HashMap<String, Session> = HashMap::new();
+        for packet in read_capture(path)? {
+            ports.entry(packet.port.clone()).or_default().packets.push(packet);
+        }
+        Ok(Replay { path: path.to_path_buf(), ports: Mutex::new(ports) })
+    }
+
+    /// Ports in the capture, sorted.
+    pub fn ports(&self) -> Vec<String> {
+        let mut ports: Vec<String> = self.ports.lock().unwrap().keys().cloned().collect();
+        ports.sort();
+        ports
+    }
+
+    /// A serial port that plays back what was recorded on `port`.
+    pub fn open(self: &Arc<Self>, port: &str, baud_rate: u32) -> Result<ReplayPort> {
+        if !self.ports.lock().unwrap().contains_key(port) {
+            bail!("{} has no packets on {}, it has {}", self.path.display(), port, self.ports().join(", "));
+        }
+        Ok(ReplayPort {
+            replay: self.clone(),
+            port: port.to_string(),
+            input: Mutex::new(VecDeque::new()),
+            baud_rate,
+            timeout: Duration::ZERO,
+        })
+    }
+
+    /// Fails if a port diverged or recorded requests were never sent.
+    pub fn finish(&self) -> Result<()> {
+        let ports = self.ports.lock().unwrap();
+        let mut names: Vec<&String> = ports.keys().collect();
+        names.sort();
+        for port in names {
+            let session = &ports[port];
+            if let Some(divergence) = &session.divergence {
+                bail!("{}", divergence);
+            }
+            let remaining: Vec<&CapturedPacket> = session.packets[session.next..]
+                .iter()
+                .filter(|packet| packet.direction == Direction::Sent)
+                .collect();
+            if let Some(first) = remaining.first() {
+                bail!(
+                    "Replay of {} stopped with {} recorded requests left, the first at {:.6}s: {}",
+                    port,
+                    remaining.len(),
+                    first.time.as_secs_f64(),
+                    hex(&first.bytes)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Matches a request against the recording and returns the bytes received after it
+    fn request(&self, port: &str, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut ports = self.ports.lock().unwrap();
+        let session = ports.get_mut(port).expect("replay ports are checked when opened");
+        if let Some(divergence) = &session.divergence {
+            return Err(io::Error::other(divergence.clone()));
+        }
+
+        let expected = session.packets[session.next..].iter().position(|packet| packet.direction == Direction::Sent);
+        let Some(index) = expected.map(|offset| session.next + offset) else {
+            let divergence = format!("Replay of {} diverged: {} sent after the end of the capture", port, hex(bytes));
+            session.divergence = Some(divergence.clone());
+            return Err(io::Error::other(divergence));
+        };
+        let recorded = &session.packets[index];
+        if recorded.bytes != bytes {
+            let divergence = format!(
+                "Replay of {} diverged at {:.6}s: sent {} instead of {}",
+                port,
+                recorded.time.as_secs_f64(),
+                hex(bytes),
+                hex(&recorded.bytes)
+            );
+            session.divergence = Some(divergence.clone());
+            return Err(io::Error::other(divergence));
+        }
+
+        let replies = session.packets[index + 1..]
+            .iter()
+            .take_while(|packet| packet.direction == Direction::Received)
+            .count();
+        session.next = index + 1 + replies;
+        Ok(session.packets[index + 1..session.next].iter().flat_map(|packet| packet.bytes.iter().copied()).collect())
+    }
+}
+
+/// One port of a [`Replay`].
+#[derive(Debug)]
+pub struct ReplayPort {
+    replay: Arc<Replay>,
+    port: String,
+    input: Mutex<VecDeque<u8>>,
+    baud_rate: u32,
+    timeout: Duration,
+}
+
+impl Read for ReplayPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.lock().unwrap();
+        if input.is_empty() {
+            drop(input);
+            // Nothing else was received, so wait out the timeout like a real port
+            thread::sleep(self.timeout);
+            return Err(io::Error::new(ErrorKind::TimedOut, "Operation timed out"));
+        }
+        let n = buf.len().min(input.len());
+        for (slot, byte) in buf.iter_mut().zip(input.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for ReplayPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let replies = self.replay.request(&self.port, buf)?;
+        self.input.lock().unwrap().extend(replies);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for ReplayPort {
+    fn name(&self) -> Option<String> {
+        Some(self.port.clone())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _parity: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.input.lock().unwrap().len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+            self.input.lock().unwrap().clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Err(serialport::Error::new(serialport::ErrorKind::Unknown, "A replayed port can't be cloned"))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const CAPTURE: &str = "\
+# sts capture v1 started test
+0.000000 a > 01 02
+0.000100 a < 03
+0.000200 a < 04 05
+0.001000 b > 06
+0.002000 a > 07
+0.003000 a > 08
+0.003100 a < 09
+";
+
+    fn replay(name: &str) -> Arc<Replay> {
+        let path = std::env::temp_dir().join(format!("sts-replay-{}-{}.cap", std::process::id(), name));
+        fs::write(&path, CAPTURE).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        Arc::new(replay)
+    }
+
+    fn received(port: &mut ReplayPort) -> Vec<u8> {
+        let mut buf = [0; 16];
+        let n = port.read(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[test]
+    fn answers_with_what_was_received() {
+        let replay = replay("answers");
+        assert_eq!(replay.ports(), ["a", "b"]);
+        let mut a = replay.open("a", 1_000_000).unwrap();
+        let mut b = replay.open("b", 1_000_000).unwrap();
+
+        a.write_all(&[0x01, 0x02]).unwrap();
+        assert_eq!(received(&mut a), [0x03, 0x04, 0x05]);
+        // Ports play back independently, and a recorded timeout answers nothing
+        b.write_all(&[0x06]).unwrap();
+        a.write_all(&[0x07]).unwrap();
+        assert_eq!(a.bytes_to_read().unwrap(), 0);
+        assert_eq!(a.read(&mut [0; 4]).unwrap_err().kind(), ErrorKind::TimedOut);
+        a.write_all(&[0x08]).unwrap();
+        assert_eq!(received(&mut a), [0x09]);
+
+        replay.finish().unwrap();
+    }
+
+    #[test]
+    fn fails_from_the_first_divergence_on() {
+        let replay = replay("diverges");
+        let mut a = replay.open("a", 1_000_000).unwrap();
+
+        let error = a.write_all(&[0x01, 0x03]).unwrap_err();
+        assert!(error.to_string().contains("sent 01 03 instead of 01 02"), "{}", error);
+        // Even the request that was recorded next
+        assert!(a.write_all(&[0x01, 0x02]).is_err());
+        assert!(replay.finish().unwrap_err().to_string().contains("diverged at 0.000000s"));
+    }
+
+    #[test]
+    fn fails_past_the_end() {
+        let replay = replay("end");
+        let mut b = replay.open("b", 1_000_000).unwrap();
+        b.write_all(&[0x06]).unwrap();
+        let error = b.write_all(&[0x06]).unwrap_err();
+        assert!(error.to_string().contains("after the end of the capture"), "{}", error);
+    }
+
+    #[test]
+    fn finish_reports_unsent_requests() {
+        let replay = replay("unsent");
+        let mut a = replay.open("a", 1_000_000).unwrap();
+        a.write_all(&[0x01, 0x02]).unwrap();
+        let error = replay.finish().unwrap_err().to_string();
+        assert!(error.contains("a stopped with 2 recorded requests left, the first at 0.002000s: 07"), "{}", error);
+        assert!(replay.open("c", 1_000_000).is_err());
+    }
+}
@@ -0,0 +1,729 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use crate::hal::{parse_servo_ids, PWM_DUTY_ENCODING, ServoBus, ServoError, ImuSource, ServoInfo, ServoRegister, ServoData, ServoMultipleWriteCommand, StagedMove, ServoMode, IMUData, SERVO_INFO_LENGTH};
+
+// Size of the simulated control table, up to and including the high byte of CurrentCurrent
+const REGISTER_SPACE: usize = 0x47;
+const BROADCAST_ID: u8 = 0xFE;
+
+// Motion model
+const MAX_SPEED: f32 = 3400.0; // ticks/s when RunningSpeed is 0
+const MAX_ACCELERATION: f32 = 50_000.0; // ticks/s^2 when Acceleration is 0
+const ACCELERATION_UNIT: f32 = 100.0; // ticks/s^2 per Acceleration LSB
+const POSITION_GAIN: f32 = 20.0; // 1/s, ~50 ms first-order time constant
+const COAST_DECELERATION: f32 = 8_000.0; // ticks/s^2 with torque off
+const FRICTION_EFFORT: f32 = 0.3; // share of full load spent on friction at MAX_SPEED
+const MOVING_THRESHOLD: f32 = 5.0; // ticks/s
+const SIM_STEP: Duration = Duration::from_millis(1);
+// Longest stretch simulated in one go; after a longer pause the servos resume where they were
+const MAX_CATCH_UP: Duration = Duration::from_millis(500);
+
+// Electrical and thermal model
+const CURRENT_LSB_MA: f32 = 6.5;
+const IDLE_CURRENT_MA: f32 = 15.0;
+const STALL_CURRENT_MA: f32 = 2700.0;
+const SUPPLY_VOLTAGE: f32 = 7.4;
+const SUPPLY_RESISTANCE: f32 = 0.3; // volts of sag per amp
+const AMBIENT_TEMPERATURE: f32 = 28.0;
+const HEATING_PER_AMP_SQUARED: f32 = 0.5; // °C/s at 1 A
+const COOLING_TIME_CONSTANT: f32 = 300.0; // s
+
+// Factory contents of the EEPROM and RAM areas, indexed by address
+const DEFAULT_REGISTERS: &[(ServoRegister, u16)] = &[
+    (ServoRegister::FirmwareMajorVersion, 3),
+    (ServoRegister::FirmwareSubVersion, 10),
+    (ServoRegister::ServoMainVersion, 9),
+    (ServoRegister::ServoSubVersion, 3),
+    (ServoRegister::BaudRate, 0),
+    (ServoRegister::ReturnDelay, 0),
+    (ServoRegister::ResponseStatusLevel, 1),
+    (ServoRegister::MinAngleLimit, 0),
+    (ServoRegister::MaxAngleLimit, 4095),
+    (ServoRegister::MaxTemperatureLimit, 70),
+    (ServoRegister::MaxInputVoltage, 140),
+    (ServoRegister::MinInputVoltage, 40),
+    (ServoRegister::MaxTorque, 1000),
+    (ServoRegister::Phase, 0x0C),
+    (ServoRegister::UnloadingCondition, 44),
+    (ServoRegister::LEDAlarmCondition, 47),
+    (ServoRegister::PProportionalCoeff, 32),
+    (ServoRegister::DDifferentialCoeff, 32),
+    (ServoRegister::MinStartupForce, 16),
+    (ServoRegister::ClockwiseInsensitiveArea, 1),
+    (ServoRegister::CounterclockwiseInsensitiveArea, 1),
+    (ServoRegister::ProtectionCurrent, 500),
+    (ServoRegister::AngularResolution, 1),
+    (ServoRegister::ProtectiveTorque, 20),
+    (ServoRegister::ProtectionTime, 200),
+    (ServoRegister::OverloadTorque, 80),
+    (ServoRegister::SpeedClosedLoopPCoeff, 10),
+    (ServoRegister::OverCurrentProtectionTime, 200),
+    (ServoRegister::VelocityClosedLoopICoeff, 200),
+    (ServoRegister::TorqueSwitch, 1),
+    (ServoRegister::TorqueLimit, 1000),
+    (ServoRegister::LockMark, 1),
+];
+
+/// One simulated STS3215: its control table plus the physical state behind it.
+#[derive(Debug, Clone)]
+pub struct SimServo {
+    registers: [u8; REGISTER_SPACE],
+    position: f32,
+    velocity: f32,
+    step_target: f32,
+    effort: f32,
+    temperature: f32,
+    pending_write: Option<(u8, Vec<u8>)>,
+}
+
+impl SimServo {
+    pub fn new(id: u8) -> Self {
+        let mut servo = SimServo {
+            registers: [0; REGISTER_SPACE],
+            position: 2048.0,
+            velocity: 0.0,
+            step_target: 2048.0,
+            effort: 0.0,
+            temperature: AMBIENT_TEMPERATURE,
+            pending_write: None,
+        };
+        servo.load_defaults(id);
+        servo
+    }
+
+    fn load_defaults(&mut self, id: u8) {
+        self.registers = [0; REGISTER_SPACE];
+        for &(register, value) in DEFAULT_REGISTERS {
+            let address = register as usize;
+            if register.info().size == 2 {
+                self.set_u16(address, value);
+            } else {
+                self.registers[address] = value as u8;
+            }
+        }
+        self.registers[ServoRegister::ID as usize] = id;
+        self.set_u16(ServoRegister::TargetLocation as usize, self.position as u16);
+        self.sync_feedback();
+    }
+
+    pub fn id(&self) -> u8 {
+        self.registers[ServoRegister::ID as usize]
+    }
+
+    /// Reads `length` bytes of the control table, or `None` if the range is out of bounds.
+    pub fn read(&self, address: u8, length: u8) -> Option<Vec<u8>> {
+        let start = address as usize;
+        let end = start + length as usize;
+        if length == 0 || end > REGISTER_SPACE {
+            return None;
+        }
+        Some(self.registers[start..end].to_vec())
+    }
+
+    /// Writes to the control table. Version and feedback registers are read-only and
+    /// silently keep their values, like on the real servo.
+    pub fn write(&mut self, address: u8, data: &[u8]) -> bool {
+        let start = address as usize;
+        if data.is_empty() || start + data.len() > REGISTER_SPACE {
+            return false;
+        }
+
+        let previous_offset = self.position_offset();
+        for (i, &byte) in data.iter().enumerate() {
+            let register = start + i;
+            if is_writable(register) {
+                self.registers[register] = byte;
+            }
+        }
+
+        let written = start..start + data.len();
+        if written.contains(&(ServoRegister::PositionCorrection as usize)) {
+            // The offset moves the reported zero, not the horn
+            let delta = self.position_offset() - previous_offset;
+            self.position -= delta;
+            self.step_target -= delta;
+        }
+        if written.contains(&(ServoRegister::OperationMode as usize)) && self.mode() != ServoMode::StepServo as u8 {
+            // Outside step mode the position only spans one turn
+            self.position = self.position.rem_euclid(4096.0);
+        }
+        if written.contains(&(ServoRegister::TargetLocation as usize)) && self.mode() == ServoMode::StepServo as u8 {
+            let steps = self.value(ServoRegister::TargetLocation);
+            self.step_target = self.position + steps as f32;
+        }
+        self.sync_feedback();
+        true
+    }
+
+    /// Buffers a write until the next ACTION.
+    pub fn reg_write(&mut self, address: u8, data: &[u8]) -> bool {
+        if data.is_empty() || address as usize + data.len() > REGISTER_SPACE {
+            return false;
+        }
+        self.registers[ServoRegister::AsyncWriteFlag as usize] = 1;
+        self.pending_write = Some((address, data.to_vec()));
+        true
+    }
+
+    pub fn action(&mut self) {
+        if let Some((address, data)) = self.pending_write.take() {
+            self.registers[ServoRegister::AsyncWriteFlag as usize] = 0;
+            self.write(address, &data);
+        }
+    }
+
+    /// Restores the factory control table, keeping the ID.
+    pub fn reset(&mut self) {
+        let id = self.id();
+        self.velocity = 0.0;
+        self.step_target = self.position;
+        self.pending_write = None;
+        self.load_defaults(id);
+    }
+
+    /// Advances the motion, electrical and thermal model by `dt` seconds.
+    pub fn step(&mut self, dt: f32) {
+        let torque_on = self.registers[ServoRegister::TorqueSwitch as usize] != 0;
+        let torque_limit = (self.u16_at(ServoRegister::TorqueLimit as usize).min(1000) as f32) / 1000.0;
+        let mode = self.mode();
+
+        let desired_velocity = if !torque_on {
+            0.0
+        } else if mode == ServoMode::ConstantSpeed as u8 {
+            let speed = self.value(ServoRegister::RunningSpeed) as f32;
+            speed.clamp(-MAX_SPEED, MAX_SPEED)
+        } else if mode == ServoMode::PWMOpenLoop as u8 {
+            self.pwm_duty() * MAX_SPEED
+        } else {
+            // Slow enough to brake to a stop at the target within the acceleration limit
+            let error = self.target() - self.position;
+            let limit = self.speed_limit().min((2.0 * self.acceleration() * error.abs()).sqrt());
+            (POSITION_GAIN * error).clamp(-limit, limit)
+        };
+
+        let max_change = if torque_on {
+            self.acceleration()
+        } else {
+            COAST_DECELERATION
+        } * dt;
+        let change = (desired_velocity - self.velocity).clamp(-max_change, max_change);
+        self.velocity += change;
+        self.position += self.velocity * dt;
+
+        if mode == ServoMode::Position as u8 {
+            if let Some((min, max)) = self.angle_limits() {
+                if self.position < min || self.position > max {
+                    self.position = self.position.clamp(min, max);
+                    self.velocity = 0.0;
+                }
+            }
+        } else if mode != ServoMode::StepServo as u8 {
+            self.position = self.position.rem_euclid(4096.0);
+        }
+
+        // Effort goes into accelerating the load and overcoming friction
+        let demand = if mode == ServoMode::PWMOpenLoop as u8 {
+            self.pwm_duty()
+        } else {
+            change / dt / MAX_ACCELERATION * (1.0 - FRICTION_EFFORT) + self.velocity / MAX_SPEED * FRICTION_EFFORT
+        };
+        self.effort = if torque_on { demand.clamp(-1.0, 1.0) * torque_limit } else { 0.0 };
+
+        let current = self.current_amps();
+        self.temperature += (current * current * HEATING_PER_AMP_SQUARED
+            - (self.temperature - AMBIENT_TEMPERATURE) / COOLING_TIME_CONSTANT) * dt;
+
+        self.sync_feedback();
+    }
+
+    pub fn info(&self) -> ServoInfo {
+        let start = ServoRegister::TorqueSwitch as usize;
+        let block = &self.registers[start..start + SERVO_INFO_LENGTH as usize];
+        ServoInfo::from_registers(block.try_into().unwrap())
+    }
+
+    fn mode(&self) -> u8 {
+        self.registers[ServoRegister::OperationMode as usize]
+    }
+
+    fn target(&self) -> f32 {
+        if self.mode() == ServoMode::StepServo as u8 {
+            return self.step_target;
+        }
+        let target = self.u16_at(ServoRegister::TargetLocation as usize) as f32;
+        match self.angle_limits() {
+            Some((min, max)) => target.clamp(min, max),
+            None => target,
+        }
+    }
+
+    fn pwm_duty(&self) -> f32 {
+        let duty = PWM_DUTY_ENCODING.decode(self.u16_at(ServoRegister::RunningTime as usize)) as f32 / 1000.0;
+        duty.clamp(-1.0, 1.0)
+    }
+
+    fn acceleration(&self) -> f32 {
+        match self.registers[ServoRegister::Acceleration as usize] {
+            0 => MAX_ACCELERATION,
+            acceleration => acceleration as f32 * ACCELERATION_UNIT,
+        }
+    }
+
+    fn speed_limit(&self) -> f32 {
+        match self.u16_at(ServoRegister::RunningSpeed as usize) & 0x7FFF {
+            0 => MAX_SPEED,
+            speed => (speed as f32).min(MAX_SPEED),
+        }
+    }
+
+    // Both limits at zero put the servo in unrestricted (multi-turn) operation
+    fn angle_limits(&self) -> Option<(f32, f32)> {
+        let min = self.u16_at(ServoRegister::MinAngleLimit as usize);
+        let max = self.u16_at(ServoRegister::MaxAngleLimit as usize);
+        if min == 0 && max == 0 {
+            None
+        } else {
+            Some((min as f32, max as f32))
+        }
+    }
+
+    fn position_offset(&self) -> f32 {
+        self.value(ServoRegister::PositionCorrection) as f32
+    }
+
+    fn current_amps(&self) -> f32 {
+        (IDLE_CURRENT_MA + self.effort.abs() * STALL_CURRENT_MA) / 1000.0
+    }
+
+    fn sync_feedback(&mut self) {
+        let position = if self.mode() == ServoMode::StepServo as u8 {
+            encode(ServoRegister::CurrentLocation, self.position.round().clamp(-32767.0, 32767.0) as i32)
+        } else {
+            self.position.round().rem_euclid(4096.0) as u16
+        };
+        let speed = encode(ServoRegister::CurrentSpeed, self.velocity.round().clamp(-32767.0, 32767.0) as i32);
+        let load = encode(ServoRegister::CurrentLoad, (self.effort * 1000.0).round() as i32);
+        let current = self.current_amps();
+        let voltage = ((SUPPLY_VOLTAGE - current * SUPPLY_RESISTANCE) * 10.0).round() as u8;
+        let temperature = self.temperature.round().clamp(0.0, 255.0) as u8;
+
+        let mut status = 0;
+        let r = &self.registers;
+        if voltage < r[ServoRegister::MinInputVoltage as usize] || voltage > r[ServoRegister::MaxInputVoltage as usize] {
+            status |= 0x01;
+        }
+        if temperature >= r[ServoRegister::MaxTemperatureLimit as usize] {
+            status |= 0x04;
+        }
+
+        self.set_u16(ServoRegister::CurrentLocation as usize, position);
+        self.set_u16(ServoRegister::CurrentSpeed as usize, speed);
+        self.set_u16(ServoRegister::CurrentLoad as usize, load);
+        self.registers[ServoRegister::CurrentVoltage as usize] = voltage;
+        self.registers[ServoRegister::CurrentTemperature as usize] = temperature;
+        self.registers[ServoRegister::ServoStatus as usize] = status;
+        self.registers[ServoRegister::MobileSign as usize] = (self.velocity.abs() > MOVING_THRESHOLD) as u8;
+        self.set_u16(ServoRegister::CurrentCurrent as usize, (current * 1000.0 / CURRENT_LSB_MA).round() as u16);
+    }
+
+    // A two-byte register, decoded as the servo would
+    fn value(&self, register: ServoRegister) -> i32 {
+        register.info().decode(self.u16_at(register as usize))
+    }
+
+    fn u16_at(&self, address: usize) -> u16 {
+        u16::from_le_bytes([self.registers[address], self.registers[address + 1]])
+    }
+
+    fn set_u16(&mut self, address: usize, value: u16) {
+        self.registers[address..address + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn is_writable(address: usize) -> bool {
+    (ServoRegister::ID as usize..ServoRegister::CurrentLocation as usize).contains(&address)
+}
+
+// Feedback values are clamped to their register's range before they get here
+fn encode(register: ServoRegister, value: i32) -> u16 {
+    register.info().encode(value).expect("simulated feedback fits its register")
+}
+
+/// A set of simulated servos sharing one bus, advanced in real time.
+#[derive(Debug)]
+pub struct SimBus {
+    servos: BTreeMap<u8, SimServo>,
+    last_step: Instant,
+}
+
+impl SimBus {
+    pub fn new(ids: impl IntoIterator<Item = u8>) -> Self {
+        SimBus {
+            servos: ids.into_iter().map(|id| (id, SimServo::new(id))).collect(),
+            last_step: Instant::now(),
+        }
+    }
+
+    pub fn ids(&self) -> Vec<u8> {
+        self.servos.keys().copied().collect()
+    }
+
+    pub fn servo(&self, id: u8) -> Option<&SimServo> {
+        self.servos.get(&id)
+    }
+
+    pub fn servo_mut(&mut self, id: u8) -> Option<&mut SimServo> {
+        self.servos.get_mut(&id)
+    }
+
+    /// Brings every servo up to the current wall-clock time.
+    pub fn advance(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_step);
+        self.last_step = now;
+        self.advance_by(elapsed);
+    }
+
+    /// Steps every servo through `elapsed`, or through `MAX_CATCH_UP` if that is longer, so
+    /// a stall such as a debugger stop doesn't hold the bus for a long simulation.
+    pub fn advance_by(&mut self, elapsed: Duration) {
+        let mut remaining = elapsed.min(MAX_CATCH_UP);
+        while !remaining.is_zero() {
+            let dt = remaining.min(SIM_STEP);
+            for servo in self.servos.values_mut() {
+                servo.step(dt.as_secs_f32());
+            }
+            remaining -= dt;
+        }
+    }
+
+    pub fn ping(&self, id: u8) -> bool {
+        self.servos.contains_key(&id)
+    }
+
+    pub fn read(&self, id: u8, address: u8, length: u8) -> Option<Vec<u8>> {
+        self.servos.get(&id)?.read(address, length)
+    }
+
+    /// Writes to one servo, or to all of them for the broadcast ID.
+    pub fn write(&mut self, id: u8, address: u8, data: &[u8]) -> bool {
+        let written = if id == BROADCAST_ID {
+            let mut written = false;
+            for servo in self.servos.values_mut() {
+                written |= servo.write(address, data);
+            }
+            written
+        } else {
+            match self.servos.get_mut(&id) {
+                Some(servo) => servo.write(address, data),
+                None => false,
+            }
+        };
+        self.rekey();
+        written
+    }
+
+    pub fn reg_write(&mut self, id: u8, address: u8, data: &[u8]) -> bool {
+        if id == BROADCAST_ID {
+            let mut written = false;
+            for servo in self.servos.values_mut() {
+                written |= servo.reg_write(address, data);
+            }
+            written
+        } else {
+            match self.servos.get_mut(&id) {
+                Some(servo) => servo.reg_write(address, data),
+                None => false,
+            }
+        }
+    }
+
+    pub fn action(&mut self) {
+        for servo in self.servos.values_mut() {
+            servo.action();
+        }
+        self.rekey();
+    }
+
+    pub fn reset(&mut self, id: u8) -> bool {
+        if id == BROADCAST_ID {
+            self.servos.values_mut().for_each(SimServo::reset);
+            return true;
+        }
+        match self.servos.get_mut(&id) {
+            Some(servo) => {
+                servo.reset();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // A write to the ID register moves the servo to its new address
+    fn rekey(&mut self) {
+        if self.servos.iter().all(|(&id, servo)| servo.id() == id) {
+            return;
+        }
+        let servos = std::mem::take(&mut self.servos);
+        self.servos = servos.into_values().map(|servo| (servo.id(), servo)).collect();
+    }
+}
+
+/// Servo backend driven by [`SimBus`], selected with `SERVO_PORT=sim://<ids>`.
+#[derive(Debug)]
+pub struct Servo {
+    bus: Mutex<SimBus>,
+    movement_enabled: AtomicBool,
+    task_run_count: AtomicU32,
+}
+
+impl Servo {
+    pub fn new(ids: impl IntoIterator<Item = u8>) -> Self {
+        Servo {
+            bus: Mutex::new(SimBus::new(ids)),
+            movement_enabled: AtomicBool::new(true),
+            task_run_count: AtomicU32::new(0),
+        }
+    }
+
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        Ok(Self::new(parse_servo_ids(spec)?))
+    }
+
+    fn with_bus<T>(&self, f: impl FnOnce(&mut SimBus) -> T) -> T {
+        let mut bus = self.bus.lock().unwrap();
+        bus.advance();
+        f(&mut bus)
+    }
+}
+
+impl ServoBus for Servo {
+    fn write(&self, id: u8, register: ServoRegister, data: &[u8]) -> Result<()> {
+        if !self.with_bus(|bus| bus.write(id, register as u8, data)) && id != BROADCAST_ID {
+            return Err(ServoError::Timeout { id }.into());
+        }
+        Ok(())
+    }
+
+    fn read(&self, id: u8, register: ServoRegister, length: u8) -> Result<Vec<u8>> {
+        match self.with_bus(|bus| bus.read(id, register as u8, length)) {
+            Some(data) => Ok(data),
+            None => Err(ServoError::Timeout { id }.into()),
+        }
+    }
+
+    fn move_servo(&self, id: u8, position: i16, time: u16, speed: u16) -> Result<()> {
+        let mut data = Vec::with_capacity(6);
+        data.extend_from_slice(&position.to_le_bytes());
+        data.extend_from_slice(&time.to_le_bytes());
+        data.extend_from_slice(&speed.to_le_bytes());
+        self.write(id, ServoRegister::TargetLocation, &data)
+    }
+
+    fn read_info(&self, id: u8) -> Result<ServoInfo> {
+        match self.with_bus(|bus| bus.servo(id).map(SimServo::info)) {
+            Some(info) => Ok(info),
+            None => Err(ServoError::Timeout { id }.into()),
+        }
+    }
+
+    fn read_continuous(&self) -> Result<ServoData> {
+        let servos = self.with_bus(|bus| bus.servos.iter().map(|(&id, servo)| (id, servo.info())).collect());
+        Ok(ServoData {
+            servos,
+            task_run_count: self.task_run_count.fetch_add(1, Ordering::SeqCst) + 1,
+        })
+    }
+
+    fn ids(&self) -> Vec<u8> {
+        self.bus.lock().unwrap().servos.keys().copied().collect()
+    }
+
+    fn last_update(&self, id: u8) -> Option<Instant> {
+        // The simulation is always current
+        self.with_bus(|bus| bus.servo(id).map(|_| Instant::now()))
+    }
+
+    fn write_multiple(&self, cmd: &ServoMultipleWriteCommand) -> Result<()> {
+        if !self.movement_enabled.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.with_bus(|bus| {
+            for target in &cmd.targets {
+                let (register, mut data) = if cmd.write_accelerations && !cmd.only_write_positions {
+                    (ServoRegister::Acceleration, vec![target.acceleration])
+                } else {
+                    (ServoRegister::TargetLocation, Vec::new())
+                };
+                data.extend_from_slice(&target.position.to_le_bytes());
+                if !cmd.only_write_positions {
+                    data.extend_from_slice(&target.time.to_le_bytes());
+                    data.extend_from_slice(&target.speed.to_le_bytes());
+                }
+                // SYNC_WRITE is unacknowledged, absent IDs are simply not moved
+                bus.write(target.id, register as u8, &data);
+            }
+        });
+        Ok(())
+    }
+
+    fn stage_move(&self, staged: &StagedMove) -> Result<()> {
+        let data = staged.to_registers();
+        if !self.with_bus(|bus| bus.reg_write(staged.id, ServoRegister::Acceleration as u8, &data)) {
+            return Err(ServoError::Timeout { id: staged.id }.into());
+        }
+        Ok(())
+    }
+
+    fn action(&self) -> Result<()> {
+        self.with_bus(SimBus::action);
+        Ok(())
+    }
+
+    fn enable_readout(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn disable_readout(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn enable_movement(&self) -> Result<()> {
+        self.movement_enabled.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn disable_movement(&self) -> Result<()> {
+        self.movement_enabled.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// A level, motionless IMU to pair with the simulated servos.
+pub struct IMU {}
+
+impl IMU {
+    pub fn new() -> Result<Self> {
+        Ok(IMU {})
+    }
+}
+
+impl ImuSource for IMU {
+    fn read_data(&self) -> Result<IMUData> {
+        Ok(IMUData {
+            acc_x: 0.0,
+            acc_y: 0.0,
+            acc_z: 9.807,
+            gyro_x: 0.0,
+            gyro_y: 0.0,
+            gyro_z: 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u16(bus: &mut SimBus, id: u8, register: ServoRegister, value: u16) {
+        assert!(bus.write(id, register as u8, &value.to_le_bytes()));
+    }
+
+    fn read_u16(bus: &SimBus, id: u8, register: ServoRegister) -> u16 {
+        let data = bus.read(id, register as u8, 2).unwrap();
+        u16::from_le_bytes([data[0], data[1]])
+    }
+
+    fn run(bus: &mut SimBus, elapsed: Duration) {
+        let mut remaining = elapsed;
+        while !remaining.is_zero() {
+            let dt = remaining.min(MAX_CATCH_UP);
+            bus.advance_by(dt);
+            remaining -= dt;
+        }
+    }
+
+    #[test]
+    fn moves_to_the_target_at_running_speed() {
+        let mut bus = SimBus::new([1]);
+        write_u16(&mut bus, 1, ServoRegister::RunningSpeed, 1000);
+        write_u16(&mut bus, 1, ServoRegister::TargetLocation, 3048);
+
+        run(&mut bus, Duration::from_millis(500));
+        let speed = read_u16(&bus, 1, ServoRegister::CurrentSpeed);
+        assert!((990..=1000).contains(&speed), "cruising at {}", speed);
+        assert_eq!(bus.read(1, ServoRegister::MobileSign as u8, 1).unwrap(), [1]);
+
+        run(&mut bus, Duration::from_millis(1500));
+        assert_eq!(read_u16(&bus, 1, ServoRegister::CurrentLocation), 3048);
+        assert_eq!(read_u16(&bus, 1, ServoRegister::CurrentSpeed), 0);
+        assert_eq!(bus.read(1, ServoRegister::MobileSign as u8, 1).unwrap(), [0]);
+    }
+
+    #[test]
+    fn stops_at_the_angle_limits() {
+        let mut bus = SimBus::new([1]);
+        write_u16(&mut bus, 1, ServoRegister::MinAngleLimit, 1000);
+        write_u16(&mut bus, 1, ServoRegister::MaxAngleLimit, 3000);
+        write_u16(&mut bus, 1, ServoRegister::TargetLocation, 4000);
+        run(&mut bus, Duration::from_secs(2));
+        assert_eq!(read_u16(&bus, 1, ServoRegister::CurrentLocation), 3000);
+
+        write_u16(&mut bus, 1, ServoRegister::TargetLocation, 0);
+        run(&mut bus, Duration::from_secs(2));
+        assert_eq!(read_u16(&bus, 1, ServoRegister::CurrentLocation), 1000);
+    }
+
+    #[test]
+    fn holds_still_with_torque_off() {
+        let mut bus = SimBus::new([1]);
+        assert!(bus.write(1, ServoRegister::TorqueSwitch as u8, &[0]));
+        write_u16(&mut bus, 1, ServoRegister::TargetLocation, 3048);
+        run(&mut bus, Duration::from_secs(1));
+        assert_eq!(read_u16(&bus, 1, ServoRegister::CurrentLocation), 2048);
+        assert_eq!(bus.servo(1).unwrap().info().current_load, 0);
+    }
+
+    #[test]
+    fn applies_buffered_writes_on_action() {
+        let mut bus = SimBus::new([1, 2]);
+        assert!(bus.reg_write(1, ServoRegister::TargetLocation as u8, &3048u16.to_le_bytes()));
+        assert_eq!(read_u16(&bus, 1, ServoRegister::TargetLocation), 2048);
+        assert_eq!(bus.read(1, ServoRegister::AsyncWriteFlag as u8, 1).unwrap(), [1]);
+        run(&mut bus, Duration::from_millis(200));
+        assert_eq!(read_u16(&bus, 1, ServoRegister::CurrentLocation), 2048);
+
+        bus.action();
+        assert_eq!(read_u16(&bus, 1, ServoRegister::TargetLocation), 3048);
+        assert_eq!(bus.read(1, ServoRegister::AsyncWriteFlag as u8, 1).unwrap(), [0]);
+        // Servos with nothing buffered ignore the ACTION
+        assert_eq!(read_u16(&bus, 2, ServoRegister::TargetLocation), 2048);
+    }
+
+    #[test]
+    fn does_not_answer_for_absent_servos() {
+        let mut bus = SimBus::new([1]);
+        assert!(!bus.ping(2));
+        assert_eq!(bus.read(2, ServoRegister::ID as u8, 1), None);
+        assert!(!bus.write(2, ServoRegister::TargetLocation as u8, &[0, 0]));
+        assert!(!bus.reg_write(2, ServoRegister::TargetLocation as u8, &[0, 0]));
+        assert!(!bus.reset(2));
+        // Nor past the end of the control table
+        assert_eq!(bus.read(1, REGISTER_SPACE as u8 - 1, 2), None);
+    }
+
+    #[test]
+    fn catches_up_at_most_half_a_second() {
+        let mut bus = SimBus::new([1]);
+        write_u16(&mut bus, 1, ServoRegister::RunningSpeed, 1000);
+        write_u16(&mut bus, 1, ServoRegister::TargetLocation, 4000);
+        bus.advance_by(Duration::from_secs(10));
+        // 500 ms at 1000 steps/s, less the ramp up
+        let position = read_u16(&bus, 1, ServoRegister::CurrentLocation);
+        assert!((2530..=2548).contains(&position), "moved to {}", position);
+    }
+}
@@ -1,16 +1,49 @@
 #[cfg(all(target_arch = "riscv64", target_os = "linux", feature = "milkv"))]
 pub mod hal_risc;
 
-#[cfg(any(target_os = "macos", all(target_os = "linux", not(feature = "milkv"))))]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 pub mod hal_serial;
 
+pub mod hal_sim;
+
 // Create a public hal module
 pub mod hal {
-    use std::os::raw::{c_short, c_uchar, c_ushort, c_uint};
+    use std::collections::BTreeMap;
+    use std::os::raw::{c_short, c_uchar, c_ushort};
     use serde::{Serialize, Deserialize};
 
+    mod async_servo;
+    mod backup;
+    mod bus;
+    mod capture;
+    mod error;
+    mod faults;
+    mod motion;
+    mod profiles;
+    mod registers;
+    mod retry;
+    mod revolutions;
+    mod settings;
+    mod stats;
+    mod units;
+
+    pub use async_servo::*;
+    pub use backup::*;
+    pub use bus::{ServoBus, ImuSource, Servo, IMU, parse_servo_ids, EEPROM_WRITE_DELAY};
+    pub use capture::*;
+    pub use error::*;
+    pub use faults::*;
+    pub use motion::*;
+    pub use profiles::*;
+    pub use registers::*;
+    pub use retry::*;
+    pub use revolutions::*;
+    pub use settings::*;
+    pub use stats::*;
+    pub use units::*;
 
-    pub const MAX_SERVOS: usize = 16;
+    /// Highest servo ID; 254 is the broadcast address.
+    pub const MAX_SERVO_ID: u8 = 253;
 
     #[repr(C)]
     #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
@@ -35,8 +68,46 @@ pub mod hal {
         pub current_current: c_ushort,
     }
 
+    /// Bytes from `TorqueSwitch` through `CurrentCurrent`, everything in `ServoInfo`.
+    pub const SERVO_INFO_LENGTH: u8 = 31;
+    /// Bytes from `CurrentLocation` through `CurrentCurrent`, the live feedback.
+    pub const SERVO_FEEDBACK_LENGTH: u8 = 15;
+
+    impl ServoInfo {
+        /// Parses the block read from `ServoRegister::TorqueSwitch`.
+        pub fn from_registers(data: &[u8; SERVO_INFO_LENGTH as usize]) -> Self {
+            let mut info = ServoInfo {
+                torque_switch: data[0],
+                acceleration: data[1],
+                target_location: i16::from_le_bytes([data[2], data[3]]),
+                running_time: u16::from_le_bytes([data[4], data[5]]),
+                running_speed: u16::from_le_bytes([data[6], data[7]]),
+                torque_limit: u16::from_le_bytes([data[8], data[9]]),
+                reserved1: [data[10], data[11], data[12], data[13], data[14], data[15]],
+                lock_mark: data[15],
+                ..Default::default()
+            };
+            info.update_feedback(data[16..].try_into().unwrap());
+            info
+        }
+
+        /// Updates the feedback fields from the block read from `ServoRegister::CurrentLocation`.
+        pub fn update_feedback(&mut self, data: &[u8; SERVO_FEEDBACK_LENGTH as usize]) {
+            self.current_location = i16::from_le_bytes([data[0], data[1]]);
+            self.current_speed = i16::from_le_bytes([data[2], data[3]]);
+            self.current_load = i16::from_le_bytes([data[4], data[5]]);
+            self.current_voltage = data[6];
+            self.current_temperature = data[7];
+            self.async_write_flag = data[8];
+            self.servo_status = data[9];
+            self.mobile_sign = data[10];
+            self.reserved2 = [data[11], data[12]];
+            self.current_current = u16::from_le_bytes([data[13], data[14]]);
+        }
+    }
+
     #[repr(u8)]
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum ServoRegister {
         FirmwareMajorVersion = 0x00,
         FirmwareSubVersion = 0x01,
@@ -96,25 +167,76 @@ pub mod hal {
         Locked = 1,
     }
 
-    #[repr(C)]
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+    /// Bus speeds selected by `ServoRegister::BaudRate` values 0 to 7.
+    pub const BAUD_RATES: [u32; 8] = [1_000_000, 500_000, 250_000, 128_000, 115_200, 76_800, 57_600, 38_400];
+
+    /// The `ServoRegister::BaudRate` value for `baud_rate`, if servos support it.
+    pub fn baud_rate_code(baud_rate: u32) -> Option<u8> {
+        BAUD_RATES.iter().position(|&rate| rate == baud_rate).map(|code| code as u8)
+    }
+
+    /// Latest state of every servo the bus reads out, keyed by ID.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
     pub struct ServoData {
-        pub servo: [ServoInfo; MAX_SERVOS],
-        pub task_run_count: c_uint,
+        pub servos: BTreeMap<u8, ServoInfo>,
+        pub task_run_count: u32,
     }
 
-    #[repr(C)]
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+    impl ServoData {
+        pub fn get(&self, id: u8) -> Option<&ServoInfo> {
+            self.servos.get(&id)
+        }
+    }
+
+    /// One servo's goal in a [`ServoMultipleWriteCommand`].
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+    pub struct ServoTarget {
+        pub id: u8,
+        pub position: i16,
+        pub time: u16,
+        pub speed: u16,
+        /// Only sent with [`ServoMultipleWriteCommand::write_accelerations`]; 0 accelerates
+        /// as fast as the servo can.
+        #[serde(default)]
+        pub acceleration: u8,
+    }
+
+    /// Goals for any number of servos, sent together with SYNC_WRITE.
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
     pub struct ServoMultipleWriteCommand {
-        pub only_write_positions: c_uchar,
-        pub ids: [c_uchar; MAX_SERVOS],
-        pub positions: [c_short; MAX_SERVOS],
-        pub times: [c_ushort; MAX_SERVOS],
-        pub speeds: [c_ushort; MAX_SERVOS],
+        /// Leave each servo's running time and speed as they are.
+        pub only_write_positions: bool,
+        /// Also write each servo's acceleration. Ignored with `only_write_positions`.
+        #[serde(default)]
+        pub write_accelerations: bool,
+        pub targets: Vec<ServoTarget>,
+    }
+
+    /// A move a servo holds back until the next ACTION; see [`Servo::move_staged`].
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+    pub struct StagedMove {
+        pub id: u8,
+        /// 0 accelerates as fast as the servo can.
+        pub acceleration: u8,
+        pub position: i16,
+        pub time: u16,
+        pub speed: u16,
+    }
+
+    impl StagedMove {
+        /// The `Acceleration` through `RunningSpeed` registers, as one write.
+        pub fn to_registers(&self) -> [u8; 7] {
+            let mut data = [0; 7];
+            data[0] = self.acceleration;
+            data[1..3].copy_from_slice(&self.position.to_le_bytes());
+            data[3..5].copy_from_slice(&self.time.to_le_bytes());
+            data[5..7].copy_from_slice(&self.speed.to_le_bytes());
+            data
+        }
     }
 
     #[repr(u8)]
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub enum ServoMode {
         Position = 0,
         ConstantSpeed = 1,
@@ -152,13 +274,7 @@ pub mod hal {
         pub gyro_y: f32,
         pub gyro_z: f32,
     }
-    // Re-export the appropriate HAL implementation
-    #[cfg(all(target_arch = "riscv64", target_os = "linux", feature = "milkv"))]
-    pub use super::hal_risc::*;
-
-    #[cfg(any(target_os = "macos", all(target_os = "linux", not(feature = "milkv"))))]
-    pub use super::hal_serial::*;
 }
 
 // Public API
-pub use hal::{Servo, IMU};
+pub use hal::{Servo, IMU, ServoBus, ImuSource, ServoError, ServoFaults};